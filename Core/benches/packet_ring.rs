@@ -0,0 +1,210 @@
+//! Packet throughput of the rings and of both packet I/O paths, run with
+//! `cargo bench --bench packet_ring`. Socketpairs stand in for the utun fd
+//! and EasyTier, so it runs on Linux as well as on macOS.
+//!
+//! `TC_BENCH_PACKETS` sets the packets per case, `TC_BENCH_SIZE` their size.
+
+use std::ffi::c_void;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use terracotta_ios::bridge::{OutboundCallback, PacketBridge, OUTBOUND_BATCH};
+use terracotta_ios::ring::{self, RingStatsSnapshot};
+use terracotta_ios::tun::{self, TunAdapter, MAX_PACKET_SIZE, UTUN_HEADER_LEN};
+
+const PUSH_BATCH: usize = 64;
+
+struct Measurement {
+    name: &'static str,
+    packets: u64,
+    elapsed: Duration,
+    ring: RingStatsSnapshot,
+}
+
+impl Measurement {
+    fn print(&self) {
+        let pps = self.packets as f64 / self.elapsed.as_secs_f64();
+        let copied = self.ring.bytes_copied as f64 / self.ring.packets.max(1) as f64;
+        println!(
+            "{:<18} {:>12.0} pkt/s {:>8.1} B copied/pkt {:>8} dropped {:>6} full",
+            self.name, pps, copied, self.ring.dropped, self.ring.full
+        );
+    }
+}
+
+fn env(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn ip_packet(size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; size];
+    packet[0] = 0x45;
+    packet
+}
+
+/// Count datagrams arriving on `socket` until `count` arrived or the
+/// sender has been quiet for a while.
+fn receive(socket: UnixDatagram, count: u64) -> thread::JoinHandle<(u64, Instant)> {
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    thread::spawn(move || {
+        let mut buf = vec![0u8; UTUN_HEADER_LEN + MAX_PACKET_SIZE];
+        let mut received = 0;
+        let mut last = Instant::now();
+        while received < count && socket.recv(&mut buf).is_ok() {
+            received += 1;
+            last = Instant::now();
+        }
+        (received, last)
+    })
+}
+
+fn ring_only(count: u64, size: usize) -> Measurement {
+    let (mut writer, mut reader) = ring::packet_ring(
+        ring::DEFAULT_RING_BYTES,
+        ring::DEFAULT_RING_PACKETS,
+        UTUN_HEADER_LEN + MAX_PACKET_SIZE,
+    );
+    let packet = ip_packet(size);
+    let start = Instant::now();
+    let mut read = 0;
+    while read < count {
+        for _ in 0..PUSH_BATCH {
+            writer.push(&packet);
+        }
+        while let Some((buf, lens)) = reader.batch(OUTBOUND_BATCH) {
+            std::hint::black_box(buf);
+            let n = lens.len();
+            reader.consume(n);
+            read += n as u64;
+        }
+    }
+    Measurement {
+        name: "ring",
+        packets: read,
+        elapsed: start.elapsed(),
+        ring: writer.stats().snapshot(),
+    }
+}
+
+fn bridge_inbound(count: u64, size: usize) -> Measurement {
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let bridge = PacketBridge::start(
+        core,
+        OutboundCallback::new(ignore, std::ptr::null_mut()),
+        None,
+    )
+    .unwrap();
+    let receiving = receive(UnixDatagram::from(easytier), count);
+
+    let packet = ip_packet(size);
+    let start = Instant::now();
+    let mut pushed = 0;
+    while pushed < count {
+        bridge.push(vec![&packet[..]; PUSH_BATCH]);
+        pushed += PUSH_BATCH as u64;
+    }
+    let (received, last) = receiving.join().unwrap();
+    Measurement {
+        name: "bridge inbound",
+        packets: received,
+        elapsed: last - start,
+        ring: bridge.stats().inbound_ring,
+    }
+}
+
+extern "C" fn ignore(_: *mut c_void, _: *const u8, _: *const usize, _: usize) {}
+
+extern "C" fn count_packets(user_data: *mut c_void, _: *const u8, _: *const usize, count: usize) {
+    let counter = unsafe { &*(user_data as *const AtomicU64) };
+    counter.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+fn bridge_outbound(count: u64, size: usize) -> Measurement {
+    static RECEIVED: AtomicU64 = AtomicU64::new(0);
+    RECEIVED.store(0, Ordering::Relaxed);
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let user_data = &RECEIVED as *const AtomicU64 as *mut c_void;
+    let bridge =
+        PacketBridge::start(core, OutboundCallback::new(count_packets, user_data), None).unwrap();
+    let easytier = UnixDatagram::from(easytier);
+
+    let packet = ip_packet(size);
+    let start = Instant::now();
+    for _ in 0..count {
+        easytier.send(&packet).unwrap();
+    }
+    while RECEIVED.load(Ordering::Relaxed) < count {
+        thread::yield_now();
+    }
+    Measurement {
+        name: "bridge outbound",
+        packets: count,
+        elapsed: start.elapsed(),
+        ring: bridge.stats().outbound_ring,
+    }
+}
+
+fn adapter_inbound(count: u64, size: usize) -> Measurement {
+    let (os, utun) = UnixDatagram::pair().unwrap();
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let adapter = TunAdapter::start(OwnedFd::from(utun), core, None).unwrap();
+    let receiving = receive(UnixDatagram::from(easytier), count);
+
+    let mut frame = (libc::AF_INET as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&ip_packet(size));
+    let start = Instant::now();
+    for _ in 0..count {
+        os.send(&frame).unwrap();
+    }
+    let (received, last) = receiving.join().unwrap();
+    Measurement {
+        name: "adapter inbound",
+        packets: received,
+        elapsed: last - start,
+        ring: adapter.stats().inbound_ring,
+    }
+}
+
+fn adapter_outbound(count: u64, size: usize) -> Measurement {
+    let (os, utun) = UnixDatagram::pair().unwrap();
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let adapter = TunAdapter::start(OwnedFd::from(utun), core, None).unwrap();
+    let receiving = receive(os, count);
+    let easytier = UnixDatagram::from(easytier);
+
+    let packet = ip_packet(size);
+    let start = Instant::now();
+    for _ in 0..count {
+        easytier.send(&packet).unwrap();
+    }
+    let (received, last) = receiving.join().unwrap();
+    Measurement {
+        name: "adapter outbound",
+        packets: received,
+        elapsed: last - start,
+        ring: adapter.stats().outbound_ring,
+    }
+}
+
+fn main() {
+    let count = env("TC_BENCH_PACKETS", 200_000) as u64;
+    let size = env("TC_BENCH_SIZE", 1280).clamp(20, MAX_PACKET_SIZE);
+    println!("{} packets of {} bytes per case", count, size);
+    for case in [
+        ring_only,
+        bridge_inbound,
+        bridge_outbound,
+        adapter_inbound,
+        adapter_outbound,
+    ] {
+        case(count, size).print();
+    }
+}
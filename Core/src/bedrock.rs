@@ -0,0 +1,397 @@
+//! Minecraft Bedrock LAN games across the virtual network.
+//!
+//! Bedrock finds LAN games by broadcasting RakNet unconnected pings to UDP
+//! port 19132 and listing every server that answers with an unconnected
+//! pong, whose `MCPE;...` string carries the world's name, version and
+//! player count. The relay pings every peer's virtual address on 19132 and
+//! keeps the worlds that answer. A ping broadcast by a game on this device
+//! is answered with a synthetic pong per world, delivered to the system as
+//! if it came from the peer's virtual address, so the game lists the world
+//! and joining it connects through the tunnel.
+//!
+//! The relay only listens on 19132 while nothing else on this device does.
+//! Sockets sharing a port with `SO_REUSEPORT` split its unicast traffic on
+//! Darwin, so a world hosted on this device would lose pings and RakNet
+//! packets to the relay. While such a world runs the relay still pings the
+//! peers, and the game lists its own world.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use crate::instance::lock;
+use crate::lan::{self, Overlay};
+use crate::shutdown::StopState;
+use crate::tun::Injector;
+use crate::udp;
+
+/// Port Bedrock servers answer LAN pings on over IPv4.
+pub const BEDROCK_PORT: u16 = 19132;
+
+/// Marks RakNet offline messages.
+pub const MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+const UNCONNECTED_PING: u8 = 0x01;
+/// Ping only answered by servers with open slots, sent by some clients.
+const UNCONNECTED_PING_OPEN_CONNECTIONS: u8 = 0x02;
+const UNCONNECTED_PONG: u8 = 0x1c;
+
+/// How often the peers are pinged.
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A world is dropped after this long without a pong.
+const WORLD_TTL: Duration = Duration::from_secs(6);
+
+const MAX_DATAGRAM_LEN: usize = 1500;
+
+fn read_i64(data: &[u8], at: usize) -> Option<i64> {
+    Some(i64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// RakNet unconnected ping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping {
+    /// Client time, echoed by the pong.
+    pub time: i64,
+    pub client_guid: i64,
+}
+
+impl Ping {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if !matches!(
+            data.first(),
+            Some(&UNCONNECTED_PING | &UNCONNECTED_PING_OPEN_CONNECTIONS)
+        ) || data.get(9..25)? != MAGIC
+        {
+            return None;
+        }
+        Some(Ping {
+            time: read_i64(data, 1)?,
+            client_guid: read_i64(data, 25)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![UNCONNECTED_PING];
+        data.extend_from_slice(&self.time.to_be_bytes());
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&self.client_guid.to_be_bytes());
+        data
+    }
+}
+
+/// The world a server describes in its pong:
+/// `MCPE;motd;protocol;version;players;max;server id;level;mode;mode id;port;port v6;`.
+/// Fields after the player limit are missing on older servers and empty then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// `MCPE`, or `MCEE` for Education Edition.
+    pub edition: String,
+    pub motd: String,
+    pub protocol: u32,
+    pub version: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub server_id: String,
+    pub level_name: String,
+    pub game_mode: String,
+    pub game_mode_id: String,
+    pub port_v4: Option<u16>,
+    pub port_v6: Option<u16>,
+}
+
+impl ServerInfo {
+    pub fn parse(text: &str) -> Option<Self> {
+        let fields: Vec<&str> = text.split(';').collect();
+        if fields.len() < 6 || !matches!(fields[0], "MCPE" | "MCEE") {
+            return None;
+        }
+        let field = |i: usize| fields.get(i).copied().unwrap_or_default().to_string();
+        let port = |i: usize| fields.get(i).and_then(|port| port.parse().ok());
+        Some(ServerInfo {
+            edition: field(0),
+            motd: field(1),
+            protocol: fields[2].parse().ok()?,
+            version: field(3),
+            players: fields[4].parse().ok()?,
+            max_players: fields[5].parse().ok()?,
+            server_id: field(6),
+            level_name: field(7),
+            game_mode: field(8),
+            game_mode_id: field(9),
+            port_v4: port(10),
+            port_v6: port(11),
+        })
+    }
+}
+
+impl fmt::Display for ServerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let port = |port: Option<u16>| port.map(|p| p.to_string()).unwrap_or_default();
+        write!(
+            f,
+            "{};{};{};{};{};{};{};{};{};{};{};{};",
+            self.edition,
+            self.motd,
+            self.protocol,
+            self.version,
+            self.players,
+            self.max_players,
+            self.server_id,
+            self.level_name,
+            self.game_mode,
+            self.game_mode_id,
+            port(self.port_v4),
+            port(self.port_v6)
+        )
+    }
+}
+
+/// RakNet unconnected pong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pong {
+    /// Time of the ping answered.
+    pub time: i64,
+    pub server_guid: i64,
+    pub server: ServerInfo,
+}
+
+impl Pong {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.first() != Some(&UNCONNECTED_PONG) || data.get(17..33)? != MAGIC {
+            return None;
+        }
+        let len = usize::from(u16::from_be_bytes(data.get(33..35)?.try_into().ok()?));
+        let text = std::str::from_utf8(data.get(35..35 + len)?).ok()?;
+        Some(Pong {
+            time: read_i64(data, 1)?,
+            server_guid: read_i64(data, 9)?,
+            server: ServerInfo::parse(text)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let text = self.server.to_string();
+        let mut data = vec![UNCONNECTED_PONG];
+        data.extend_from_slice(&self.time.to_be_bytes());
+        data.extend_from_slice(&self.server_guid.to_be_bytes());
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&(text.len() as u16).to_be_bytes());
+        data.extend_from_slice(text.as_bytes());
+        data
+    }
+}
+
+struct World {
+    pong: Pong,
+    seen: Instant,
+}
+
+/// Bedrock worlds of the peers of one instance.
+pub struct BedrockRelay {
+    overlay: Arc<Overlay>,
+    /// GUID the relay pings with.
+    guid: i64,
+    started: Instant,
+    /// Worlds that answered recently, by the address they answered from.
+    worlds: Mutex<HashMap<SocketAddrV4, World>>,
+    system: Mutex<Option<Injector>>,
+}
+
+impl BedrockRelay {
+    pub fn new(overlay: Arc<Overlay>) -> Self {
+        BedrockRelay {
+            overlay,
+            guid: rand::random(),
+            started: Instant::now(),
+            worlds: Mutex::new(HashMap::new()),
+            system: Mutex::new(None),
+        }
+    }
+
+    /// Deliver synthetic pongs to the system through `injector`, the
+    /// [`Injector`] of the attached packet I/O.
+    pub fn attach_system(&self, injector: Injector) {
+        *lock(&self.system) = Some(injector);
+    }
+
+    /// Ping port 19132 of every peer from `reply_port` of the local virtual
+    /// address, returns the number of peers pinged.
+    pub async fn ping_peers(&self, reply_port: u16) -> usize {
+        let ping = Ping {
+            time: self.started.elapsed().as_millis() as i64,
+            client_guid: self.guid,
+        };
+        let dsts = self
+            .overlay
+            .peers()
+            .into_iter()
+            .map(|peer| SocketAddrV4::new(peer, BEDROCK_PORT));
+        self.overlay
+            .send_udp(reply_port, dsts, &ping.to_bytes())
+            .await
+    }
+
+    /// Remember the world in a pong `from` a peer, returns whether it was
+    /// one.
+    pub fn record(&self, from: SocketAddrV4, data: &[u8]) -> bool {
+        if !self.overlay.peers().contains(from.ip()) {
+            return false;
+        }
+        let Some(pong) = Pong::parse(data) else {
+            return false;
+        };
+        let world = World {
+            pong,
+            seen: Instant::now(),
+        };
+        lock(&self.worlds).insert(from, world);
+        true
+    }
+
+    /// The worlds that answered within the last few pings, dropping the
+    /// others.
+    pub fn worlds(&self) -> Vec<(SocketAddrV4, Pong)> {
+        let mut worlds = lock(&self.worlds);
+        worlds.retain(|_, world| world.seen.elapsed() < WORLD_TTL);
+        worlds
+            .iter()
+            .map(|(addr, world)| (*addr, world.pong.clone()))
+            .collect()
+    }
+
+    /// Answer the ping `client` sent with a pong from every world, returns
+    /// the number of pongs delivered.
+    pub async fn answer(&self, client: SocketAddrV4, data: &[u8]) -> usize {
+        let Some(ping) = Ping::parse(data) else {
+            return 0;
+        };
+        let Some(system) = lock(&self.system).clone() else {
+            return 0;
+        };
+        let mut sent = 0;
+        for (addr, pong) in self.worlds() {
+            // 回应客户端的时间戳, 其余照搬对方的 pong
+            let pong = Pong {
+                time: ping.time,
+                ..pong
+            };
+            let packet = udp::packet(addr, client, &pong.to_bytes());
+            if system.send(packet).await.is_ok() {
+                sent += 1;
+            }
+        }
+        sent
+    }
+}
+
+fn v4(addr: SocketAddr) -> Option<SocketAddrV4> {
+    match addr {
+        SocketAddr::V4(addr) => Some(addr),
+        SocketAddr::V6(_) => None,
+    }
+}
+
+/// Whether a server on this device, such as a world hosted by the game, owns
+/// the Bedrock port.
+///
+/// Checked with an exclusive bind, so it must run while the relay's own
+/// discovery socket is closed.
+pub fn local_server_running() -> bool {
+    match std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, BEDROCK_PORT)) {
+        Ok(_) => false,
+        Err(e) => e.kind() == io::ErrorKind::AddrInUse,
+    }
+}
+
+// 只在本机没有服务器占用 19132 时共享端口接收广播的 ping. Darwin 上 SO_REUSEPORT
+// 会把单播也分给最后绑定的套接字, 一起共享的话发给本机世界的 ping 和 RakNet 包会被中继吞掉
+fn discovery_socket() -> io::Result<Option<UdpSocket>> {
+    if local_server_running() {
+        return Ok(None);
+    }
+    let socket = lan::shared_socket(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, BEDROCK_PORT))?;
+    Ok(Some(UdpSocket::from_std(socket)?))
+}
+
+// 对方的 pong 经隧道回到临时端口上的套接字
+fn bind_pongs() -> io::Result<(UdpSocket, u16)> {
+    let pongs = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    pongs.set_nonblocking(true)?;
+    let reply_port = pongs.local_addr()?.port();
+    Ok((UdpSocket::from_std(pongs)?, reply_port))
+}
+
+async fn recv_ping(
+    discovery: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr)> {
+    match discovery {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Ping the peers and answer the pings of local games until the run ends.
+///
+/// The discovery socket is reopened before every round of pings and stays
+/// closed while a server on this device owns port 19132, so a world hosted
+/// on this device keeps receiving its unicast traffic.
+pub async fn run(relay: Arc<BedrockRelay>, stop_state: Arc<StopState>) {
+    let (pongs, reply_port) = match bind_pongs() {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            tracing::warn!("Bedrock LAN discovery is unavailable: {}", e);
+            return;
+        }
+    };
+    let mut discovery = None;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut ping_buf = [0u8; MAX_DATAGRAM_LEN];
+    let mut pong_buf = [0u8; MAX_DATAGRAM_LEN];
+    loop {
+        tokio::select! {
+            _ = stop_state.wait() => break,
+            _ = ping.tick() => {
+                // 先关掉自己的套接字, 才能看出本机是否开了世界
+                drop(discovery.take());
+                discovery = discovery_socket().unwrap_or_else(|e| {
+                    tracing::warn!("failed to open Bedrock discovery socket: {}", e);
+                    None
+                });
+                relay.ping_peers(reply_port).await;
+            }
+            received = recv_ping(discovery.as_ref(), &mut ping_buf) => match received {
+                // 对方中继的 ping 不回应, 免得互相转发
+                Ok((n, from)) => match v4(from) {
+                    Some(from) if !relay.overlay.contains(*from.ip()) => {
+                        relay.answer(from, &ping_buf[..n]).await;
+                    }
+                    _ => {}
+                },
+                Err(e) => {
+                    tracing::warn!("failed to receive Bedrock ping: {}", e);
+                    discovery = None;
+                }
+            },
+            received = pongs.recv_from(&mut pong_buf) => match received {
+                Ok((n, from)) => {
+                    if let Some(from) = v4(from) {
+                        relay.record(from, &pong_buf[..n]);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("failed to receive Bedrock pong: {}", e);
+                    break;
+                }
+            },
+        }
+    }
+}
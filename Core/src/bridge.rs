@@ -0,0 +1,215 @@
+//! Packet I/O driven by the extension through callbacks.
+//!
+//! An alternative to handing the core the utun fd: the extension reads
+//! packets with `NEPacketTunnelFlow.readPackets` and pushes them in, and
+//! receives EasyTier's packets in batches to pass to `writePackets`. Packets
+//! are bare IP packets in both directions. Batches are laid out as one
+//! buffer holding the packets back to back plus an array of their lengths.
+
+use std::ffi::c_void;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::dns::{Interceptor, MagicDns};
+use crate::error::{TcError, TcErrorCode};
+use crate::instance::lock;
+use crate::lan::{Echo, Overlay};
+use crate::ring::{RingReader, RingWriter};
+use crate::runtime;
+use crate::tun::{self, Injector, PacketFd, TunStats, TunStatsSnapshot, MAX_PACKET_SIZE};
+
+/// Most packets handed to the outbound callback at once.
+pub const OUTBOUND_BATCH: usize = 64;
+
+/// Receives packets from EasyTier. `buf` holds `count` packets back to
+/// back, `lens` their lengths. Both are only valid until the callback
+/// returns. The callback runs on a core runtime thread.
+pub type OutboundPacketCallback =
+    extern "C" fn(user_data: *mut c_void, buf: *const u8, lens: *const usize, count: usize);
+
+#[derive(Clone, Copy)]
+pub struct OutboundCallback {
+    callback: OutboundPacketCallback,
+    user_data: *mut c_void,
+}
+
+// user_data 由调用方保证可在任意线程使用
+unsafe impl Send for OutboundCallback {}
+unsafe impl Sync for OutboundCallback {}
+
+impl OutboundCallback {
+    pub fn new(callback: OutboundPacketCallback, user_data: *mut c_void) -> Self {
+        OutboundCallback {
+            callback,
+            user_data,
+        }
+    }
+
+    fn call(self, buf: &[u8], lens: &[usize]) {
+        (self.callback)(self.user_data, buf.as_ptr(), lens.as_ptr(), lens.len());
+    }
+}
+
+/// Split `buf` into the packets described by `lens`, `None` if the lengths
+/// add up to more than `buf` holds.
+pub fn split_packets<'a>(buf: &'a [u8], lens: &[usize]) -> Option<Vec<&'a [u8]>> {
+    let mut packets = Vec::with_capacity(lens.len());
+    let mut rest = buf;
+    for &len in lens {
+        if len > rest.len() {
+            return None;
+        }
+        let (packet, tail) = rest.split_at(len);
+        packets.push(packet);
+        rest = tail;
+    }
+    Some(packets)
+}
+
+fn is_ip_packet(packet: &[u8]) -> bool {
+    packet.len() <= MAX_PACKET_SIZE && tun::utun_header(packet).is_some()
+}
+
+/// Moves packets between the callbacks and the core end of a
+/// [`nic_pair`](tun::nic_pair), buffering each direction in a
+/// [`packet_ring`](crate::ring::packet_ring). Queries for the [`MagicDns`]
+/// are answered by the bridge and never reach EasyTier, announcements of
+/// the peers' LAN worlds are echoed as with the
+/// [`TunAdapter`](tun::TunAdapter). Dropping the bridge stops it and closes
+/// the socket.
+pub struct PacketBridge {
+    inbound: Mutex<RingWriter>,
+    callback: Arc<Mutex<OutboundCallback>>,
+    interceptor: Option<Interceptor>,
+    injector: Injector,
+    stats: Arc<TunStats>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl PacketBridge {
+    pub fn start(
+        nic: OwnedFd,
+        callback: OutboundCallback,
+        dns: Option<Arc<MagicDns>>,
+        lan: Option<Arc<Overlay>>,
+    ) -> Result<Self, TcError> {
+        let runtime = runtime::get()?;
+        let _guard = runtime.enter();
+        let nic =
+            Arc::new(PacketFd::new(nic).map_err(|e| TcError::from_error(TcErrorCode::Io, &e))?);
+        let callback = Arc::new(Mutex::new(callback));
+        let (inbound_writer, inbound_reader) = tun::io_ring();
+        let (outbound_writer, outbound_reader) = tun::io_ring();
+        let stats = Arc::new(TunStats::new(&inbound_writer, &outbound_writer));
+        let (injector, injected) = tun::injector();
+        let interceptor = dns
+            .map(|dns| Interceptor::new(dns, injector.clone()))
+            .transpose()?;
+        let echo = lan.map(|overlay| Echo::new(overlay, injector.clone()));
+
+        let inbound = stats.clone();
+        let tasks = vec![
+            runtime.spawn(tun::drain(inbound_reader, nic.clone(), move |len| {
+                TunStats::count(&inbound.inbound_packets, &inbound.inbound_bytes, len)
+            })),
+            runtime.spawn(tun::read_nic(
+                nic,
+                outbound_writer,
+                false,
+                echo,
+                stats.clone(),
+            )),
+            runtime.spawn(outbound(outbound_reader, callback.clone(), stats.clone())),
+            runtime.spawn(send_injected(injected, callback.clone())),
+        ];
+        Ok(PacketBridge {
+            inbound: Mutex::new(inbound_writer),
+            callback,
+            interceptor,
+            injector,
+            stats,
+            tasks,
+        })
+    }
+
+    /// Replace the outbound callback, later batches go to `callback`.
+    pub fn set_callback(&self, callback: OutboundCallback) {
+        *lock(&self.callback) = callback;
+    }
+
+    /// Copy packets read from the system into the inbound ring. Packets
+    /// that are not IP packets are skipped, packets that do not fit into
+    /// the ring are dropped, both only show up in the counters. Queries for
+    /// the magic DNS are answered through the outbound callback.
+    pub fn push<'a>(&self, packets: impl IntoIterator<Item = &'a [u8]>) {
+        let mut inbound = lock(&self.inbound);
+        for packet in packets {
+            if !is_ip_packet(packet) {
+                self.stats.malformed.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if let Some(interceptor) = &self.interceptor {
+                if interceptor.intercept(packet) {
+                    continue;
+                }
+            }
+            inbound.push(packet);
+        }
+    }
+
+    /// Sender of packets for the system, handed to the outbound callback.
+    pub fn injector(&self) -> Injector {
+        self.injector.clone()
+    }
+
+    pub fn stats(&self) -> TunStatsSnapshot {
+        self.stats.snapshot()
+    }
+}
+
+impl Drop for PacketBridge {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// 环 -> 回调, 首尾相接的包直接从环中整批交出
+async fn outbound(
+    mut reader: RingReader,
+    callback: Arc<Mutex<OutboundCallback>>,
+    stats: Arc<TunStats>,
+) {
+    loop {
+        reader.readable().await;
+        while let Some((buf, lens)) = reader.batch(OUTBOUND_BATCH) {
+            let (count, bytes) = (lens.len(), buf.len());
+            // 先复制出回调再调用, 回调中可以重新设置回调
+            let callback = *lock(&callback);
+            callback.call(buf, lens);
+            reader.consume(count);
+            stats
+                .outbound_packets
+                .fetch_add(count as u64, Ordering::Relaxed);
+            stats
+                .outbound_bytes
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+// 核心生成的包 (魔法 DNS 的应答等) -> 回调, 每个包单独一批
+async fn send_injected(
+    mut injected: mpsc::Receiver<Vec<u8>>,
+    callback: Arc<Mutex<OutboundCallback>>,
+) {
+    while let Some(packet) = injected.recv().await {
+        let callback = *lock(&callback);
+        callback.call(&packet, &[packet.len()]);
+    }
+}
@@ -0,0 +1,328 @@
+//! Typed EasyTier configuration for Terracotta rooms.
+//!
+//! [`RoomConfig`] is the single place where room settings are turned into
+//! the TOML understood by EasyTier's `TomlConfigLoader`.
+
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::room_code::RoomCode;
+
+/// Public EasyTier nodes used to find the other players when no peers are
+/// given explicitly.
+pub const DEFAULT_PEERS: &[&str] = &["tcp://public.easytier.top:11010"];
+
+/// Default MTU, small enough to avoid fragmentation on mobile networks.
+pub const DEFAULT_MTU: u32 = 1380;
+
+/// Port the host listens on for direct connections from guests.
+pub const DEFAULT_LISTEN_PORT: u16 = 11010;
+
+/// Virtual address of the host. Guests get theirs from EasyTier's DHCP,
+/// which picks free addresses in the same subnet.
+pub const HOST_IPV4: Ipv4Addr = Ipv4Addr::new(10, 14, 0, 1);
+pub const NETWORK_PREFIX_LEN: u8 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Host,
+    #[default]
+    Guest,
+}
+
+/// Settings of one EasyTier instance taking part in a room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RoomConfig {
+    pub role: Role,
+    pub instance_name: String,
+    pub hostname: Option<String>,
+    pub network_name: String,
+    pub network_secret: String,
+    /// Static virtual address in CIDR form, e.g. `10.14.0.1/16`.
+    pub ipv4: Option<String>,
+    pub dhcp: bool,
+    pub listeners: Vec<String>,
+    pub peers: Vec<String>,
+    pub proxy_networks: Vec<String>,
+    pub rpc_portal: Option<String>,
+    pub mtu: u32,
+    pub no_tun: bool,
+    /// Answer DNS queries for player and room hostnames, see [`crate::dns`].
+    pub magic_dns: bool,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            role: Role::Guest,
+            instance_name: String::new(),
+            hostname: None,
+            network_name: String::new(),
+            network_secret: String::new(),
+            ipv4: None,
+            dhcp: true,
+            listeners: Vec::new(),
+            peers: Vec::new(),
+            proxy_networks: Vec::new(),
+            rpc_portal: None,
+            mtu: DEFAULT_MTU,
+            no_tun: false,
+            magic_dns: false,
+        }
+    }
+}
+
+/// Input of `build_room_config`: a room code plus optional overrides.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RoomConfigRequest {
+    pub role: Role,
+    pub code: Option<String>,
+    pub hostname: Option<String>,
+    pub network_name: Option<String>,
+    pub network_secret: Option<String>,
+    pub ipv4: Option<String>,
+    pub listen_port: Option<u16>,
+    pub listeners: Option<Vec<String>>,
+    pub peers: Vec<String>,
+    pub proxy_networks: Vec<String>,
+    pub rpc_portal: Option<String>,
+    pub mtu: Option<u32>,
+    pub no_tun: bool,
+    pub magic_dns: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomConfigBuilder {
+    config: RoomConfig,
+}
+
+impl RoomConfig {
+    pub fn builder(role: Role) -> RoomConfigBuilder {
+        let mut config = RoomConfig {
+            role,
+            ..RoomConfig::default()
+        };
+        if role == Role::Host {
+            config.ipv4 = Some(format!("{}/{}", HOST_IPV4, NETWORK_PREFIX_LEN));
+            config.dhcp = false;
+            config.listeners = host_listeners(DEFAULT_LISTEN_PORT);
+        }
+        RoomConfigBuilder { config }
+    }
+
+    /// Builder preloaded with the network identity derived from `code`.
+    pub fn for_room(code: &RoomCode, role: Role) -> RoomConfigBuilder {
+        Self::builder(role)
+            .instance_name(code.network_name())
+            .network_identity(code.network_name(), code.network_secret())
+    }
+
+    pub fn from_request(request: RoomConfigRequest) -> Result<Self, String> {
+        let mut builder = match &request.code {
+            Some(code) => {
+                let code = code.parse::<RoomCode>().map_err(|e| e.to_string())?;
+                Self::for_room(&code, request.role)
+            }
+            None => Self::builder(request.role),
+        };
+        if let Some(name) = request.network_name {
+            builder = builder.instance_name(name.clone());
+            builder.config.network_name = name;
+        }
+        if let Some(secret) = request.network_secret {
+            builder.config.network_secret = secret;
+        }
+        if let Some(hostname) = request.hostname {
+            builder = builder.hostname(hostname);
+        }
+        if let Some(ipv4) = request.ipv4 {
+            builder = builder.ipv4(ipv4);
+        }
+        if let Some(port) = request.listen_port {
+            builder = builder.listeners(host_listeners(port));
+        }
+        if let Some(listeners) = request.listeners {
+            builder = builder.listeners(listeners);
+        }
+        if let Some(mtu) = request.mtu {
+            builder = builder.mtu(mtu);
+        }
+        if let Some(rpc_portal) = request.rpc_portal {
+            builder = builder.rpc_portal(rpc_portal);
+        }
+        Ok(builder
+            .peers(request.peers)
+            .proxy_networks(request.proxy_networks)
+            .no_tun(request.no_tun)
+            .magic_dns(request.magic_dns)
+            .build())
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let request: RoomConfigRequest = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        Self::from_request(request)
+    }
+
+    /// Serialize to the TOML format read by EasyTier's `TomlConfigLoader`.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let peers = if self.peers.is_empty() {
+            DEFAULT_PEERS.iter().map(|p| p.to_string()).collect()
+        } else {
+            self.peers.clone()
+        };
+
+        let file = TomlFile {
+            instance_name: &self.instance_name,
+            hostname: self.hostname.as_deref(),
+            ipv4: self.ipv4.as_deref(),
+            dhcp: self.dhcp,
+            listeners: &self.listeners,
+            rpc_portal: self.rpc_portal.as_deref(),
+            network_identity: TomlNetworkIdentity {
+                network_name: &self.network_name,
+                network_secret: &self.network_secret,
+            },
+            peer: peers.iter().map(|uri| TomlPeer { uri }).collect(),
+            proxy_network: self
+                .proxy_networks
+                .iter()
+                .map(|cidr| TomlProxyNetwork { cidr })
+                .collect(),
+            flags: TomlFlags {
+                mtu: self.mtu,
+                no_tun: self.no_tun,
+                accept_dns: self.magic_dns,
+            },
+        };
+        toml::to_string(&file).map_err(|e| e.to_string())
+    }
+}
+
+impl RoomConfigBuilder {
+    pub fn instance_name(mut self, name: impl Into<String>) -> Self {
+        self.config.instance_name = name.into();
+        self
+    }
+
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.config.hostname = Some(hostname.into());
+        self
+    }
+
+    pub fn network_identity(mut self, name: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.config.network_name = name.into();
+        self.config.network_secret = secret.into();
+        self
+    }
+
+    /// Use a static address instead of DHCP.
+    pub fn ipv4(mut self, cidr: impl Into<String>) -> Self {
+        self.config.ipv4 = Some(cidr.into());
+        self.config.dhcp = false;
+        self
+    }
+
+    pub fn listener(mut self, url: impl Into<String>) -> Self {
+        self.config.listeners.push(url.into());
+        self
+    }
+
+    /// Replace the listeners, including the role's defaults. The other plural
+    /// setters replace their lists too, the singular ones append.
+    pub fn listeners(mut self, urls: Vec<String>) -> Self {
+        self.config.listeners = urls;
+        self
+    }
+
+    pub fn peer(mut self, uri: impl Into<String>) -> Self {
+        self.config.peers.push(uri.into());
+        self
+    }
+
+    pub fn peers(mut self, uris: Vec<String>) -> Self {
+        self.config.peers = uris;
+        self
+    }
+
+    pub fn proxy_networks(mut self, cidrs: Vec<String>) -> Self {
+        self.config.proxy_networks = cidrs;
+        self
+    }
+
+    pub fn rpc_portal(mut self, addr: impl Into<String>) -> Self {
+        self.config.rpc_portal = Some(addr.into());
+        self
+    }
+
+    pub fn mtu(mut self, mtu: u32) -> Self {
+        self.config.mtu = mtu;
+        self
+    }
+
+    pub fn no_tun(mut self, no_tun: bool) -> Self {
+        self.config.no_tun = no_tun;
+        self
+    }
+
+    pub fn magic_dns(mut self, enable: bool) -> Self {
+        self.config.magic_dns = enable;
+        self
+    }
+
+    pub fn build(self) -> RoomConfig {
+        self.config
+    }
+}
+
+pub fn host_listeners(port: u16) -> Vec<String> {
+    vec![
+        format!("tcp://0.0.0.0:{}", port),
+        format!("udp://0.0.0.0:{}", port),
+    ]
+}
+
+// 与 EasyTier 配置文件结构一一对应, 表必须位于普通键之后
+#[derive(Serialize)]
+struct TomlFile<'a> {
+    instance_name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv4: Option<&'a str>,
+    dhcp: bool,
+    listeners: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_portal: Option<&'a str>,
+    network_identity: TomlNetworkIdentity<'a>,
+    peer: Vec<TomlPeer<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    proxy_network: Vec<TomlProxyNetwork<'a>>,
+    flags: TomlFlags,
+}
+
+#[derive(Serialize)]
+struct TomlNetworkIdentity<'a> {
+    network_name: &'a str,
+    network_secret: &'a str,
+}
+
+#[derive(Serialize)]
+struct TomlPeer<'a> {
+    uri: &'a str,
+}
+
+#[derive(Serialize)]
+struct TomlProxyNetwork<'a> {
+    cidr: &'a str,
+}
+
+#[derive(Serialize)]
+struct TomlFlags {
+    mtu: u32,
+    no_tun: bool,
+    accept_dns: bool,
+}
@@ -0,0 +1,412 @@
+//! Magic DNS for player and room hostnames.
+//!
+//! The resolver sits on the packet path between the system tunnel and
+//! EasyTier and answers UDP queries sent to port 53 of the last host address
+//! of the virtual subnet, e.g. `10.14.255.254`. Names under `.terracotta`
+//! are answered from EasyTier's route table:
+//!
+//! - `<hostname>.terracotta` and `<hostname>.<room>.terracotta` resolve to
+//!   the virtual address of the player with that hostname,
+//! - `host.terracotta` and `host.<room>.terracotta` to the room host,
+//!
+//! where `<room>` is the network name without the `terracotta-mc-` prefix.
+//! The room host is the node whose hostname marks it as the host of the
+//! [scaffolding protocol](crate::scaffolding), whatever its address. Every
+//! other query is forwarded to the upstream servers.
+//!
+//! This resolver is the only one answering `.terracotta`. EasyTier's
+//! `accept_dns` merely switches it on and is cleared before EasyTier starts,
+//! so EasyTier's own magic DNS never runs.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use easytier::proto::api::instance::{NodeInfo, Route};
+use tokio::net::UdpSocket;
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+
+use crate::error::TcError;
+use crate::instance::lock;
+use crate::net::Ipv4Net;
+use crate::room_code::NETWORK_NAME_PREFIX;
+use crate::runtime;
+use crate::scaffolding;
+use crate::tun::Injector;
+use crate::udp;
+
+/// Domain the magic names live under.
+pub const MAGIC_DNS_DOMAIN: &str = "terracotta";
+
+pub const DNS_PORT: u16 = 53;
+
+/// Upstream servers used when the options name none.
+pub const DEFAULT_UPSTREAMS: &[&str] = &["223.5.5.5", "1.1.1.1"];
+
+/// Queries answered at once, further queries are dropped.
+pub const MAX_PENDING_QUERIES: usize = 64;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// TTL of magic answers, short so that renamed players show up quickly.
+const ANSWER_TTL: u32 = 5;
+
+const HEADER_LEN: usize = 12;
+const MAX_MESSAGE_LEN: usize = 4096;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// `name` as a lowercase DNS label, other characters collapse into `-`.
+/// `None` if nothing is left.
+pub fn dns_label(name: &str) -> Option<String> {
+    let mut label = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            label.push(c.to_ascii_lowercase());
+        } else if !label.is_empty() && !label.ends_with('-') {
+            label.push('-');
+        }
+    }
+    label.truncate(63);
+    let label = label.trim_end_matches('-');
+    (!label.is_empty()).then(|| label.to_string())
+}
+
+/// Label of the room in magic names, the network name without the
+/// [`NETWORK_NAME_PREFIX`].
+pub fn room_label(network_name: &str) -> Option<String> {
+    dns_label(
+        network_name
+            .strip_prefix(NETWORK_NAME_PREFIX)
+            .unwrap_or(network_name),
+    )
+}
+
+/// Address the resolver listens on in `subnet`, the last host address.
+/// `None` for subnets too small to spare one.
+pub fn magic_address(subnet: Ipv4Net) -> Option<Ipv4Addr> {
+    if subnet.prefix_len > 30 {
+        return None;
+    }
+    let broadcast = u32::from(subnet.network()) | !u32::from(subnet.netmask());
+    let addr = Ipv4Addr::from(broadcast - 1);
+    (addr != subnet.addr).then_some(addr)
+}
+
+/// Parse upstream servers given as `ip` or `ip:port`, invalid entries are
+/// skipped. Falls back to [`DEFAULT_UPSTREAMS`] if none are left.
+pub fn upstreams(servers: &[String]) -> Vec<SocketAddr> {
+    let parse = |server: &str| {
+        server
+            .parse::<SocketAddr>()
+            .or_else(|_| server.parse::<IpAddr>().map(|ip| (ip, DNS_PORT).into()))
+            .ok()
+    };
+    let parsed: Vec<SocketAddr> = servers
+        .iter()
+        .filter_map(|server| {
+            let addr = parse(server);
+            if addr.is_none() {
+                tracing::warn!("ignoring DNS server {}", server);
+            }
+            addr
+        })
+        .collect();
+    if parsed.is_empty() {
+        DEFAULT_UPSTREAMS.iter().filter_map(|s| parse(s)).collect()
+    } else {
+        parsed
+    }
+}
+
+/// How a name is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// A magic name with this address.
+    Found(Ipv4Addr),
+    /// A magic name nobody has.
+    NotFound,
+    /// Not a magic name, asked upstream.
+    Forward,
+}
+
+#[derive(Debug, Default)]
+struct Names {
+    hosts: HashMap<String, Ipv4Addr>,
+    host: Option<Ipv4Addr>,
+}
+
+/// The resolver of one instance, shared with its packet I/O.
+pub struct MagicDns {
+    room: Option<String>,
+    upstreams: Vec<SocketAddr>,
+    /// Address the resolver listens on, `0` until the instance has one.
+    addr: AtomicU32,
+    names: Mutex<Names>,
+}
+
+impl MagicDns {
+    pub fn new(room: Option<String>, upstreams: Vec<SocketAddr>) -> Self {
+        MagicDns {
+            room,
+            upstreams,
+            addr: AtomicU32::new(0),
+            names: Mutex::new(Names::default()),
+        }
+    }
+
+    pub fn address(&self) -> Option<Ipv4Addr> {
+        match self.addr.load(Ordering::Relaxed) {
+            0 => None,
+            addr => Some(Ipv4Addr::from(addr)),
+        }
+    }
+
+    /// Take the address and the names from the local node and the route
+    /// table. A hostname used twice keeps the first address, the local
+    /// node comes first.
+    pub fn update(&self, node: Option<&NodeInfo>, routes: &[Route]) {
+        let local = node
+            .and_then(|n| n.ipv4_addr.parse::<Ipv4Net>().ok())
+            .filter(|net| net.addr != Ipv4Addr::UNSPECIFIED);
+        let addr = local.and_then(magic_address).map_or(0, u32::from);
+        self.addr.store(addr, Ordering::Relaxed);
+
+        let local = node
+            .zip(local)
+            .map(|(n, net)| (n.hostname.as_str(), net.addr));
+        let peers = routes.iter().filter_map(|route| {
+            let addr = route.ipv4_addr.as_ref()?.to_string();
+            Some((route.hostname.as_str(), addr.parse::<Ipv4Net>().ok()?.addr))
+        });
+        let mut names = Names::default();
+        for (hostname, addr) in local.into_iter().chain(peers) {
+            if scaffolding::host_port(hostname).is_some() {
+                names.host.get_or_insert(addr);
+            }
+            if let Some(label) = dns_label(hostname) {
+                names.hosts.entry(label).or_insert(addr);
+            }
+        }
+        *lock(&self.names) = names;
+    }
+
+    pub fn lookup(&self, name: &str) -> Lookup {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let Some(labels) = name
+            .strip_suffix(MAGIC_DNS_DOMAIN)
+            .and_then(|rest| rest.strip_suffix('.'))
+        else {
+            return if name == MAGIC_DNS_DOMAIN {
+                Lookup::NotFound
+            } else {
+                Lookup::Forward
+            };
+        };
+        let label = match labels.split_once('.') {
+            None => labels,
+            Some((label, room)) if Some(room) == self.room.as_deref() => label,
+            Some(_) => return Lookup::NotFound,
+        };
+        let names = lock(&self.names);
+        let addr = match label {
+            "host" => names.host,
+            label => names.hosts.get(label).copied(),
+        };
+        addr.map_or(Lookup::NotFound, Lookup::Found)
+    }
+
+    /// Answer a DNS message, `None` if it is not a query. Magic names are
+    /// answered right away, everything else is forwarded and answered with
+    /// SERVFAIL if no upstream server responds.
+    pub async fn answer(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < HEADER_LEN || query[2] & 0x80 != 0 {
+            return None;
+        }
+        let question = parse_question(query);
+        let lookup = question
+            .as_ref()
+            .map_or(Lookup::Forward, |q| self.lookup(&q.name));
+        let question = question.as_ref();
+        Some(match lookup {
+            Lookup::Found(addr) => {
+                let q = question?;
+                let wanted = matches!(q.qtype, TYPE_A | TYPE_ANY) && q.qclass == CLASS_IN;
+                response(query, question, 0, wanted.then_some(addr))
+            }
+            Lookup::NotFound => response(query, question, RCODE_NXDOMAIN, None),
+            Lookup::Forward => match self.forward(query).await {
+                Some(reply) => reply,
+                None => response(query, question, RCODE_SERVFAIL, None),
+            },
+        })
+    }
+
+    async fn forward(&self, query: &[u8]) -> Option<Vec<u8>> {
+        for upstream in &self.upstreams {
+            match exchange(*upstream, query).await {
+                Ok(reply) => return Some(reply),
+                Err(e) => tracing::debug!("DNS server {} failed: {}", upstream, e),
+            }
+        }
+        None
+    }
+
+    /// Whether `packet` is an IPv4 UDP datagram to the resolver.
+    pub fn is_query(&self, packet: &[u8]) -> bool {
+        let Some(addr) = self.address() else {
+            return false;
+        };
+        udp::parse(packet).is_some_and(|d| *d.dst.ip() == addr && d.dst.port() == DNS_PORT)
+    }
+
+    /// Answer a query packet, the reply goes back to where it came from.
+    pub async fn reply(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        let query = udp::parse(packet)?;
+        let answer = self.answer(query.payload).await?;
+        Some(udp::packet(query.dst, query.src, &answer))
+    }
+}
+
+/// Takes queries for a [`MagicDns`] off a packet path and answers them in
+/// the background, sending the reply packets to the system through the
+/// [`Injector`] of the path.
+#[derive(Clone)]
+pub struct Interceptor {
+    dns: Arc<MagicDns>,
+    pending: Arc<Semaphore>,
+    replies: Injector,
+    runtime: &'static Runtime,
+}
+
+impl Interceptor {
+    pub fn new(dns: Arc<MagicDns>, replies: Injector) -> Result<Self, TcError> {
+        Ok(Interceptor {
+            dns,
+            pending: Arc::new(Semaphore::new(MAX_PENDING_QUERIES)),
+            replies,
+            runtime: runtime::get()?,
+        })
+    }
+
+    /// Whether `packet` is a query for the resolver. Such packets are
+    /// answered here and must not be passed on.
+    pub fn intercept(&self, packet: &[u8]) -> bool {
+        if !self.dns.is_query(packet) {
+            return false;
+        }
+        let Ok(permit) = self.pending.clone().try_acquire_owned() else {
+            tracing::debug!("dropping DNS query, too many pending");
+            return true;
+        };
+        let (dns, replies, packet) = (self.dns.clone(), self.replies.clone(), packet.to_vec());
+        self.runtime.spawn(async move {
+            if let Some(reply) = dns.reply(&packet).await {
+                let _ = replies.send(reply).await;
+            }
+            drop(permit);
+        });
+        true
+    }
+}
+
+struct Question<'a> {
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// The question section as sent.
+    raw: &'a [u8],
+}
+
+// 只处理恰好一个问题且不含压缩指针的查询
+fn parse_question(msg: &[u8]) -> Option<Question<'_>> {
+    if u16::from_be_bytes([msg[4], msg[5]]) != 1 || (msg[2] >> 3) & 0x0f != 0 {
+        return None;
+    }
+    let mut pos = HEADER_LEN;
+    let mut name = String::new();
+    loop {
+        let len = usize::from(*msg.get(pos)?);
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xc0 != 0 || name.len() + len > 253 {
+            return None;
+        }
+        let label = std::str::from_utf8(msg.get(pos..pos + len)?).ok()?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(label);
+        pos += len;
+    }
+    let fixed = msg.get(pos..pos + 4)?;
+    Some(Question {
+        name,
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        raw: &msg[HEADER_LEN..pos + 4],
+    })
+}
+
+fn response(
+    query: &[u8],
+    question: Option<&Question>,
+    rcode: u8,
+    answer: Option<Ipv4Addr>,
+) -> Vec<u8> {
+    // 魔法域名的应答是权威应答, 保留查询的 opcode 和 RD 位
+    let authoritative = if rcode == RCODE_SERVFAIL { 0 } else { 0x04 };
+    let mut msg = Vec::with_capacity(HEADER_LEN + 64);
+    msg.extend_from_slice(&query[..2]);
+    msg.push(0x80 | (query[2] & 0x79) | authoritative);
+    msg.push(0x80 | rcode);
+    msg.extend_from_slice(&u16::from(question.is_some()).to_be_bytes());
+    msg.extend_from_slice(&u16::from(answer.is_some()).to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(question) = question {
+        msg.extend_from_slice(question.raw);
+    }
+    if let Some(addr) = answer {
+        // 指向问题中的名字
+        msg.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+        msg.extend_from_slice(&TYPE_A.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+        msg.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+        msg.extend_from_slice(&4u16.to_be_bytes());
+        msg.extend_from_slice(&addr.octets());
+    }
+    msg
+}
+
+async fn exchange(upstream: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+    let mut buf = vec![0; MAX_MESSAGE_LEN];
+    let len = tokio::time::timeout(UPSTREAM_TIMEOUT, async {
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            // 只接受 ID 相同的应答
+            if n >= HEADER_LEN && buf[..2] == query[..2] {
+                return Ok::<_, io::Error>(n);
+            }
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no response"))??;
+    buf.truncate(len);
+    Ok(buf)
+}
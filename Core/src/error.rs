@@ -0,0 +1,160 @@
+//! Error codes returned by every FFI function.
+//!
+//! A failing call returns a negative [`TcErrorCode`], writes a readable
+//! message to its `err_msg` out-parameter and records the full error, cause
+//! chain included, as the last error of the calling thread. `tc_last_error`
+//! hands that record out as JSON.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::strings;
+
+/// Stable error codes, the numeric values are part of the C ABI.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TcErrorCode {
+    Ok = 0,
+    /// A null pointer or otherwise unusable argument.
+    InvalidArgument = -1,
+    InvalidRoomCode = -2,
+    InvalidOptions = -3,
+    /// The config could not be parsed or built.
+    Config = -4,
+    AlreadyRunning = -5,
+    /// EasyTier failed to bring the network up or to reach a peer.
+    Network = -6,
+    NotRunning = -7,
+    InvalidHandle = -8,
+    Io = -9,
+    /// A bug or an unexpected state inside the core.
+    Internal = -10,
+}
+
+impl TcErrorCode {
+    pub fn name(self) -> &'static str {
+        match self {
+            TcErrorCode::Ok => "ok",
+            TcErrorCode::InvalidArgument => "invalid_argument",
+            TcErrorCode::InvalidRoomCode => "invalid_room_code",
+            TcErrorCode::InvalidOptions => "invalid_options",
+            TcErrorCode::Config => "config",
+            TcErrorCode::AlreadyRunning => "already_running",
+            TcErrorCode::Network => "network",
+            TcErrorCode::NotRunning => "not_running",
+            TcErrorCode::InvalidHandle => "invalid_handle",
+            TcErrorCode::Io => "io",
+            TcErrorCode::Internal => "internal",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcError {
+    pub code: TcErrorCode,
+    pub message: String,
+    /// Underlying causes, outermost first.
+    pub causes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct TcErrorJson<'a> {
+    code: i32,
+    name: &'static str,
+    message: &'a str,
+    causes: &'a [String],
+}
+
+impl TcError {
+    pub fn new(code: TcErrorCode, message: impl Into<String>) -> Self {
+        TcError {
+            code,
+            message: message.into(),
+            causes: Vec::new(),
+        }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(TcErrorCode::InvalidArgument, message)
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::new(TcErrorCode::Config, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(TcErrorCode::Internal, message)
+    }
+
+    /// Keep the `source()` chain of `err` as causes.
+    pub fn from_error(code: TcErrorCode, err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut causes = Vec::new();
+        let mut source = err.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        TcError {
+            code,
+            message: err.to_string(),
+            causes,
+        }
+    }
+
+    /// Wrap the error in a higher level message, the old message becomes
+    /// the first cause.
+    pub fn context(mut self, message: impl Into<String>) -> Self {
+        let inner = std::mem::replace(&mut self.message, message.into());
+        self.causes.insert(0, inner);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let json = TcErrorJson {
+            code: self.code as i32,
+            name: self.code.name(),
+            message: &self.message,
+            causes: &self.causes,
+        };
+        serde_json::to_string(&json).unwrap_or_default()
+    }
+
+    /// Write the message to `err_msg`, remember the error as the last
+    /// error of this thread and return its code.
+    pub(crate) fn report(self, err_msg: *mut *const c_char) -> TcErrorCode {
+        if !err_msg.is_null() {
+            if let Ok(cstr) = CString::new(self.to_string()) {
+                unsafe {
+                    *err_msg = strings::into_raw(cstr);
+                }
+            };
+        }
+        let code = self.code;
+        LAST_ERROR.with(|last| *last.borrow_mut() = Some(self));
+        code
+    }
+}
+
+impl fmt::Display for TcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for cause in &self.causes {
+            write!(f, ": {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TcError {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<TcError>> = const { RefCell::new(None) };
+}
+
+/// Last error reported on the calling thread.
+pub fn last_error() -> Option<TcError> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
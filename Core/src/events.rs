@@ -0,0 +1,449 @@
+//! EasyTier events as versioned JSON.
+//!
+//! Every [`GlobalCtxEvent`] maps to an [`Event`] that serializes as
+//! `{"version": 1, "instance": 1, "timestampMs": ..., "type": "peer_added", ...}`
+//! with the payload fields of its type flattened in.
+
+use std::collections::VecDeque;
+use std::ffi::{c_char, c_void, CString};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use easytier::common::global_ctx::GlobalCtxEvent;
+use serde::Serialize;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+
+use crate::instance::{lock, Handle, INVALID_HANDLE};
+use crate::shutdown::{StopReason, StopState};
+
+pub const EVENTS_VERSION: u32 = 1;
+
+/// Events buffered per instance for [`EventQueue::poll`].
+pub const EVENT_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum EventPayload {
+    TunDeviceReady {
+        device: String,
+    },
+    TunDeviceError {
+        error: String,
+    },
+    PeerAdded {
+        peer_id: u32,
+    },
+    PeerRemoved {
+        peer_id: u32,
+    },
+    PeerConnAdded {
+        conn: serde_json::Value,
+    },
+    PeerConnRemoved {
+        conn: serde_json::Value,
+    },
+    ListenerAdded {
+        url: String,
+    },
+    ListenerAddFailed {
+        url: String,
+        error: String,
+    },
+    ListenerAcceptFailed {
+        url: String,
+        error: String,
+    },
+    ConnectionAccepted {
+        local_addr: String,
+        remote_addr: String,
+    },
+    ConnectionError {
+        local_addr: String,
+        remote_addr: String,
+        error: String,
+    },
+    Connecting {
+        url: String,
+    },
+    ConnectError {
+        url: String,
+        ip_version: String,
+        error: String,
+    },
+    VpnPortalClientConnected {
+        portal: String,
+        client: String,
+    },
+    VpnPortalClientDisconnected {
+        portal: String,
+        client: String,
+    },
+    DhcpIpv4Changed {
+        old: Option<String>,
+        new: Option<String>,
+    },
+    DhcpIpv4Conflicted {
+        ip: Option<String>,
+    },
+    PortForwardAdded {
+        config: serde_json::Value,
+    },
+    ProxyCidrsUpdated {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    ConfigPatched {
+        patch: serde_json::Value,
+    },
+    /// The subscriber fell behind and `count` events were lost.
+    EventsDropped {
+        count: u64,
+    },
+    /// The run of the instance ended, always the last event of a run.
+    InstanceStopped {
+        reason: StopReason,
+    },
+    /// An event this version of the core does not know how to describe.
+    Unknown {
+        debug: String,
+    },
+}
+
+fn to_value<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+fn to_strings<T: ToString>(values: &[T]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl From<&GlobalCtxEvent> for EventPayload {
+    fn from(event: &GlobalCtxEvent) -> Self {
+        match event {
+            GlobalCtxEvent::TunDeviceReady(device) => EventPayload::TunDeviceReady {
+                device: device.clone(),
+            },
+            GlobalCtxEvent::TunDeviceError(error) => EventPayload::TunDeviceError {
+                error: error.clone(),
+            },
+            GlobalCtxEvent::PeerAdded(peer_id) => EventPayload::PeerAdded { peer_id: *peer_id },
+            GlobalCtxEvent::PeerRemoved(peer_id) => EventPayload::PeerRemoved { peer_id: *peer_id },
+            GlobalCtxEvent::PeerConnAdded(conn) => EventPayload::PeerConnAdded {
+                conn: to_value(conn),
+            },
+            GlobalCtxEvent::PeerConnRemoved(conn) => EventPayload::PeerConnRemoved {
+                conn: to_value(conn),
+            },
+            GlobalCtxEvent::ListenerAdded(url) => EventPayload::ListenerAdded {
+                url: url.to_string(),
+            },
+            GlobalCtxEvent::ListenerAddFailed(url, error) => EventPayload::ListenerAddFailed {
+                url: url.to_string(),
+                error: error.clone(),
+            },
+            GlobalCtxEvent::ListenerAcceptFailed(url, error) => {
+                EventPayload::ListenerAcceptFailed {
+                    url: url.to_string(),
+                    error: error.clone(),
+                }
+            }
+            GlobalCtxEvent::ConnectionAccepted(local_addr, remote_addr) => {
+                EventPayload::ConnectionAccepted {
+                    local_addr: local_addr.clone(),
+                    remote_addr: remote_addr.clone(),
+                }
+            }
+            GlobalCtxEvent::ConnectionError(local_addr, remote_addr, error) => {
+                EventPayload::ConnectionError {
+                    local_addr: local_addr.clone(),
+                    remote_addr: remote_addr.clone(),
+                    error: error.clone(),
+                }
+            }
+            GlobalCtxEvent::Connecting(url) => EventPayload::Connecting {
+                url: url.to_string(),
+            },
+            GlobalCtxEvent::ConnectError(url, ip_version, error) => EventPayload::ConnectError {
+                url: url.clone(),
+                ip_version: ip_version.clone(),
+                error: error.clone(),
+            },
+            GlobalCtxEvent::VpnPortalClientConnected(portal, client) => {
+                EventPayload::VpnPortalClientConnected {
+                    portal: portal.clone(),
+                    client: client.clone(),
+                }
+            }
+            GlobalCtxEvent::VpnPortalClientDisconnected(portal, client) => {
+                EventPayload::VpnPortalClientDisconnected {
+                    portal: portal.clone(),
+                    client: client.clone(),
+                }
+            }
+            GlobalCtxEvent::DhcpIpv4Changed(old, new) => EventPayload::DhcpIpv4Changed {
+                old: old.map(|ip| ip.to_string()),
+                new: new.map(|ip| ip.to_string()),
+            },
+            GlobalCtxEvent::DhcpIpv4Conflicted(ip) => EventPayload::DhcpIpv4Conflicted {
+                ip: ip.map(|ip| ip.to_string()),
+            },
+            GlobalCtxEvent::PortForwardAdded(config) => EventPayload::PortForwardAdded {
+                config: to_value(config),
+            },
+            GlobalCtxEvent::ProxyCidrsUpdated(added, removed) => EventPayload::ProxyCidrsUpdated {
+                added: to_strings(added),
+                removed: to_strings(removed),
+            },
+            GlobalCtxEvent::ConfigPatched(patch) => EventPayload::ConfigPatched {
+                patch: to_value(patch),
+            },
+            // 新版本 EasyTier 增加的事件
+            #[allow(unreachable_patterns)]
+            event => EventPayload::Unknown {
+                debug: format!("{:?}", event),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub version: u32,
+    pub instance: Handle,
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub payload: EventPayload,
+}
+
+impl Event {
+    pub fn new(instance: Handle, payload: EventPayload) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Event {
+            version: EVENTS_VERSION,
+            instance,
+            timestamp_ms,
+            payload,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Receive events until the instance stops. A lagging receiver reports
+/// the number of events it missed as `Err(count)` and keeps going.
+pub async fn recv_loop(
+    mut rx: Receiver<GlobalCtxEvent>,
+    mut f: impl FnMut(Result<GlobalCtxEvent, u64>),
+) {
+    loop {
+        match rx.recv().await {
+            Ok(event) => f(Ok(event)),
+            Err(RecvError::Closed) => {
+                break;
+            }
+            Err(RecvError::Lagged(count)) => {
+                f(Err(count));
+                continue;
+            }
+        }
+    }
+}
+
+fn to_event(instance: Handle, event: Result<GlobalCtxEvent, u64>) -> Event {
+    let payload = match event {
+        Ok(event) => EventPayload::from(&event),
+        Err(count) => EventPayload::EventsDropped { count },
+    };
+    Event::new(instance, payload)
+}
+
+/// Forward every event of `rx` to `f` as an [`Event`] of `instance`.
+pub async fn forward(instance: Handle, rx: Receiver<GlobalCtxEvent>, mut f: impl FnMut(Event)) {
+    recv_loop(rx, |event| f(to_event(instance, event))).await
+}
+
+/// [`forward`] until the run guarded by `stop_state` ends, returns why it
+/// ended. A forced shutdown may never close the event stream.
+pub async fn forward_until_stopped(
+    instance: Handle,
+    mut rx: Receiver<GlobalCtxEvent>,
+    stop_state: &StopState,
+    mut f: impl FnMut(Event),
+) -> StopReason {
+    loop {
+        tokio::select! {
+            biased;
+            received = rx.recv() => match received {
+                Ok(event) => f(to_event(instance, Ok(event))),
+                Err(RecvError::Lagged(count)) => f(to_event(instance, Err(count))),
+                Err(RecvError::Closed) => return stop_state.wait().await,
+            },
+            reason = stop_state.wait() => {
+                // 停止前已排队的事件仍然送出
+                loop {
+                    match rx.try_recv() {
+                        Ok(event) => f(to_event(instance, Ok(event))),
+                        Err(TryRecvError::Lagged(count)) => f(to_event(instance, Err(count))),
+                        Err(_) => return reason,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Receives each event as a JSON string that is only valid during the
+/// call. A null `event` ends the subscription, `user_data` is not used
+/// afterwards.
+pub type EventCallback = extern "C" fn(user_data: *mut c_void, event: *const c_char);
+
+pub struct Subscriber {
+    callback: EventCallback,
+    user_data: *mut c_void,
+}
+
+// user_data 由调用方保证可在任意线程使用
+unsafe impl Send for Subscriber {}
+// 回调只在订阅任务中依次调用, 不会并发
+unsafe impl Sync for Subscriber {}
+
+impl Subscriber {
+    pub fn new(callback: EventCallback, user_data: *mut c_void) -> Self {
+        Subscriber {
+            callback,
+            user_data,
+        }
+    }
+
+    pub fn deliver(&self, event: &Event) {
+        if let Ok(json) = CString::new(event.to_json()) {
+            (self.callback)(self.user_data, json.as_ptr());
+        }
+    }
+
+    pub fn close(self) {
+        (self.callback)(self.user_data, std::ptr::null());
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    events: VecDeque<Event>,
+    /// Dropped since the last poll.
+    pending_dropped: u64,
+    /// Dropped since the queue was created.
+    dropped: u64,
+}
+
+impl QueueState {
+    fn record_dropped(&mut self, count: u64) {
+        self.pending_dropped += count;
+        self.dropped += count;
+    }
+}
+
+/// Bounded event buffer for embedders that pull events instead of taking
+/// callbacks. Once full the oldest event is dropped, the next poll starts
+/// with an `events_dropped` event counting what was lost.
+pub struct EventQueue {
+    instance: AtomicU64,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        EventQueue::new(EVENT_QUEUE_CAPACITY)
+    }
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> Self {
+        EventQueue {
+            instance: AtomicU64::new(INVALID_HANDLE),
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Handle stamped on queued events, the instance is registered after
+    /// its queue is created.
+    pub fn set_instance(&self, instance: Handle) {
+        self.instance.store(instance, Ordering::Relaxed);
+    }
+
+    pub fn push(&self, mut event: Event) {
+        event.instance = self.instance.load(Ordering::Relaxed);
+        let mut state = lock(&self.state);
+        if let EventPayload::EventsDropped { count } = event.payload {
+            state.record_dropped(count);
+        } else {
+            if state.events.len() >= self.capacity {
+                state.events.pop_front();
+                state.record_dropped(1);
+            }
+            state.events.push_back(event);
+        }
+        drop(state);
+        self.ready.notify_all();
+    }
+
+    /// Take up to `max` events, waiting at most `timeout` for the first one.
+    pub fn poll(&self, max: usize, timeout: Duration) -> Vec<Event> {
+        let state = lock(&self.state);
+        let (mut state, _) = self
+            .ready
+            .wait_timeout_while(state, timeout, |state| {
+                state.events.is_empty() && state.pending_dropped == 0
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut events = Vec::new();
+        if state.pending_dropped > 0 && max > 0 {
+            let count = std::mem::take(&mut state.pending_dropped);
+            let instance = self.instance.load(Ordering::Relaxed);
+            events.push(Event::new(instance, EventPayload::EventsDropped { count }));
+        }
+        while events.len() < max {
+            match state.events.pop_front() {
+                Some(event) => events.push(event),
+                None => break,
+            }
+        }
+        events
+    }
+
+    /// Number of events lost since the queue was created.
+    pub fn dropped(&self) -> u64 {
+        lock(&self.state).dropped
+    }
+}
+
+/// Fill `queue` with the events of `rx` until the run guarded by
+/// `stop_state` ends.
+pub async fn feed(
+    queue: Arc<EventQueue>,
+    rx: Receiver<GlobalCtxEvent>,
+    stop_state: Arc<StopState>,
+) {
+    let reason =
+        forward_until_stopped(INVALID_HANDLE, rx, &stop_state, |event| queue.push(event)).await;
+    queue.push(Event::new(
+        INVALID_HANDLE,
+        EventPayload::InstanceStopped { reason },
+    ));
+}
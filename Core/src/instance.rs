@@ -0,0 +1,655 @@
+//! Network instances addressed by opaque handles.
+//!
+//! Every instance lives in a process-wide registry keyed by a [`Handle`].
+//! Handles are plain integers that are never reused, so a stale or freed
+//! handle is reported as an error instead of touching freed memory. The
+//! legacy global FFI functions operate on the [default handle](default_handle).
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::fd::{IntoRawFd, OwnedFd};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::Duration;
+
+use easytier::{
+    common::config::{ConfigFileControl, ConfigLoader},
+    common::global_ctx::GlobalCtxEvent,
+    launcher::{ApiService, NetworkInstance},
+    proto::{
+        api::instance::{ListPeerRequest, ListRouteRequest, ShowNodeInfoRequest},
+        rpc_types::controller::BaseController,
+    },
+};
+use tokio::sync::broadcast::Receiver;
+
+use crate::bedrock::{self, BedrockRelay};
+use crate::bridge::{OutboundCallback, PacketBridge};
+use crate::dns::{self, MagicDns};
+use crate::error::{TcError, TcErrorCode};
+use crate::events::{self, Event, EventPayload, EventQueue, Subscriber};
+use crate::lan::{self, LanRelay, Overlay};
+use crate::options;
+use crate::runtime;
+use crate::scaffolding::{self, PlayerProfile};
+use crate::shutdown::{
+    self, ListenerPort, Running, StopCallback, StopReason, StopState, DEFAULT_SHUTDOWN_TIMEOUT,
+};
+use crate::tun::{self, TunAdapter, TunStatsSnapshot};
+use crate::tunnel::{self, TunnelParams};
+
+/// Opaque instance handle, `0` is never a valid handle.
+pub type Handle = u64;
+
+pub const INVALID_HANDLE: Handle = 0;
+
+/// How often the magic DNS and the LAN relays pick up route changes.
+const ROUTE_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How packets reach the running instance.
+enum PacketIo {
+    Tun(TunAdapter),
+    Bridge(PacketBridge),
+}
+
+pub struct Instance {
+    /// Config as passed in, reloaded on every start so a stopped instance
+    /// can be started again.
+    source: String,
+    params: TunnelParams,
+    inst: Option<NetworkInstance>,
+    events: Arc<EventQueue>,
+    /// End of the current run, replaced on every start.
+    stop_state: Arc<StopState>,
+    /// Ports the listeners of the current run bind.
+    listeners: Vec<ListenerPort>,
+    packet_io: Option<PacketIo>,
+    /// Resolver of the current run if magic DNS is enabled.
+    dns: Option<Arc<MagicDns>>,
+    /// Peers the LAN world relays of the current run send to if LAN
+    /// discovery is enabled.
+    overlay: Option<Arc<Overlay>>,
+    bedrock: Option<Arc<BedrockRelay>>,
+    /// Room served by the scaffolding server of the current run, if this
+    /// instance hosts one.
+    scaffolding: Option<Arc<scaffolding::Host>>,
+}
+
+impl Instance {
+    /// Parse `cfg_str`, given as EasyTier TOML or a `TerracottaOptions`
+    /// JSON envelope, without starting anything.
+    pub fn new(cfg_str: &str) -> Result<Self, TcError> {
+        let loaded = options::load(cfg_str).map_err(TcError::config)?;
+        let params = TunnelParams::new(&loaded.config.get_flags(), &loaded.options);
+        Ok(Instance {
+            source: cfg_str.to_string(),
+            params,
+            inst: None,
+            events: Arc::new(EventQueue::default()),
+            stop_state: StopState::new(),
+            listeners: Vec::new(),
+            packet_io: None,
+            dns: None,
+            overlay: None,
+            bedrock: None,
+            scaffolding: None,
+        })
+    }
+
+    pub fn start(&mut self) -> Result<(), TcError> {
+        if self.inst.is_some() {
+            return Err(TcError::new(
+                TcErrorCode::AlreadyRunning,
+                "instance is already running",
+            ));
+        }
+        let loaded = options::load(&self.source).map_err(TcError::config)?;
+        self.params = TunnelParams::new(&loaded.config.get_flags(), &loaded.options);
+        // accept_dns 只用来打开核心的魔法 DNS, 不启动 EasyTier 自带的解析器
+        let mut flags = loaded.config.get_flags();
+        if flags.accept_dns {
+            flags.accept_dns = false;
+            loaded.config.set_flags(flags);
+        }
+        let dns = self.params.magic_dns.then(|| {
+            let network_name = loaded.config.get_network_identity().network_name;
+            Arc::new(MagicDns::new(
+                dns::room_label(&network_name),
+                dns::upstreams(&self.params.dns),
+            ))
+        });
+        let overlay = loaded
+            .options
+            .lan_discovery
+            .then(|| Arc::new(Overlay::default()));
+        let bedrock = overlay
+            .as_ref()
+            .map(|overlay| Arc::new(BedrockRelay::new(overlay.clone())));
+        let scaffolding = loaded.options.scaffolding.as_ref().map(|options| {
+            let profile = PlayerProfile {
+                name: options
+                    .player_name
+                    .clone()
+                    .unwrap_or_else(|| loaded.config.get_hostname()),
+                machine_id: options
+                    .machine_id
+                    .clone()
+                    .unwrap_or_else(|| loaded.config.get_id().to_string()),
+                vendor: scaffolding::VENDOR.to_string(),
+                kind: None,
+            };
+            let host = Arc::new(scaffolding::Host::new(profile));
+            host.set_server_port(options.server_port);
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, options.port));
+            (host, addr)
+        });
+        let listeners = ListenerPort::from_urls(&loaded.config.get_listener_uris());
+        let mut inst = NetworkInstance::new(loaded.config, ConfigFileControl::STATIC_CONFIG);
+        inst.start().map_err(|e| {
+            TcError::from_error(TcErrorCode::Network, e.as_ref())
+                .context("failed to start network instance")
+        })?;
+        let stop_state = StopState::new();
+        if let Some(stop) = inst.get_stop_notifier() {
+            runtime::spawn(shutdown::monitor(stop, stop_state.clone()))?;
+        }
+        if let Some(rx) = inst.subscribe_event() {
+            runtime::spawn(events::feed(self.events.clone(), rx, stop_state.clone()))?;
+        }
+        if dns.is_some() || overlay.is_some() {
+            if let Some(api_service) = inst.get_api_service() {
+                runtime::spawn(refresh_routes(
+                    api_service,
+                    dns.clone(),
+                    overlay.clone(),
+                    stop_state.clone(),
+                ))?;
+            }
+        }
+        if let Some(overlay) = &overlay {
+            runtime::spawn(lan::run(LanRelay::new(overlay.clone()), stop_state.clone()))?;
+        }
+        if let Some(bedrock) = &bedrock {
+            runtime::spawn(bedrock::run(bedrock.clone(), stop_state.clone()))?;
+        }
+        // 监听所有地址, 其他玩家经隧道访问主机的虚拟 IP
+        if let Some((host, addr)) = &scaffolding {
+            runtime::spawn(scaffolding::serve(host.clone(), *addr, stop_state.clone()))?;
+        }
+        self.inst = Some(inst);
+        self.stop_state = stop_state;
+        self.listeners = listeners;
+        self.dns = dns;
+        self.overlay = overlay;
+        self.bedrock = bedrock;
+        self.scaffolding = scaffolding.map(|(host, _)| host);
+        Ok(())
+    }
+
+    /// Detach the running EasyTier instance so it can be shut down without
+    /// holding the instance lock.
+    fn take_running(&mut self) -> Option<Running> {
+        self.packet_io = None;
+        self.dns = None;
+        self.overlay = None;
+        self.bedrock = None;
+        self.scaffolding = None;
+        let inst = self.inst.take()?;
+        Some(Running {
+            inst,
+            stop_state: self.stop_state.clone(),
+            listeners: std::mem::take(&mut self.listeners),
+        })
+    }
+
+    /// Hand EasyTier one end of a new [`nic_pair`](tun::nic_pair) as its
+    /// tun device and return the other end.
+    fn attach_nic(&mut self) -> Result<OwnedFd, TcError> {
+        let inst = self
+            .inst
+            .as_mut()
+            .ok_or_else(|| TcError::new(TcErrorCode::NotRunning, "instance is not running"))?;
+        let (easytier_end, core_end) =
+            tun::nic_pair().map_err(|e| TcError::from_error(TcErrorCode::Io, &e))?;
+        // EasyTier 接管 socketpair 的另一端, 收发不带地址族头的 IP 包
+        inst.set_tun_fd(easytier_end.into_raw_fd()).map_err(|e| {
+            TcError::from_error(TcErrorCode::Network, e.as_ref()).context("failed to attach tun fd")
+        })?;
+        if let Some(overlay) = &self.overlay {
+            let nic = core_end
+                .try_clone()
+                .map_err(|e| TcError::from_error(TcErrorCode::Io, &e))?;
+            overlay.attach(nic)?;
+        }
+        Ok(core_end)
+    }
+
+    /// Attach the system tunnel `fd`. The instance owns the fd from now on
+    /// and closes it when stopped or when other packet I/O is attached.
+    pub fn set_tun_fd(&mut self, fd: OwnedFd) -> Result<(), TcError> {
+        let nic = self.attach_nic()?;
+        let adapter = TunAdapter::start(fd, nic, self.dns.clone(), self.overlay.clone())?;
+        if let Some(bedrock) = &self.bedrock {
+            bedrock.attach_system(adapter.injector());
+        }
+        self.packet_io = Some(PacketIo::Tun(adapter));
+        Ok(())
+    }
+
+    /// Send EasyTier's packets to `callback`, attaching the packet bridge
+    /// if it is not attached yet.
+    pub fn set_outbound_packet_callback(
+        &mut self,
+        callback: OutboundCallback,
+    ) -> Result<(), TcError> {
+        if let Some(PacketIo::Bridge(bridge)) = &self.packet_io {
+            bridge.set_callback(callback);
+            return Ok(());
+        }
+        let nic = self.attach_nic()?;
+        let bridge = PacketBridge::start(nic, callback, self.dns.clone(), self.overlay.clone())?;
+        if let Some(bedrock) = &self.bedrock {
+            bedrock.attach_system(bridge.injector());
+        }
+        self.packet_io = Some(PacketIo::Bridge(bridge));
+        Ok(())
+    }
+
+    /// Pass packets read from the system to EasyTier through the packet
+    /// bridge.
+    pub fn push_inbound_packets<'a>(
+        &self,
+        packets: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<(), TcError> {
+        match &self.packet_io {
+            Some(PacketIo::Bridge(bridge)) => {
+                bridge.push(packets);
+                Ok(())
+            }
+            _ if !self.is_running() => Err(TcError::new(
+                TcErrorCode::NotRunning,
+                "instance is not running",
+            )),
+            _ => Err(TcError::new(
+                TcErrorCode::NotRunning,
+                "packet bridge is not attached, set an outbound packet callback first",
+            )),
+        }
+    }
+
+    pub fn tun_stats(&self) -> Option<TunStatsSnapshot> {
+        match self.packet_io.as_ref()? {
+            PacketIo::Tun(adapter) => Some(adapter.stats()),
+            PacketIo::Bridge(bridge) => Some(bridge.stats()),
+        }
+    }
+
+    /// Stop the instance and wait up to `timeout` for it to shut down,
+    /// returns `None` if it was not running.
+    pub fn stop(&mut self, timeout: Duration) -> Result<Option<StopReason>, TcError> {
+        match self.take_running() {
+            Some(running) => shutdown::shutdown(running, timeout).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inst.is_some()
+    }
+
+    pub fn events(&self) -> &Arc<EventQueue> {
+        &self.events
+    }
+
+    pub fn tunnel_params(&self) -> &TunnelParams {
+        &self.params
+    }
+
+    pub fn running(&self) -> Result<&NetworkInstance, TcError> {
+        self.inst
+            .as_ref()
+            .ok_or_else(|| TcError::new(TcErrorCode::NotRunning, "instance is not running"))
+    }
+
+    pub fn api_service(&self) -> Result<ApiService, TcError> {
+        self.running()?
+            .get_api_service()
+            .ok_or_else(|| TcError::internal("no API service"))
+    }
+
+    /// Room served by the scaffolding server of the running instance.
+    pub fn scaffolding_host(&self) -> Result<&Arc<scaffolding::Host>, TcError> {
+        self.running()?;
+        self.scaffolding.as_ref().ok_or_else(|| {
+            TcError::new(
+                TcErrorCode::NotRunning,
+                "instance does not run a scaffolding server",
+            )
+        })
+    }
+}
+
+struct Registry {
+    next: Handle,
+    instances: HashMap<Handle, Arc<Mutex<Instance>>>,
+}
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| {
+    Mutex::new(Registry {
+        next: 1,
+        instances: HashMap::new(),
+    })
+});
+
+static DEFAULT_HANDLE: Mutex<Option<Handle>> = Mutex::new(None);
+
+// 从检查默认实例到登记新实例一直持有, 并发的启动不会都通过检查
+static DEFAULT_START: Mutex<()> = Mutex::new(());
+
+/// Lock a mutex, ignoring poisoning. A panic while holding one of these
+/// locks leaves the data consistent, so the next caller can carry on.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn insert(instance: Instance) -> Handle {
+    let mut registry = lock(&REGISTRY);
+    let handle = registry.next;
+    registry.next += 1;
+    instance.events.set_instance(handle);
+    registry
+        .instances
+        .insert(handle, Arc::new(Mutex::new(instance)));
+    handle
+}
+
+pub fn get(handle: Handle) -> Result<Arc<Mutex<Instance>>, TcError> {
+    lock(&REGISTRY)
+        .instances
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| {
+            TcError::new(
+                TcErrorCode::InvalidHandle,
+                format!("invalid instance handle {}", handle),
+            )
+        })
+}
+
+/// Stop and drop the instance, returns false for an unknown handle.
+pub fn free(handle: Handle) -> bool {
+    let Some(instance) = lock(&REGISTRY).instances.remove(&handle) else {
+        return false;
+    };
+    let running = lock(&instance).take_running();
+    if let Some(running) = running {
+        if let Err(e) = shutdown::shutdown(running, DEFAULT_SHUTDOWN_TIMEOUT) {
+            tracing::error!("failed to shut down instance {}: {}", handle, e);
+        }
+    }
+    let mut default = lock(&DEFAULT_HANDLE);
+    if *default == Some(handle) {
+        *default = None;
+    }
+    true
+}
+
+/// Run `f` with the instance locked.
+pub fn with<R>(handle: Handle, f: impl FnOnce(&mut Instance) -> R) -> Result<R, TcError> {
+    let instance = get(handle)?;
+    let mut instance = lock(&instance);
+    Ok(f(&mut instance))
+}
+
+pub fn start(handle: Handle) -> Result<(), TcError> {
+    with(handle, Instance::start)?
+}
+
+/// Stop the instance and wait up to `timeout` for it to shut down, the
+/// instance lock is released while waiting. Returns `None` if it was not
+/// running.
+pub fn stop(handle: Handle, timeout: Duration) -> Result<Option<StopReason>, TcError> {
+    match with(handle, Instance::take_running)? {
+        Some(running) => shutdown::shutdown(running, timeout).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Handle used by the global FFI functions.
+pub fn default_handle() -> Result<Handle, TcError> {
+    lock(&DEFAULT_HANDLE)
+        .ok_or_else(|| TcError::new(TcErrorCode::NotRunning, "no running instance"))
+}
+
+/// Make `handle` the default, returns the previous default.
+pub fn set_default_handle(handle: Handle) -> Option<Handle> {
+    lock(&DEFAULT_HANDLE).replace(handle)
+}
+
+/// Make the instance returned by `start` the default, failing with
+/// `already_running` while the current default runs. A stopped default is
+/// freed before `start` runs, so its ports are released before the new
+/// instance binds them.
+pub fn start_default<E>(
+    already_running: impl FnOnce() -> E,
+    start: impl FnOnce() -> Result<Instance, E>,
+) -> Result<Handle, E> {
+    let _starting = lock(&DEFAULT_START);
+    if default_is_running() {
+        return Err(already_running());
+    }
+    if let Ok(old) = default_handle() {
+        free(old);
+    }
+    let handle = insert(start()?);
+    set_default_handle(handle);
+    Ok(handle)
+}
+
+/// Whether the default instance exists and is running.
+pub fn default_is_running() -> bool {
+    default_handle()
+        .and_then(|handle| with(handle, |instance| instance.is_running()))
+        .unwrap_or(false)
+}
+
+// 查询前先释放实例锁, 避免阻塞其他调用
+fn api_service(handle: Handle) -> Result<ApiService, TcError> {
+    with(handle, |instance| instance.api_service())?
+}
+
+pub fn latest_error_msg(handle: Handle) -> Result<Option<String>, TcError> {
+    with(handle, |instance| {
+        Ok(instance.running()?.get_latest_error_msg())
+    })?
+}
+
+pub fn running_info(handle: Handle) -> Result<String, TcError> {
+    runtime::block_on(running_info_async(handle))?
+}
+
+pub async fn running_info_async(handle: Handle) -> Result<String, TcError> {
+    let api_service = api_service(handle)?;
+    let peer_service = api_service.get_peer_manage_service();
+
+    // 获取路由信息
+    let routes = peer_service
+        .list_route(BaseController::default(), ListRouteRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+
+    // 获取节点信息
+    let node_info = peer_service
+        .show_node_info(BaseController::default(), ShowNodeInfoRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+
+    // 获取对等节点信息
+    let peers = peer_service
+        .list_peer(BaseController::default(), ListPeerRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+
+    let node = node_info.node_info.as_ref();
+    let result = serde_json::json!({
+        "routes": routes.routes,
+        "node_info": node_info.node_info,
+        "peers": peers.peers,
+        "network_info": {
+            "node_id": node.map(|n| n.node_id.clone()).unwrap_or_default(),
+            "ipv4": node.and_then(|n| n.ipv4.as_ref()).map(|ip| ip.to_string()),
+            "ipv6": node.and_then(|n| n.ipv6.as_ref()).map(|ip| ip.to_string()),
+            "version": node.map(|n| n.version.clone()).unwrap_or_default()
+        }
+    });
+    Ok(result.to_string())
+}
+
+pub fn tunnel_settings(handle: Handle) -> Result<String, TcError> {
+    runtime::block_on(tunnel_settings_async(handle))?
+}
+
+pub async fn tunnel_settings_async(handle: Handle) -> Result<String, TcError> {
+    let (api_service, params) = with(handle, |instance| {
+        Ok::<_, TcError>((instance.api_service()?, instance.tunnel_params().clone()))
+    })??;
+    let peer_service = api_service.get_peer_manage_service();
+
+    let node_info = peer_service
+        .show_node_info(BaseController::default(), ShowNodeInfoRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+    let routes = peer_service
+        .list_route(BaseController::default(), ListRouteRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+    let peers = peer_service
+        .list_peer(BaseController::default(), ListPeerRequest::default())
+        .await
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))?;
+
+    // DHCP 分配地址之前 ipv4 为 null
+    let settings = tunnel::compute(
+        node_info.node_info.as_ref(),
+        &routes.routes,
+        &peers.peers,
+        &params,
+    );
+    serde_json::to_string(&settings).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+}
+
+// 定期把路由表交给魔法 DNS 和局域网中继, 直到本次运行结束
+async fn refresh_routes(
+    api_service: ApiService,
+    dns: Option<Arc<MagicDns>>,
+    overlay: Option<Arc<Overlay>>,
+    stop_state: Arc<StopState>,
+) {
+    let peer_service = api_service.get_peer_manage_service();
+    loop {
+        let node_info = peer_service
+            .show_node_info(BaseController::default(), ShowNodeInfoRequest::default())
+            .await;
+        let routes = peer_service
+            .list_route(BaseController::default(), ListRouteRequest::default())
+            .await;
+        match (node_info, routes) {
+            (Ok(node_info), Ok(routes)) => {
+                let node = node_info.node_info.as_ref();
+                if let Some(dns) = &dns {
+                    dns.update(node, &routes.routes);
+                }
+                if let Some(overlay) = &overlay {
+                    overlay.update(node, &routes.routes);
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => tracing::debug!("failed to refresh routes: {}", e),
+        }
+        tokio::select! {
+            _ = stop_state.wait() => break,
+            _ = tokio::time::sleep(ROUTE_REFRESH_INTERVAL) => {}
+        }
+    }
+}
+
+/// Set the Minecraft server port the scaffolding server reports, `None`
+/// while there is no game to join.
+pub fn set_scaffolding_server_port(handle: Handle, port: Option<u16>) -> Result<(), TcError> {
+    with(handle, |instance| {
+        instance.scaffolding_host()?.set_server_port(port);
+        Ok(())
+    })?
+}
+
+/// Players of the room served by the scaffolding server as JSON.
+pub fn scaffolding_players(handle: Handle) -> Result<String, TcError> {
+    let host = with(handle, |instance| instance.scaffolding_host().cloned())??;
+    serde_json::to_string(&host.players())
+        .map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+}
+
+/// Packet and ring counters of the attached packet I/O as JSON.
+pub fn tun_stats(handle: Handle) -> Result<String, TcError> {
+    let stats = with(handle, |instance| instance.tun_stats())?.ok_or_else(|| {
+        TcError::new(
+            TcErrorCode::NotRunning,
+            "no tun fd or packet bridge attached",
+        )
+    })?;
+    serde_json::to_string(&stats).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+}
+
+/// Call `callback` once the current run of the instance ends.
+pub fn on_stop(handle: Handle, callback: StopCallback) -> Result<(), TcError> {
+    let stop_state = with(handle, |instance| {
+        instance.running()?;
+        Ok::<_, TcError>(instance.stop_state.clone())
+    })??;
+    stop_state.on_stop(callback);
+    Ok(())
+}
+
+fn subscribe(handle: Handle) -> Result<Receiver<GlobalCtxEvent>, TcError> {
+    with(handle, |instance| {
+        instance
+            .running()?
+            .subscribe_event()
+            .ok_or_else(|| TcError::internal("no event subscriber"))
+    })?
+}
+
+/// Call `callback` whenever the address, proxied subnets or config change.
+pub fn on_running_info_changed(handle: Handle, callback: extern "C" fn()) -> Result<(), TcError> {
+    let ev = subscribe(handle)?;
+    runtime::spawn(events::recv_loop(ev, move |event| {
+        if let Ok(
+            GlobalCtxEvent::DhcpIpv4Changed(_, _)
+            | GlobalCtxEvent::ProxyCidrsUpdated(_, _)
+            | GlobalCtxEvent::ConfigPatched(_),
+        ) = event
+        {
+            callback();
+        }
+    }))
+}
+
+/// Event queue of the instance, polled without holding the instance lock.
+pub fn event_queue(handle: Handle) -> Result<Arc<EventQueue>, TcError> {
+    with(handle, |instance| instance.events().clone())
+}
+
+/// Deliver every event of the instance to `subscriber` until it stops.
+pub fn on_event(handle: Handle, subscriber: Subscriber) -> Result<(), TcError> {
+    let ev = subscribe(handle)?;
+    let stop_state = with(handle, |instance| instance.stop_state.clone())?;
+    runtime::spawn(async move {
+        let reason = events::forward_until_stopped(handle, ev, &stop_state, |event| {
+            subscriber.deliver(&event)
+        })
+        .await;
+        subscriber.deliver(&Event::new(
+            handle,
+            EventPayload::InstanceStopped { reason },
+        ));
+        subscriber.close();
+    })
+}
@@ -0,0 +1,351 @@
+//! Minecraft Java LAN worlds across the virtual network.
+//!
+//! A game that opens its world to LAN multicasts `[MOTD]motd[/MOTD][AD]port[/AD]`
+//! to `224.0.2.60:4445`, which does not cross the overlay. The relay picks
+//! these announcements up on the local network and sends them on as unicast
+//! UDP from the local virtual address to port 4445 of every peer. There the
+//! game's LAN listener, bound to port 4445, receives them from the virtual
+//! address of the player hosting the world, so the world shows up under
+//! "LAN Games" and joining it connects through the tunnel.
+//!
+//! Only worlds opened on this device are relayed: the peers see them at
+//! this device's virtual address, where a world announced by another
+//! machine on the same network cannot be reached. On the receiving side
+//! [`Echo`] also multicasts every announcement from a peer to the group on
+//! this device, still from the peer's virtual address, for games that only
+//! listen to the group.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use easytier::proto::api::instance::{NodeInfo, Route};
+use tokio::net::UdpSocket;
+
+use crate::error::{TcError, TcErrorCode};
+use crate::instance::lock;
+use crate::net::Ipv4Net;
+use crate::runtime;
+use crate::shutdown::StopState;
+use crate::tun::{Injector, PacketFd};
+use crate::udp;
+
+/// Multicast group Minecraft Java announces LAN worlds to.
+pub const LAN_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 2, 60);
+
+pub const LAN_PORT: u16 = 4445;
+
+/// Longest announcement relayed, the game sends far less.
+const MAX_ANNOUNCEMENT_LEN: usize = 1024;
+
+/// A LAN world as announced by the game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub motd: String,
+    /// Port of the world on the announcing machine.
+    pub port: u16,
+}
+
+fn between<'a>(text: &'a str, open: &str, close: &str) -> Option<&'a str> {
+    let start = text.find(open)? + open.len();
+    let len = text[start..].find(close)?;
+    Some(&text[start..start + len])
+}
+
+impl Announcement {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        Some(Announcement {
+            motd: between(text, "[MOTD]", "[/MOTD]")?.to_string(),
+            port: between(text, "[AD]", "[/AD]")?.trim().parse().ok()?,
+        })
+    }
+}
+
+/// The announcement in the game's wire format.
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[MOTD]{}[/MOTD][AD]{}[/AD]", self.motd, self.port)
+    }
+}
+
+fn enable(fd: &OwnedFd, option: libc::c_int) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// UDP socket bound to `addr` that shares the port with other sockets, such
+/// as the game's own.
+pub fn shared_socket(addr: SocketAddrV4) -> io::Result<std::net::UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    enable(&fd, libc::SO_REUSEADDR)?;
+    enable(&fd, libc::SO_REUSEPORT)?;
+
+    let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    {
+        sin.sin_len = std::mem::size_of::<libc::sockaddr_in>() as u8;
+    }
+    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+    sin.sin_port = addr.port().to_be();
+    sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+    let ret = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            (&sin as *const libc::sockaddr_in).cast(),
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let socket = std::net::UdpSocket::from(fd);
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Whether `addr` is an address of one of this device's interfaces.
+pub fn is_local(addr: Ipv4Addr) -> io::Result<bool> {
+    if addr.is_loopback() {
+        return Ok(true);
+    }
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = false;
+    let mut cur = ifaddrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        if !ifa.ifa_addr.is_null()
+            && unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int == libc::AF_INET
+        {
+            let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+            if Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)) == addr {
+                found = true;
+                break;
+            }
+        }
+        cur = ifa.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(found)
+}
+
+/// Socket receiving the announcements multicast on the local network. It
+/// shares the port with a game on the same device and, bound to the group
+/// address, only receives multicast, so announcements sent to this device
+/// directly still reach the game.
+pub fn listen() -> io::Result<std::net::UdpSocket> {
+    let socket = shared_socket(SocketAddrV4::new(LAN_GROUP, LAN_PORT))?;
+    socket.join_multicast_v4(&LAN_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+/// The local virtual address and the peers of an instance, and the packet
+/// I/O the relays send through.
+#[derive(Default)]
+pub struct Overlay {
+    /// Local virtual address, `0` until the instance has one.
+    local: AtomicU32,
+    peers: Mutex<Vec<Ipv4Addr>>,
+    /// Duplicate of the core end of the nic pair of the attached packet I/O.
+    nic: Mutex<Option<Arc<PacketFd>>>,
+}
+
+impl Overlay {
+    pub fn local_address(&self) -> Option<Ipv4Addr> {
+        match self.local.load(Ordering::Relaxed) {
+            0 => None,
+            addr => Some(Ipv4Addr::from(addr)),
+        }
+    }
+
+    /// Virtual addresses of the other players, sorted.
+    pub fn peers(&self) -> Vec<Ipv4Addr> {
+        lock(&self.peers).clone()
+    }
+
+    /// Whether `addr` is the local or a peer's virtual address.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        self.local_address() == Some(addr) || lock(&self.peers).binary_search(&addr).is_ok()
+    }
+
+    /// Take the local address and the peer addresses from the local node
+    /// and the route table.
+    pub fn update(&self, node: Option<&NodeInfo>, routes: &[Route]) {
+        let local = node
+            .and_then(|n| n.ipv4_addr.parse::<Ipv4Net>().ok())
+            .map_or(Ipv4Addr::UNSPECIFIED, |net| net.addr);
+        self.local.store(u32::from(local), Ordering::Relaxed);
+        let mut peers: Vec<Ipv4Addr> = routes
+            .iter()
+            .filter_map(|route| {
+                let addr = route.ipv4_addr.as_ref()?.to_string();
+                Some(addr.parse::<Ipv4Net>().ok()?.addr)
+            })
+            .filter(|addr| *addr != local && !addr.is_unspecified())
+            .collect();
+        peers.sort();
+        peers.dedup();
+        *lock(&self.peers) = peers;
+    }
+
+    /// Send packets into EasyTier through `nic`, a duplicate of the core
+    /// end of the attached [`nic_pair`](crate::tun::nic_pair).
+    pub fn attach(&self, nic: OwnedFd) -> Result<(), TcError> {
+        let _guard = runtime::get()?.enter();
+        let nic = PacketFd::new(nic).map_err(|e| TcError::from_error(TcErrorCode::Io, &e))?;
+        *lock(&self.nic) = Some(Arc::new(nic));
+        Ok(())
+    }
+
+    /// Send `payload` from port `src_port` of the local virtual address to
+    /// every address in `dsts`, returns the number of datagrams handed to
+    /// EasyTier.
+    pub async fn send_udp(
+        &self,
+        src_port: u16,
+        dsts: impl IntoIterator<Item = SocketAddrV4>,
+        payload: &[u8],
+    ) -> usize {
+        let (Some(local), Some(nic)) = (self.local_address(), lock(&self.nic).clone()) else {
+            return 0;
+        };
+        let mut sent = 0;
+        for dst in dsts {
+            let packet = udp::packet(SocketAddrV4::new(local, src_port), dst, payload);
+            match nic.send(&packet).await {
+                Ok(_) => sent += 1,
+                Err(e) => tracing::warn!("failed to send to {}: {}", dst, e),
+            }
+        }
+        sent
+    }
+}
+
+/// Sends announcements to the peers of one instance.
+pub struct LanRelay {
+    overlay: Arc<Overlay>,
+}
+
+impl LanRelay {
+    pub fn new(overlay: Arc<Overlay>) -> Self {
+        LanRelay { overlay }
+    }
+
+    /// Send `announcement` to every peer, returns the number of peers it
+    /// was handed to EasyTier for.
+    pub async fn forward(&self, announcement: &Announcement) -> usize {
+        let peers = self.overlay.peers();
+        let dsts = peers
+            .into_iter()
+            .map(|peer| SocketAddrV4::new(peer, LAN_PORT));
+        self.overlay
+            .send_udp(LAN_PORT, dsts, announcement.to_string().as_bytes())
+            .await
+    }
+}
+
+/// Multicasts the announcements peers send to this device to the group on
+/// the system side. The copy keeps the peer's virtual address as its source,
+/// which the game joins the world at.
+#[derive(Clone)]
+pub struct Echo {
+    overlay: Arc<Overlay>,
+    system: Injector,
+}
+
+impl Echo {
+    pub fn new(overlay: Arc<Overlay>, system: Injector) -> Self {
+        Echo { overlay, system }
+    }
+
+    /// Queue a multicast copy of `packet`, on its way from EasyTier to the
+    /// system, if it is a peer's announcement for this device. The packet
+    /// itself is delivered as usual. Returns whether a copy was queued.
+    pub fn observe(&self, packet: &[u8]) -> bool {
+        let Some(datagram) = udp::parse(packet) else {
+            return false;
+        };
+        if datagram.dst.port() != LAN_PORT
+            || self.overlay.local_address() != Some(*datagram.dst.ip())
+            || !self.overlay.contains(*datagram.src.ip())
+            || Announcement::parse(datagram.payload).is_none()
+        {
+            return false;
+        }
+        let group = SocketAddrV4::new(LAN_GROUP, LAN_PORT);
+        // 不等待, 系统方向满了就丢掉这一份, 游戏仍会收到单播
+        self.system
+            .try_send(udp::packet(datagram.src, group, datagram.payload))
+            .is_ok()
+    }
+}
+
+/// Relay the worlds announced on the local network until the run ends.
+pub async fn run(relay: LanRelay, stop_state: Arc<StopState>) {
+    let socket = match listen().and_then(UdpSocket::from_std) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tracing::warn!("LAN discovery is unavailable: {}", e);
+            return;
+        }
+    };
+    let mut buf = [0u8; MAX_ANNOUNCEMENT_LEN];
+    loop {
+        let received = tokio::select! {
+            _ = stop_state.wait() => break,
+            received = socket.recv_from(&mut buf) => received,
+        };
+        let (n, from) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!("failed to receive LAN announcement: {}", e);
+                break;
+            }
+        };
+        let Some(announcement) = Announcement::parse(&buf[..n]) else {
+            continue;
+        };
+        // 其他机器的世界无法经本机的虚拟地址访问
+        let local = match from.ip() {
+            IpAddr::V4(ip) => is_local(ip).unwrap_or_else(|e| {
+                tracing::warn!("failed to list local addresses: {}", e);
+                false
+            }),
+            IpAddr::V6(_) => false,
+        };
+        if !local {
+            tracing::trace!("ignored LAN world announced by {}", from);
+            continue;
+        }
+        let sent = relay.forward(&announcement).await;
+        tracing::trace!(
+            "relayed LAN world on port {} from {} to {} peers",
+            announcement.port,
+            from,
+            sent
+        );
+    }
+}
@@ -1,94 +1,166 @@
 use std::ffi::CString;
+use std::os::fd::FromRawFd;
 
-use easytier::{common::{config::{ConfigFileControl, TomlConfigLoader}, global_ctx::GlobalCtxEvent}, launcher::NetworkInstance};
-use tracing_subscriber::EnvFilter;
+pub mod bedrock;
+pub mod bridge;
+pub mod config;
+pub mod dns;
+pub mod error;
+pub mod events;
+pub mod instance;
+pub mod lan;
+pub mod logger;
+pub mod minecraft;
+pub mod net;
+pub mod options;
+pub mod ring;
+pub mod room;
+pub mod room_code;
+pub mod runtime;
+pub mod scaffolding;
+pub mod shutdown;
+pub mod strings;
+pub mod tun;
+pub mod tunnel;
+pub mod udp;
+pub mod validate;
 
-static mut INSTANCE: Option<NetworkInstance> = None;
+use bridge::{OutboundCallback, OutboundPacketCallback};
+use error::{TcError, TcErrorCode};
+use events::{EventCallback, Subscriber};
+use instance::{Handle, Instance, INVALID_HANDLE};
+use room::{RoomOptions, RoomError};
+use runtime::{Completion, CompletionCallback};
+use shutdown::{StopCallback, StopCallbackFn, StopReason};
+
+// 替换默认实例, 旧实例会被停止并释放
+// 接管 utun fd, 由核心在 utun 和 EasyTier 之间转发数据包
+// 句柄无效或实例未运行时不接管, fd 仍归调用方所有
+fn set_instance_tun_fd(handle: Handle, fd: std::ffi::c_int) -> Result<(), TcError> {
+    if fd < 0 {
+        return Err(TcError::invalid_argument("fd is invalid"));
+    }
+    instance::with(handle, |instance| {
+        instance.running()?;
+        let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+        instance.set_tun_fd(fd)
+    })?
+}
+
+fn push_instance_inbound_packets(
+    handle: Handle,
+    buf: *const u8,
+    lens: *const usize,
+    count: usize,
+) -> Result<(), TcError> {
+    if count == 0 {
+        return Ok(());
+    }
+    if buf.is_null() {
+        return Err(TcError::invalid_argument("buf is nullptr"));
+    }
+    if lens.is_null() {
+        return Err(TcError::invalid_argument("lens is nullptr"));
+    }
+    let lens = unsafe { std::slice::from_raw_parts(lens, count) };
+    let total = lens
+        .iter()
+        .try_fold(0usize, |total, len| total.checked_add(*len))
+        .ok_or_else(|| TcError::invalid_argument("lens overflow"))?;
+    let buf = unsafe { std::slice::from_raw_parts(buf, total) };
+    let packets = bridge::split_packets(buf, lens)
+        .ok_or_else(|| TcError::invalid_argument("lens exceed buf"))?;
+    instance::with(handle, |instance| instance.push_inbound_packets(packets))?
+}
 
 /// # Safety
-/// Run the network instance
+/// Run the network instance, cfg_str is either EasyTier TOML or a TerracottaOptions JSON object
 #[no_mangle]
 pub extern "C" fn run_network_instance(
     cfg_str: *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
+) -> TcErrorCode {
     let impl_func = || {
         if cfg_str.is_null() {
-            return Err("cfg_str is nullptr".to_string());
+            return Err(TcError::invalid_argument("cfg_str is nullptr"));
         }
         let cfg_str = unsafe {
             std::ffi::CStr::from_ptr(cfg_str)
                 .to_string_lossy()
                 .into_owned()
         };
-        let cfg = TomlConfigLoader::new_from_str(&cfg_str).map_err(|e| e.to_string())?;
-        let mut new_inst = NetworkInstance::new(cfg, ConfigFileControl::STATIC_CONFIG);
-        new_inst.start().map_err(|e| e.to_string())?;
-        unsafe { INSTANCE = Some(new_inst); }
+        instance::start_default(
+            || TcError::new(TcErrorCode::AlreadyRunning, "a network instance is already running"),
+            || {
+                let mut new_inst = Instance::new(&cfg_str)?;
+                new_inst.start()?;
+                Ok(new_inst)
+            },
+        )?;
         Ok(())
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
-                };
-            }
-            -1
-        }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Stop the network instance
+/// Stop the network instance, waiting at most `timeout_ms` for it to close its sockets.
+/// `reason` receives whether the shutdown was clean or forced.
 #[no_mangle]
-pub extern "C" fn stop_network_instance() -> std::ffi::c_int {
-    unsafe {
-        if let Some(inst) = &INSTANCE {
-            if let Some(stop) = inst.get_stop_notifier() {
-                stop.notify_waiters();
+pub extern "C" fn tc_stop_network_instance(
+    timeout_ms: u32,
+    reason: *mut StopReason,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<StopReason, TcError> {
+        let handle = instance::default_handle()?;
+        let timeout = std::time::Duration::from_millis(timeout_ms.into());
+        // 无论是否仍在运行都释放句柄, 避免运行失败后泄漏
+        let stopped = instance::stop(handle, timeout);
+        instance::free(handle);
+        stopped?.ok_or_else(|| TcError::new(TcErrorCode::NotRunning, "instance is not running"))
+    };
+
+    match impl_func() {
+        Ok(stop_reason) => {
+            if !reason.is_null() {
+                unsafe { *reason = stop_reason; }
             }
+            TcErrorCode::Ok
         }
-        INSTANCE = None;
+        Err(e) => e.report(err_msg),
     }
-    0
 }
 
 /// # Safety
-/// Register stop callback
+/// Stop the network instance, waiting at most the default timeout for it to shut down
+#[no_mangle]
+pub extern "C" fn stop_network_instance() -> TcErrorCode {
+    if let Ok(handle) = instance::default_handle() {
+        instance::free(handle);
+    }
+    TcErrorCode::Ok
+}
+
+/// # Safety
+/// Register stop callback, called exactly once with the reason the current run ends
 #[no_mangle]
 pub extern "C" fn register_stop_callback(
-    callback: Option<extern "C" fn()>,
+    callback: Option<StopCallbackFn>,
+    user_data: *mut std::ffi::c_void,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<(), String> {
-        let callback = callback.ok_or("callback is null".to_string())?;
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let stop = inst.get_stop_notifier().ok_or("no stop notifier".to_string())?;
-        std::thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new();
-            if let Ok(runtime) = runtime {
-                runtime.block_on(stop.notified());
-                callback();
-            } else {
-                tracing::error!("failed to create runtime for stop callback");
-            }
-        });
-        Ok(())
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_stop(instance::default_handle()?, StopCallback::new(callback, user_data))
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
-                };
-            }
-            -1
-        }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
     }
 }
 
@@ -98,318 +170,508 @@ pub extern "C" fn register_stop_callback(
 pub extern "C" fn register_running_info_callback(
     callback: Option<extern "C" fn()>,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<(), String> {
-        let callback = callback.ok_or("callback is null".to_string())?;
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let mut ev = inst
-            .subscribe_event()
-            .ok_or("no event subscriber".to_string())?;
-        std::thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new();
-            if let Ok(runtime) = runtime {
-                runtime.block_on(async move {
-                    loop {
-                        match ev.recv().await {
-                            Ok(event) => match event {
-                                GlobalCtxEvent::DhcpIpv4Changed(_, _)
-                                | GlobalCtxEvent::ProxyCidrsUpdated(_, _)
-                                | GlobalCtxEvent::ConfigPatched(_) => {
-                                    callback();
-                                }
-                                _ => {}
-                            },
-                            Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-                                break;
-                            }
-                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
-                                continue;
-                            }
-                        }
-                    }
-                });
-            } else {
-                tracing::error!("failed to create runtime for running info callback");
-            }
-        });
-        Ok(())
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_running_info_changed(instance::default_handle()?, callback)
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Subscribe to every event of the network instance
+/// `callback` receives each event as versioned JSON with `user_data`, and a null
+/// event once the instance stops.
+#[no_mangle]
+pub extern "C" fn tc_subscribe_events(
+    callback: Option<EventCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_event(instance::default_handle()?, Subscriber::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Take up to `max` queued events of the network instance as a JSON array, waiting
+/// at most `timeout_ms` for the first one. An empty array means the wait timed out.
+#[no_mangle]
+pub extern "C" fn tc_poll_events(
+    max: u32,
+    timeout_ms: u32,
+    events: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        if max == 0 {
+            return Err(TcError::invalid_argument("max must be positive"));
+        }
+        let queue = instance::event_queue(instance::default_handle()?)?;
+        let polled = queue.poll(max as usize, std::time::Duration::from_millis(timeout_ms.into()));
+        serde_json::to_string(&polled).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+    };
+
+    match impl_func() {
+        Ok(events_str) => {
+            if !events.is_null() {
+                if let Ok(cstr) = CString::new(events_str) {
+                    unsafe { *events = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
-use std::ffi::CString;
-
-use easytier::{common::{config::{ConfigFileControl, TomlConfigLoader}, global_ctx::GlobalCtxEvent}, launcher::NetworkInstance};
-use tracing_subscriber::EnvFilter;
+/// # Safety
+/// Get the number of events dropped because the event queue was not polled in time
+#[no_mangle]
+pub extern "C" fn tc_events_dropped(
+    dropped: *mut u64,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        if dropped.is_null() {
+            return Err(TcError::invalid_argument("dropped is nullptr"));
+        }
+        let count = instance::event_queue(instance::default_handle()?)?.dropped();
+        unsafe { *dropped = count; }
+        Ok(())
+    };
 
-static mut INSTANCE: Option<NetworkInstance> = None;
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
 
 /// # Safety
-/// Run the network instance
+/// Create a Terracotta room and start the host network instance.
+/// `options` is an optional JSON object, see `room::RoomOptions`.
+/// On success `result` receives a JSON `room::RoomDescriptor`.
 #[no_mangle]
-pub extern "C" fn run_network_instance(
-    cfg_str: *const std::ffi::c_char,
+pub extern "C" fn create_room(
+    room_name: *const std::ffi::c_char,
+    options: *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || {
-        if cfg_str.is_null() {
-            return Err("cfg_str is nullptr".to_string());
+    result: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, RoomError> {
+        if room_name.is_null() {
+            return Err(RoomError::InvalidArgument("room_name is nullptr".to_string()));
         }
-        let cfg_str = unsafe {
-            std::ffi::CStr::from_ptr(cfg_str)
+        let room_name = unsafe {
+            std::ffi::CStr::from_ptr(room_name)
                 .to_string_lossy()
                 .into_owned()
         };
-        let cfg = TomlConfigLoader::new_from_str(&cfg_str).map_err(|e| e.to_string())?;
-        let mut new_inst = NetworkInstance::new(cfg, ConfigFileControl::STATIC_CONFIG);
-        new_inst.start().map_err(|e| e.to_string())?;
-        unsafe { INSTANCE = Some(new_inst); }
-        Ok(())
+        let options = if options.is_null() {
+            RoomOptions::default()
+        } else {
+            let options = unsafe { std::ffi::CStr::from_ptr(options).to_string_lossy() };
+            RoomOptions::from_json(&options)?
+        };
+
+        // 生成房间代码, 由其派生网络身份并启动房主实例
+        let mut descriptor = None;
+        instance::start_default(
+            || RoomError::AlreadyRunning,
+            || {
+                let (new_inst, created) = room::create(&room_name, &options)?;
+                descriptor = Some(created);
+                Ok(new_inst)
+            },
+        )?;
+        let descriptor = descriptor.expect("a started room has a descriptor");
+
+        serde_json::to_string(&descriptor).map_err(|e| RoomError::Config(e.to_string()))
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(descriptor) => {
+            if !result.is_null() {
+                if let Ok(cstr) = CString::new(descriptor) {
+                    unsafe { *result = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => TcError::from(e).report(err_msg),
     }
 }
 
 /// # Safety
-/// Stop the network instance
+/// Join a Terracotta room and start the guest network instance.
+/// `options` is an optional JSON object, see `room::RoomOptions`.
 #[no_mangle]
-pub extern "C" fn stop_network_instance() -> std::ffi::c_int {
-    unsafe {
-        if let Some(inst) = &INSTANCE {
-            if let Some(stop) = inst.get_stop_notifier() {
-                stop.notify_waiters();
-            }
+pub extern "C" fn join_room(
+    room_code: *const std::ffi::c_char,
+    options: *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), RoomError> {
+        if room_code.is_null() {
+            return Err(RoomError::InvalidArgument("room_code is nullptr".to_string()));
         }
-        INSTANCE = None;
+        let room_code = unsafe {
+            std::ffi::CStr::from_ptr(room_code)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let options = if options.is_null() {
+            RoomOptions::default()
+        } else {
+            let options = unsafe { std::ffi::CStr::from_ptr(options).to_string_lossy() };
+            RoomOptions::from_json(&options)?
+        };
+
+        instance::start_default(|| RoomError::AlreadyRunning, || room::join(&room_code, &options))?;
+        Ok(())
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => TcError::from(e).report(err_msg),
     }
-    0
 }
 
 /// # Safety
-/// Register stop callback
+/// Build an EasyTier TOML config from a JSON room description,
+/// see `config::RoomConfigRequest`
 #[no_mangle]
-pub extern "C" fn register_stop_callback(
-    callback: Option<extern "C" fn()>,
+pub extern "C" fn build_room_config(
+    room_json: *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<(), String> {
-        let callback = callback.ok_or("callback is null".to_string())?;
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let stop = inst.get_stop_notifier().ok_or("no stop notifier".to_string())?;
-        std::thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new();
-            if let Ok(runtime) = runtime {
-                runtime.block_on(stop.notified());
-                callback();
-            } else {
-                tracing::error!("failed to create runtime for stop callback");
-            }
-        });
-        Ok(())
+    result: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        if room_json.is_null() {
+            return Err(TcError::invalid_argument("room_json is nullptr"));
+        }
+        let room_json = unsafe {
+            std::ffi::CStr::from_ptr(room_json)
+                .to_string_lossy()
+                .into_owned()
+        };
+        config::RoomConfig::from_json(&room_json)
+            .and_then(|room| room.to_toml())
+            .map_err(TcError::config)
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(toml) => {
+            if !result.is_null() {
+                if let Ok(cstr) = CString::new(toml) {
+                    unsafe { *result = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Register running info callback
+/// Validate a config before starting, accepts the same input as run_network_instance
 #[no_mangle]
-pub extern "C" fn register_running_info_callback(
-    callback: Option<extern "C" fn()>,
+pub extern "C" fn validate_config(
+    cfg_str: *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<(), String> {
-        let callback = callback.ok_or("callback is null".to_string())?;
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let mut ev = inst
-            .subscribe_event()
-            .ok_or("no event subscriber".to_string())?;
-        std::thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new();
-            if let Ok(runtime) = runtime {
-                runtime.block_on(async move {
-                    loop {
-                        match ev.recv().await {
-                            Ok(event) => match event {
-                                GlobalCtxEvent::DhcpIpv4Changed(_, _)
-                                | GlobalCtxEvent::ProxyCidrsUpdated(_, _)
-                                | GlobalCtxEvent::ConfigPatched(_) => {
-                                    callback();
-                                }
-                                _ => {}
-                            },
-                            Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-                                break;
-                            }
-                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
-                                continue;
-                            }
-                        }
-                    }
-                });
-            } else {
-                tracing::error!("failed to create runtime for running info callback");
-            }
-        });
-        Ok(())
+    result: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        if cfg_str.is_null() {
+            return Err(TcError::invalid_argument("cfg_str is nullptr"));
+        }
+        let cfg_str = unsafe {
+            std::ffi::CStr::from_ptr(cfg_str)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        // 返回 {"valid": bool, "diagnostics": [{"severity", "path", "line", "column", "message"}]}
+        let report = validate::validate(&cfg_str);
+        serde_json::to_string(&report).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(report) => {
+            if !result.is_null() {
+                if let Ok(cstr) = CString::new(report) {
+                    unsafe { *result = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Create a Terracotta room
+/// Check a room code and suggest corrections for likely typos
 #[no_mangle]
-pub extern "C" fn create_room(
-    room_name: *const std::ffi::c_char,
+pub extern "C" fn diagnose_room_code(
+    room_code: *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
     result: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<String, String> {
-        if room_name.is_null() {
-            return Err("room_name is nullptr".to_string());
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        if room_code.is_null() {
+            return Err(TcError::invalid_argument("room_code is nullptr"));
         }
-        let room_name = unsafe {
-            std::ffi::CStr::from_ptr(room_name)
+        let room_code = unsafe {
+            std::ffi::CStr::from_ptr(room_code)
                 .to_string_lossy()
                 .into_owned()
         };
-        
-        // 生成与原版陶瓦联机兼容的房间代码
-        // 生成一个随机种子并编码为房间代码
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        let seed: u64 = rng.gen();
-        
-        // 使用base32编码生成房间代码
-        let encoded = base32_encode(seed);
-        
-        // 格式为 U/XXXX-XXXX-XXXX-XXXX
-        let code = format!("U/{:.4}-{:.4}-{:.4}-{:.4}", 
-            &encoded[0..4],
-            &encoded[4..8],
-            &encoded[8..12],
-            &encoded[12..16]
-        );
-        
-        Ok(code)
-    };
-
-    match impl_func() {
-        Ok(code) => {
+
+        // 返回 {"status": "valid|malformed|bad_checksum", "suggestions": [...]}
+        let diagnosis = room_code::diagnose(&room_code);
+        serde_json::to_string(&diagnosis).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+    };
+
+    match impl_func() {
+        Ok(diagnosis) => {
             if !result.is_null() {
-                if let Ok(cstr) = CString::new(code) {
-                    unsafe { *result = cstr.into_raw(); }
+                if let Ok(cstr) = CString::new(diagnosis) {
+                    unsafe { *result = strings::into_raw(cstr); }
                 };
             }
-            0
+            TcErrorCode::Ok
         }
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+// timeout_ms 为 0 时使用默认超时
+fn probe_target(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+) -> Result<(String, u16, std::time::Duration), TcError> {
+    if addr.is_null() {
+        return Err(TcError::invalid_argument("addr is nullptr"));
+    }
+    let addr = unsafe { std::ffi::CStr::from_ptr(addr).to_string_lossy().into_owned() };
+    if addr.is_empty() {
+        return Err(TcError::invalid_argument("addr is empty"));
+    }
+    let port = if port == 0 { minecraft::DEFAULT_PORT } else { port };
+    let timeout = match timeout_ms {
+        0 => minecraft::DEFAULT_TIMEOUT,
+        ms => std::time::Duration::from_millis(ms.into()),
+    };
+    Ok((addr, port, timeout))
+}
+
+/// # Safety
+/// Probe a Minecraft Java server with a Server List Ping, blocks until it answers or times out
+#[no_mangle]
+pub extern "C" fn tc_probe_minecraft(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+    err_msg: *mut *const std::ffi::c_char,
+    result: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        let (addr, port, timeout) = probe_target(addr, port, timeout_ms)?;
+        runtime::block_on(minecraft::probe_json(addr, port, timeout))?
+    };
+
+    match impl_func() {
+        Ok(status) => {
+            if !result.is_null() {
+                if let Ok(cstr) = CString::new(status) {
+                    unsafe { *result = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Join a Terracotta room
+/// Probe a Minecraft Java server without blocking
+/// `callback` receives the JSON result with `user_data` once the probe finishes.
 #[no_mangle]
-pub extern "C" fn join_room(
-    room_code: *const std::ffi::c_char,
+pub extern "C" fn tc_probe_minecraft_async(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<(), String> {
-        if room_code.is_null() {
-            return Err("room_code is nullptr".to_string());
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        let (addr, port, timeout) = probe_target(addr, port, timeout_ms)?;
+        runtime::spawn_completion(minecraft::probe_json(addr, port, timeout), Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+// port 为 0 时使用默认端口, timeout_ms 为 0 时使用默认超时
+fn scaffolding_target(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+) -> Result<(std::net::SocketAddr, std::time::Duration), TcError> {
+    if addr.is_null() {
+        return Err(TcError::invalid_argument("addr is nullptr"));
+    }
+    let addr = unsafe { std::ffi::CStr::from_ptr(addr).to_string_lossy().into_owned() };
+    let ip = addr
+        .parse::<std::net::IpAddr>()
+        .map_err(|e| TcError::invalid_argument(format!("invalid addr '{}': {}", addr, e)))?;
+    let port = if port == 0 { scaffolding::DEFAULT_PORT } else { port };
+    let timeout = match timeout_ms {
+        0 => scaffolding::DEFAULT_TIMEOUT,
+        ms => std::time::Duration::from_millis(ms.into()),
+    };
+    Ok((std::net::SocketAddr::new(ip, port), timeout))
+}
+
+/// # Safety
+/// Ask a room host's scaffolding server for its game port and players, blocks until it answers
+#[no_mangle]
+pub extern "C" fn tc_scaffolding_query(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+    err_msg: *mut *const std::ffi::c_char,
+    result: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        let (addr, timeout) = scaffolding_target(addr, port, timeout_ms)?;
+        runtime::block_on(scaffolding::query_json(addr, timeout))?
+    };
+
+    match impl_func() {
+        Ok(info) => {
+            if !result.is_null() {
+                if let Ok(cstr) = CString::new(info) {
+                    unsafe { *result = strings::into_raw(cstr); }
+                };
+            }
+            TcErrorCode::Ok
         }
-        let room_code = unsafe {
-            std::ffi::CStr::from_ptr(room_code)
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Ask a room host's scaffolding server for its game port and players without blocking
+/// `callback` receives the JSON result with `user_data` once the query finishes.
+#[no_mangle]
+pub extern "C" fn tc_scaffolding_query_async(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    timeout_ms: u32,
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        let (addr, timeout) = scaffolding_target(addr, port, timeout_ms)?;
+        runtime::spawn_completion(scaffolding::query_json(addr, timeout), Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Send a player heartbeat to a room host's scaffolding server
+#[no_mangle]
+pub extern "C" fn tc_scaffolding_player_ping(
+    addr: *const std::ffi::c_char,
+    port: u16,
+    profile: *const std::ffi::c_char,
+    timeout_ms: u32,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let (addr, timeout) = scaffolding_target(addr, port, timeout_ms)?;
+        if profile.is_null() {
+            return Err(TcError::invalid_argument("profile is nullptr"));
+        }
+        let profile = unsafe {
+            std::ffi::CStr::from_ptr(profile)
                 .to_string_lossy()
                 .into_owned()
         };
-        
-        // 验证房间代码格式 - 必须是 U/XXXX-XXXX-XXXX-XXXX 格式
-        if !room_code.starts_with("U/") {
-            return Err("Invalid room code format. Must start with 'U/'".to_string());
-        }
-        
-        // 解析房间代码，提取各部分并组合成种子
-        let parts: Vec<&str> = room_code.split('-').collect();
-        if parts.len() != 4 {
-            return Err("Invalid room code format".to_string());
-        }
-        
-        // 重新组合各个部分
-        let combined = format!("{}{}{}{}", 
-            parts[0].strip_prefix("U/").unwrap_or(""),
-            parts[1],
-            parts[2],
-            parts[3]
-        );
-        
-        // 验证编码长度
-        if combined.len() < 16 {
-            return Err("Invalid room code length".to_string());
-        }
-        
-        Ok(())
+        let profile: scaffolding::PlayerProfile = serde_json::from_str(&profile)
+            .map_err(|e| TcError::invalid_argument(format!("invalid profile: {}", e)))?;
+        runtime::block_on(scaffolding::send_heartbeat(addr, &profile, timeout))?
+            .map_err(TcError::from)
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Set the Minecraft server port the scaffolding server of the hosting instance reports,
+/// 0 while there is no game to join
+#[no_mangle]
+pub extern "C" fn tc_scaffolding_set_server_port(
+    port: u16,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        instance::set_scaffolding_server_port(instance::default_handle()?, (port != 0).then_some(port))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the players of the room hosted by the network instance as JSON
+#[no_mangle]
+pub extern "C" fn tc_scaffolding_get_players(
+    players: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::scaffolding_players(instance::default_handle()?)
+    };
+
+    match impl_func() {
+        Ok(players_str) => {
+            if !players.is_null() {
+                if let Ok(cstr) = CString::new(players_str) {
+                    unsafe { *players = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
@@ -419,10 +681,9 @@ pub extern "C" fn join_room(
 pub extern "C" fn get_latest_error_msg(
     msg: *mut *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<Option<String>, String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        Ok(inst.get_latest_error_msg())
+) -> TcErrorCode {
+    let impl_func = || -> Result<Option<String>, TcError> {
+        instance::latest_error_msg(instance::default_handle()?)
     };
 
     match impl_func() {
@@ -430,22 +691,15 @@ pub extern "C" fn get_latest_error_msg(
             if !msg.is_null() {
                 if let Some(message) = opt_msg {
                     if let Ok(cstr) = CString::new(message) {
-                        unsafe { *msg = cstr.into_raw(); }
+                        unsafe { *msg = strings::into_raw(cstr); }
                     };
                 } else {
                     unsafe { *msg = std::ptr::null(); }
                 }
             }
-            0
-        }
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
-                };
-            }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
@@ -455,97 +709,189 @@ pub extern "C" fn get_latest_error_msg(
 pub extern "C" fn get_running_info(
     info: *mut *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<String, String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let api_service = inst.get_api_service().ok_or("no API service".to_string())?;
-        
-        // 获取节点信息和网络信息
-        use easytier::proto::api::instance::{ShowNodeInfoRequest, ListPeerRequest, ListRouteRequest};
-        use easytier::proto::rpc_types::controller::BaseController;
-        
-        let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
-        let peer_service = api_service.get_peer_manage_service();
-        
-        // 获取路由信息
-        let routes = runtime.block_on(
-            peer_service.list_route(BaseController::default(), ListRouteRequest::default())
-        ).map_err(|e| e.to_string())?;
-        
-        // 获取节点信息
-        let node_info = runtime.block_on(
-            peer_service.show_node_info(BaseController::default(), ShowNodeInfoRequest::default())
-        ).map_err(|e| e.to_string())?;
-        
-        // 获取对等节点信息
-        let peers = runtime.block_on(
-            peer_service.list_peer(BaseController::default(), ListPeerRequest::default())
-        ).map_err(|e| e.to_string())?;
-        
-        // 返回JSON格式的运行信息
-        use serde_json;
-        let result = serde_json::json!({
-            "routes": routes.routes,
-            "node_info": node_info.node_info,
-            "peers": peers.peers,
-            "network_info": {
-                "node_id": node_info.node_info.as_ref().map(|n| n.node_id.clone()).unwrap_or_default(),
-                "ipv4": node_info.node_info.as_ref().and_then(|n| n.ipv4.as_ref()).map(|ip| ip.to_string()),
-                "ipv6": node_info.node_info.as_ref().and_then(|n| n.ipv6.as_ref()).map(|ip| ip.to_string()),
-                "version": node_info.node_info.as_ref().map(|n| n.version.clone()).unwrap_or_default()
-            }
-        });
-        
-        Ok(result.to_string())
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::running_info(instance::default_handle()?)
     };
 
     match impl_func() {
         Ok(info_str) => {
             if !info.is_null() {
                 if let Ok(cstr) = CString::new(info_str) {
-                    unsafe { *info = cstr.into_raw(); }
+                    unsafe { *info = strings::into_raw(cstr); }
                 };
             }
-            0
+            TcErrorCode::Ok
         }
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the network settings to apply to the tunnel interface
+#[no_mangle]
+pub extern "C" fn get_tunnel_settings(
+    settings: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::tunnel_settings(instance::default_handle()?)
+    };
+
+    match impl_func() {
+        Ok(settings_str) => {
+            if !settings.is_null() {
+                if let Ok(cstr) = CString::new(settings_str) {
+                    unsafe { *settings = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get running info from the network instance without blocking
+/// `callback` receives the JSON result with `user_data` once the query finishes.
+#[no_mangle]
+pub extern "C" fn get_running_info_async(
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        runtime::spawn_completion(async { instance::running_info_async(instance::default_handle()?).await }, Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the network settings to apply to the tunnel interface without blocking
+/// `callback` receives the JSON result with `user_data` once the query finishes.
+#[no_mangle]
+pub extern "C" fn get_tunnel_settings_async(
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        runtime::spawn_completion(async { instance::tunnel_settings_async(instance::default_handle()?).await }, Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Set the TUN file descriptor
+/// Set the TUN file descriptor, the core takes ownership of `fd` and closes it
 #[no_mangle]
 pub extern "C" fn set_tun_fd(
     fd: std::ffi::c_int,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    // 在iOS上，我们使用不同的机制来设置TUN FD
-    let impl_func = || -> Result<(), String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        
-        // 在实际实现中，我们需要通过API服务更新TUN配置
-        // 但目前EasyTier的iOS实现可能不需要直接设置TUN FD
-        // 因为网络扩展会处理TUN接口
-        Ok(())
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        set_instance_tun_fd(instance::default_handle()?, fd)
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Pass packets read from the system tunnel to EasyTier. `buf` holds `count` bare IP
+/// packets back to back, `lens` their lengths. Requires an outbound packet callback
+#[no_mangle]
+pub extern "C" fn tc_push_inbound_packets(
+    buf: *const u8,
+    lens: *const usize,
+    count: usize,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        push_instance_inbound_packets(instance::default_handle()?, buf, lens, count)
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Set the callback receiving EasyTier's packets in batches, attaching the packet bridge
+/// instead of a TUN file descriptor. Calling it again replaces the callback
+#[no_mangle]
+pub extern "C" fn tc_set_outbound_packet_callback(
+    callback: Option<OutboundPacketCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        let callback = OutboundCallback::new(callback, user_data);
+        instance::with(instance::default_handle()?, |instance| {
+            instance.set_outbound_packet_callback(callback)
+        })?
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get packet and ring counters of the TUN fd or packet bridge as JSON
+#[no_mangle]
+pub extern "C" fn get_tun_stats(
+    stats: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::tun_stats(instance::default_handle()?)
+    };
+
+    match impl_func() {
+        Ok(stats_str) => {
+            if !stats.is_null() {
+                if let Ok(cstr) = CString::new(stats_str) {
+                    unsafe { *stats = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Free a string returned through any `const char **` out-parameter of this library.
+/// `s` must not be used afterwards, passing null is allowed.
+#[no_mangle]
+pub extern "C" fn tc_string_free(s: *const std::ffi::c_char) {
+    unsafe { strings::free(s); }
+}
+
+/// # Safety
+/// Get the last error reported on the calling thread as JSON
+/// `{"code", "name", "message", "causes": [...]}`, or null if there is none.
+/// The caller owns the string and frees it with `tc_string_free`.
+#[no_mangle]
+pub extern "C" fn tc_last_error() -> *const std::ffi::c_char {
+    match error::last_error() {
+        Some(e) => CString::new(e.to_json()).map(strings::into_raw).unwrap_or(std::ptr::null()),
+        None => std::ptr::null(),
     }
 }
 
@@ -555,9 +901,7 @@ pub extern "C" fn set_tun_fd(
 pub extern "C" fn init_rust_logger(level: *const std::ffi::c_char) {
     if level.is_null() {
         // 默认使用info级别
-        let _ = tracing_subscriber::fmt()
-            .with_env_filter(EnvFilter::from_default_env())
-            .try_init();
+        logger::init(None);
         return;
     }
 
@@ -567,39 +911,268 @@ pub extern "C" fn init_rust_logger(level: *const std::ffi::c_char) {
             .into_owned()
     };
 
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(&format!("easytier=info,easytier_proto=info,terracotta_ios={}", level_str)));
+    logger::init(Some(&level_str));
+}
+
+/// # Safety
+/// Create a network instance from EasyTier TOML or a TerracottaOptions JSON object
+/// without starting it. `handle` receives the new instance handle.
+#[no_mangle]
+pub extern "C" fn tc_instance_new(
+    cfg_str: *const std::ffi::c_char,
+    handle: *mut Handle,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<Handle, TcError> {
+        if cfg_str.is_null() {
+            return Err(TcError::invalid_argument("cfg_str is nullptr"));
+        }
+        if handle.is_null() {
+            return Err(TcError::invalid_argument("handle is nullptr"));
+        }
+        let cfg_str = unsafe {
+            std::ffi::CStr::from_ptr(cfg_str)
+                .to_string_lossy()
+                .into_owned()
+        };
+        Ok(instance::insert(Instance::new(&cfg_str)?))
+    };
+
+    match impl_func() {
+        Ok(new_handle) => {
+            unsafe { *handle = new_handle; }
+            TcErrorCode::Ok
+        }
+        Err(e) => {
+            if !handle.is_null() {
+                unsafe { *handle = INVALID_HANDLE; }
+            }
+            e.report(err_msg)
+        }
+    }
+}
+
+/// # Safety
+/// Start the network instance
+#[no_mangle]
+pub extern "C" fn tc_instance_start(
+    handle: Handle,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        instance::start(handle)
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Stop the network instance, the handle stays valid and can be started again
+#[no_mangle]
+pub extern "C" fn tc_instance_stop(
+    handle: Handle,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        instance::stop(handle, shutdown::DEFAULT_SHUTDOWN_TIMEOUT)?;
+        Ok(())
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Stop the network instance, waiting at most `timeout_ms` for it to close its sockets.
+/// `reason` receives whether the shutdown was clean or forced.
+#[no_mangle]
+pub extern "C" fn tc_instance_shutdown(
+    handle: Handle,
+    timeout_ms: u32,
+    reason: *mut StopReason,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<StopReason, TcError> {
+        let timeout = std::time::Duration::from_millis(timeout_ms.into());
+        let stop_reason = instance::stop(handle, timeout)?
+            .ok_or_else(|| TcError::new(TcErrorCode::NotRunning, "instance is not running"))?;
+        Ok(stop_reason)
+    };
+
+    match impl_func() {
+        Ok(stop_reason) => {
+            if !reason.is_null() {
+                unsafe { *reason = stop_reason; }
+            }
+            TcErrorCode::Ok
+        }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Stop the network instance if running and release the handle
+#[no_mangle]
+pub extern "C" fn tc_instance_free(handle: Handle) -> TcErrorCode {
+    if instance::free(handle) { TcErrorCode::Ok } else { TcErrorCode::InvalidHandle }
+}
+
+/// # Safety
+/// Check whether the network instance is running
+#[no_mangle]
+pub extern "C" fn tc_instance_is_running(
+    handle: Handle,
+    running: *mut bool,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        if running.is_null() {
+            return Err(TcError::invalid_argument("running is nullptr"));
+        }
+        let is_running = instance::with(handle, |instance| instance.is_running())?;
+        unsafe { *running = is_running; }
+        Ok(())
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Register stop callback of the network instance, called exactly once with the
+/// reason the current run ends
+#[no_mangle]
+pub extern "C" fn tc_instance_register_stop_callback(
+    handle: Handle,
+    callback: Option<StopCallbackFn>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_stop(handle, StopCallback::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Register running info callback of the network instance
+#[no_mangle]
+pub extern "C" fn tc_instance_register_running_info_callback(
+    handle: Handle,
+    callback: Option<extern "C" fn()>,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_running_info_changed(handle, callback)
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Subscribe to every event of the network instance
+/// `callback` receives each event as versioned JSON with `user_data`, and a null
+/// event once the instance stops.
+#[no_mangle]
+pub extern "C" fn tc_instance_subscribe_events(
+    handle: Handle,
+    callback: Option<EventCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        instance::on_event(handle, Subscriber::new(callback, user_data))
+    };
 
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .try_init();
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
 }
 
-// Base32编码辅助函数
-fn base32_encode(num: u64) -> String {
-    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
-    let mut result = String::new();
-    let mut n = num;
-    
-    // 将数字转换为base32字符串
-    for _ in 0..16 {
-        result.push(ALPHABET[(n & 0x1F) as usize] as char);
-        n >>= 5;
+/// # Safety
+/// Take up to `max` queued events of the network instance as a JSON array, waiting
+/// at most `timeout_ms` for the first one. An empty array means the wait timed out.
+#[no_mangle]
+pub extern "C" fn tc_instance_poll_events(
+    handle: Handle,
+    max: u32,
+    timeout_ms: u32,
+    events: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        if max == 0 {
+            return Err(TcError::invalid_argument("max must be positive"));
+        }
+        let queue = instance::event_queue(handle)?;
+        let polled = queue.poll(max as usize, std::time::Duration::from_millis(timeout_ms.into()));
+        serde_json::to_string(&polled).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+    };
+
+    match impl_func() {
+        Ok(events_str) => {
+            if !events.is_null() {
+                if let Ok(cstr) = CString::new(events_str) {
+                    unsafe { *events = strings::into_raw(cstr); }
+                };
+            }
+            TcErrorCode::Ok
+        }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the number of events dropped because the event queue was not polled in time
+#[no_mangle]
+pub extern "C" fn tc_instance_events_dropped(
+    handle: Handle,
+    dropped: *mut u64,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        if dropped.is_null() {
+            return Err(TcError::invalid_argument("dropped is nullptr"));
+        }
+        let count = instance::event_queue(handle)?.dropped();
+        unsafe { *dropped = count; }
+        Ok(())
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
     }
-    
-    result.chars().rev().collect() // 反转以获得正确的顺序
 }
 
 /// # Safety
 /// Get the latest error message from the network instance
 #[no_mangle]
-pub extern "C" fn get_latest_error_msg(
+pub extern "C" fn tc_instance_get_latest_error_msg(
+    handle: Handle,
     msg: *mut *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<Option<String>, String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        Ok(inst.get_latest_error_msg())
+) -> TcErrorCode {
+    let impl_func = || -> Result<Option<String>, TcError> {
+        instance::latest_error_msg(handle)
     };
 
     match impl_func() {
@@ -607,152 +1180,234 @@ pub extern "C" fn get_latest_error_msg(
             if !msg.is_null() {
                 if let Some(message) = opt_msg {
                     if let Ok(cstr) = CString::new(message) {
-                        unsafe { *msg = cstr.into_raw(); }
+                        unsafe { *msg = strings::into_raw(cstr); }
                     };
                 } else {
                     unsafe { *msg = std::ptr::null(); }
                 }
             }
-            0
-        }
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
-                };
-            }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
 /// Get running info from the network instance
 #[no_mangle]
-pub extern "C" fn get_running_info(
+pub extern "C" fn tc_instance_get_running_info(
+    handle: Handle,
     info: *mut *const std::ffi::c_char,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    let impl_func = || -> Result<String, String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        let api_service = inst.get_api_service().ok_or("no API service".to_string())?;
-        
-        // 获取节点信息
-        use easytier::proto::api::instance::{ShowNodeInfoRequest, ListRouteRequest};
-        use easytier::proto::rpc_types::controller::BaseController;
-        
-        let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
-        let peer_service = api_service.get_peer_manage_service();
-        
-        let routes = runtime.block_on(
-            peer_service.list_route(BaseController::default(), ListRouteRequest::default())
-        ).map_err(|e| e.to_string())?;
-        
-        let node_info = runtime.block_on(
-            peer_service.show_node_info(BaseController::default(), ShowNodeInfoRequest::default())
-        ).map_err(|e| e.to_string())?;
-        
-        // 返回JSON格式的运行信息
-        use serde_json;
-        let result = serde_json::json!({
-            "routes": routes.routes,
-            "node_info": node_info.node_info
-        });
-        
-        Ok(result.to_string())
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::running_info(handle)
     };
 
     match impl_func() {
         Ok(info_str) => {
             if !info.is_null() {
                 if let Ok(cstr) = CString::new(info_str) {
-                    unsafe { *info = cstr.into_raw(); }
+                    unsafe { *info = strings::into_raw(cstr); }
                 };
             }
-            0
+            TcErrorCode::Ok
         }
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the network settings to apply to the tunnel interface of the network instance
+#[no_mangle]
+pub extern "C" fn tc_instance_get_tunnel_settings(
+    handle: Handle,
+    settings: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::tunnel_settings(handle)
+    };
+
+    match impl_func() {
+        Ok(settings_str) => {
+            if !settings.is_null() {
+                if let Ok(cstr) = CString::new(settings_str) {
+                    unsafe { *settings = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Set the TUN file descriptor
+/// Get running info from the network instance without blocking
+/// `callback` receives the JSON result with `user_data` once the query finishes.
 #[no_mangle]
-pub extern "C" fn set_tun_fd(
+pub extern "C" fn tc_instance_get_running_info_async(
+    handle: Handle,
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        runtime::spawn_completion(instance::running_info_async(handle), Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get the network settings to apply to the tunnel interface of the network instance without blocking
+/// `callback` receives the JSON result with `user_data` once the query finishes.
+#[no_mangle]
+pub extern "C" fn tc_instance_get_tunnel_settings_async(
+    handle: Handle,
+    callback: Option<CompletionCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        runtime::spawn_completion(instance::tunnel_settings_async(handle), Completion::new(callback, user_data))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Set the TUN file descriptor of the network instance, the core takes ownership of `fd`
+#[no_mangle]
+pub extern "C" fn tc_instance_set_tun_fd(
+    handle: Handle,
     fd: std::ffi::c_int,
     err_msg: *mut *const std::ffi::c_char,
-) -> std::ffi::c_int {
-    // 在iOS上，我们使用不同的机制来设置TUN FD
-    // 在EasyTier中，TUN接口的设置可能通过不同的方式进行
-    let impl_func = || -> Result<(), String> {
-        let inst = unsafe { INSTANCE.as_ref().ok_or("no running instance".to_string())? };
-        
-        // 获取API服务来设置TUN FD
-        let api_service = inst.get_api_service().ok_or("no API service".to_string())?;
-        let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
-        
-        // 使用配置服务来更新TUN配置
-        use easytier::proto::api::config::{ConfigPatchAction, InstanceConfigPatch, PatchConfigRequest};
-        use easytier::proto::api::instance::BaseController;
-        
-        let config_service = api_service.get_config_service();
-        let patch_request = PatchConfigRequest {
-            patch: Some(InstanceConfigPatch {
-                tun_fd: fd as i32,  // 直接设置TUN FD
-                ..Default::default()
-            }),
-            ..Default::default()
-        };
-        
-        runtime.block_on(
-            config_service.patch_config(BaseController::default(), patch_request)
-        ).map_err(|e| e.to_string())?;
-        
-        Ok(())
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        set_instance_tun_fd(handle, fd)
     };
 
     match impl_func() {
-        Ok(_) => 0,
-        Err(e) => {
-            if !err_msg.is_null() {
-                if let Ok(cstr) = CString::new(e) {
-                    unsafe { *err_msg = cstr.into_raw(); }
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Pass packets read from the system tunnel to EasyTier through the packet bridge of the
+/// network instance
+#[no_mangle]
+pub extern "C" fn tc_instance_push_inbound_packets(
+    handle: Handle,
+    buf: *const u8,
+    lens: *const usize,
+    count: usize,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        push_instance_inbound_packets(handle, buf, lens, count)
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Set the callback receiving the packets of the network instance in batches
+#[no_mangle]
+pub extern "C" fn tc_instance_set_outbound_packet_callback(
+    handle: Handle,
+    callback: Option<OutboundPacketCallback>,
+    user_data: *mut std::ffi::c_void,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        let callback = callback.ok_or_else(|| TcError::invalid_argument("callback is null"))?;
+        let callback = OutboundCallback::new(callback, user_data);
+        instance::with(handle, |instance| instance.set_outbound_packet_callback(callback))?
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
+    }
+}
+
+/// # Safety
+/// Get packet and ring counters of the network instance as JSON
+#[no_mangle]
+pub extern "C" fn tc_instance_get_tun_stats(
+    handle: Handle,
+    stats: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::tun_stats(handle)
+    };
+
+    match impl_func() {
+        Ok(stats_str) => {
+            if !stats.is_null() {
+                if let Ok(cstr) = CString::new(stats_str) {
+                    unsafe { *stats = strings::into_raw(cstr); }
                 };
             }
-            -1
+            TcErrorCode::Ok
         }
+        Err(e) => e.report(err_msg),
     }
 }
 
 /// # Safety
-/// Initialize Rust logger with the specified level
+/// Set the Minecraft server port the scaffolding server of the network instance reports
 #[no_mangle]
-pub extern "C" fn init_rust_logger(level: *const std::ffi::c_char) {
-    if level.is_null() {
-        // 默认使用info级别
-        let _ = tracing_subscriber::fmt()
-            .with_env_filter(EnvFilter::from_default_env())
-            .try_init();
-        return;
+pub extern "C" fn tc_instance_scaffolding_set_server_port(
+    handle: Handle,
+    port: u16,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<(), TcError> {
+        instance::set_scaffolding_server_port(handle, (port != 0).then_some(port))
+    };
+
+    match impl_func() {
+        Ok(_) => TcErrorCode::Ok,
+        Err(e) => e.report(err_msg),
     }
+}
 
-    let level_str = unsafe {
-        std::ffi::CStr::from_ptr(level)
-            .to_string_lossy()
-            .into_owned()
+/// # Safety
+/// Get the players of the room hosted by the network instance as JSON
+#[no_mangle]
+pub extern "C" fn tc_instance_scaffolding_get_players(
+    handle: Handle,
+    players: *mut *const std::ffi::c_char,
+    err_msg: *mut *const std::ffi::c_char,
+) -> TcErrorCode {
+    let impl_func = || -> Result<String, TcError> {
+        instance::scaffolding_players(handle)
     };
 
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(&format!("easytier={}", level_str)));
-
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .try_init();
+    match impl_func() {
+        Ok(players_str) => {
+            if !players.is_null() {
+                if let Ok(cstr) = CString::new(players_str) {
+                    unsafe { *players = strings::into_raw(cstr); }
+                };
+            }
+            TcErrorCode::Ok
+        }
+        Err(e) => e.report(err_msg),
+    }
 }
@@ -0,0 +1,45 @@
+//! Process-wide tracing subscriber whose level can be changed at runtime.
+
+use std::sync::OnceLock;
+
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+fn filter_for(level: &str) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(format!(
+            "easytier=info,easytier_proto=info,terracotta_ios={}",
+            level
+        ))
+    })
+}
+
+/// Install the subscriber. Does nothing if one is already installed.
+pub fn init(level: Option<&str>) {
+    // 默认使用环境变量中的过滤规则
+    let filter = match level {
+        Some(level) => filter_for(level),
+        None => EnvFilter::from_default_env(),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    if tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .try_init()
+        .is_ok()
+    {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// Change the level of the installed subscriber, installing one if needed.
+pub fn set_level(level: &str) -> Result<(), String> {
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter_for(level)).map_err(|e| e.to_string()),
+        None => {
+            init(Some(level));
+            Ok(())
+        }
+    }
+}
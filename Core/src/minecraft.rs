@@ -0,0 +1,310 @@
+//! Minecraft Java Edition Server List Ping.
+//!
+//! The probe does what the game's server list does: a handshake with next
+//! state `1` (status), a status request answered with the server's status
+//! JSON, then a ping whose pong gives the round-trip latency. Works against
+//! any address the device can reach, peers' virtual addresses included.
+
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::error::{TcError, TcErrorCode};
+
+pub const DEFAULT_PORT: u16 = 25565;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Protocol version sent in the handshake, `-1` asks for the server's own.
+const STATUS_PROTOCOL_VERSION: i32 = -1;
+
+const NEXT_STATE_STATUS: i32 = 1;
+
+const PACKET_STATUS: i32 = 0x00;
+const PACKET_PING: i32 = 0x01;
+
+/// Longest status response read, leaves room for a server icon.
+const MAX_PACKET_LEN: usize = 256 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Version {
+    pub name: String,
+    pub protocol: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Player {
+    pub name: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Players {
+    pub online: i64,
+    pub max: i64,
+    /// The few players the server chooses to list, often empty.
+    pub sample: Vec<Player>,
+}
+
+/// What the server list shows for a server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub version: Version,
+    /// Message of the day as plain text, formatting codes removed.
+    pub motd: String,
+    pub players: Players,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The server could not be reached or dropped the connection.
+    Io(io::Error),
+    Timeout,
+    /// The server answered with something that is not a status response.
+    Protocol(String),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Io(e) => write!(f, "failed to reach the server: {}", e),
+            ProbeError::Timeout => write!(f, "the server did not answer in time"),
+            ProbeError::Protocol(e) => write!(f, "invalid server list response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProbeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProbeError {
+    fn from(e: io::Error) -> Self {
+        ProbeError::Io(e)
+    }
+}
+
+impl From<ProbeError> for TcError {
+    fn from(e: ProbeError) -> Self {
+        TcError::from_error(TcErrorCode::Network, &e)
+    }
+}
+
+fn protocol_error(message: impl Into<String>) -> ProbeError {
+    ProbeError::Protocol(message.into())
+}
+
+fn put_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value < 0x80 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+fn get_varint(data: &mut &[u8]) -> Result<i32, ProbeError> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let (&byte, rest) = data
+            .split_first()
+            .ok_or_else(|| protocol_error("truncated VarInt"))?;
+        *data = rest;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(protocol_error("VarInt is too long"))
+}
+
+async fn read_varint(stream: &mut TcpStream) -> Result<i32, ProbeError> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let byte = stream.read_u8().await?;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(protocol_error("VarInt is too long"))
+}
+
+/// Frame `body` as a packet with id `id`.
+fn packet(id: i32, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(body.len() + 5);
+    put_varint(&mut payload, id);
+    payload.extend_from_slice(body);
+    let mut framed = Vec::with_capacity(payload.len() + 5);
+    put_varint(&mut framed, payload.len() as i32);
+    framed.extend_from_slice(&payload);
+    framed
+}
+
+fn handshake(host: &str, port: u16) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_varint(&mut body, STATUS_PROTOCOL_VERSION);
+    put_varint(&mut body, host.len() as i32);
+    body.extend_from_slice(host.as_bytes());
+    body.extend_from_slice(&port.to_be_bytes());
+    put_varint(&mut body, NEXT_STATE_STATUS);
+    packet(0x00, &body)
+}
+
+/// Read one packet, returns its id and body.
+async fn read_packet(stream: &mut TcpStream) -> Result<(i32, Vec<u8>), ProbeError> {
+    let len = read_varint(stream).await?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| (1..=MAX_PACKET_LEN).contains(len))
+        .ok_or_else(|| protocol_error(format!("bad packet length {}", len)))?;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    let mut body = &payload[..];
+    let id = get_varint(&mut body)?;
+    Ok((id, body.to_vec()))
+}
+
+/// Plain text of a chat component: a string, an array or an object with
+/// `text` and `extra`.
+fn chat_text(component: &Value, out: &mut String) {
+    match component {
+        Value::String(text) => out.push_str(text),
+        Value::Array(parts) => parts.iter().for_each(|part| chat_text(part, out)),
+        Value::Object(object) => {
+            if let Some(text) = object.get("text") {
+                chat_text(text, out);
+            }
+            if let Some(extra) = object.get("extra") {
+                chat_text(extra, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Drop the `§x` formatting codes of the legacy format.
+fn strip_formatting(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parse the status JSON of a server, `latency_ms` is left at `0`.
+pub fn parse_status(json: &str) -> Result<Status, ProbeError> {
+    let status: Value = serde_json::from_str(json)
+        .map_err(|e| protocol_error(format!("bad status JSON: {}", e)))?;
+    if !status.is_object() {
+        return Err(protocol_error("status is not a JSON object"));
+    }
+    let str_at = |value: &Value, key: &str| value[key].as_str().unwrap_or_default().to_string();
+
+    let version = &status["version"];
+    let players = &status["players"];
+    let sample = players["sample"]
+        .as_array()
+        .map(|sample| {
+            sample
+                .iter()
+                .map(|player| Player {
+                    name: str_at(player, "name"),
+                    id: str_at(player, "id"),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut motd = String::new();
+    chat_text(&status["description"], &mut motd);
+
+    Ok(Status {
+        version: Version {
+            name: str_at(version, "name"),
+            protocol: version["protocol"].as_i64().unwrap_or_default(),
+        },
+        motd: strip_formatting(&motd),
+        players: Players {
+            online: players["online"].as_i64().unwrap_or_default(),
+            max: players["max"].as_i64().unwrap_or_default(),
+            sample,
+        },
+        latency_ms: 0,
+    })
+}
+
+/// Connect and read the status, leaves the connection open for the ping.
+async fn request_status(host: &str, port: u16) -> Result<(TcpStream, Status), ProbeError> {
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.set_nodelay(true)?;
+
+    let mut request = handshake(host, port);
+    request.extend_from_slice(&packet(PACKET_STATUS, &[]));
+    let started = Instant::now();
+    stream.write_all(&request).await?;
+
+    let (id, body) = read_packet(&mut stream).await?;
+    if id != PACKET_STATUS {
+        return Err(protocol_error(format!("unexpected packet {:#04x}", id)));
+    }
+    let mut body = &body[..];
+    let len = get_varint(&mut body)?;
+    let json = usize::try_from(len)
+        .ok()
+        .and_then(|len| body.get(..len))
+        .ok_or_else(|| protocol_error("truncated status JSON"))?;
+    let json = std::str::from_utf8(json).map_err(|e| protocol_error(e.to_string()))?;
+    let mut status = parse_status(json)?;
+    // 不回 pong 的服务器以状态请求的往返时间为准
+    status.latency_ms = started.elapsed().as_millis() as u64;
+    Ok((stream, status))
+}
+
+/// Round-trip time of a ping, `None` if the server does not answer it.
+async fn ping(stream: &mut TcpStream) -> Option<Duration> {
+    let token = rand::random::<i64>().to_be_bytes();
+    let started = Instant::now();
+    stream.write_all(&packet(PACKET_PING, &token)).await.ok()?;
+    match read_packet(stream).await.ok()? {
+        (PACKET_PING, pong) if pong == token => Some(started.elapsed()),
+        _ => None,
+    }
+}
+
+/// Ask the server at `host:port` for its status, giving up after `timeout`.
+pub async fn probe(host: &str, port: u16, timeout: Duration) -> Result<Status, ProbeError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let (mut stream, mut status) = tokio::time::timeout_at(deadline, request_status(host, port))
+        .await
+        .map_err(|_| ProbeError::Timeout)??;
+    if let Ok(Some(latency)) = tokio::time::timeout_at(deadline, ping(&mut stream)).await {
+        status.latency_ms = latency.as_millis() as u64;
+    }
+    Ok(status)
+}
+
+/// [`probe`] with the result as JSON for the FFI.
+pub async fn probe_json(host: String, port: u16, timeout: Duration) -> Result<String, TcError> {
+    let status = probe(&host, port, timeout).await?;
+    serde_json::to_string(&status).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+}
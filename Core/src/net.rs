@@ -0,0 +1,126 @@
+//! Minimal IPv4 CIDR arithmetic used for config checks and route planning.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// An IPv4 address with a prefix length, e.g. `10.14.0.1/16`.
+/// Host bits are kept so the same type can describe an interface address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Net {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Ipv4Net {
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        (prefix_len <= 32).then_some(Ipv4Net { addr, prefix_len })
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(mask(self.prefix_len))
+    }
+
+    /// Address with the host bits cleared.
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & mask(self.prefix_len))
+    }
+
+    /// The same prefix with the host bits cleared.
+    pub fn trunc(&self) -> Self {
+        Ipv4Net {
+            addr: self.network(),
+            prefix_len: self.prefix_len,
+        }
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & mask(self.prefix_len) == u32::from(self.network())
+    }
+
+    pub fn overlaps(&self, other: &Ipv4Net) -> bool {
+        let shorter = self.prefix_len.min(other.prefix_len);
+        (u32::from(self.addr) ^ u32::from(other.addr)) & mask(shorter) == 0
+    }
+}
+
+fn mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        n => u32::MAX << (32 - n.min(32)),
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = String;
+
+    /// Accepts `a.b.c.d/len`, or a bare address meaning `/32`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: Ipv4Addr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not an IPv4 address", addr))?;
+        let prefix_len = match len {
+            Some(len) => len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= 32)
+                .ok_or_else(|| format!("'{}' is not a valid prefix length", len))?,
+            None => 32,
+        };
+        Ok(Ipv4Net { addr, prefix_len })
+    }
+}
+
+/// The smallest set of prefixes covering exactly the addresses of `nets`,
+/// sorted by address. Prefixes inside another one are dropped and sibling
+/// prefixes are merged into their parent.
+pub fn aggregate(nets: impl IntoIterator<Item = Ipv4Net>) -> Vec<Ipv4Net> {
+    let mut nets: Vec<Ipv4Net> = nets.into_iter().map(|net| net.trunc()).collect();
+    nets.sort_by_key(|net| (u32::from(net.addr), net.prefix_len));
+
+    let mut merged: Vec<Ipv4Net> = Vec::with_capacity(nets.len());
+    for net in nets {
+        if merged
+            .last()
+            .is_some_and(|last| last.prefix_len <= net.prefix_len && last.contains(net.addr))
+        {
+            continue;
+        }
+        merged.push(net);
+        // 相邻的两个同级网段合并为上一级, 合并后可能继续与前一个合并
+        while let [.., a, b] = merged[..] {
+            match sibling_parent(a, b) {
+                Some(parent) => {
+                    merged.truncate(merged.len() - 2);
+                    merged.push(parent);
+                }
+                None => break,
+            }
+        }
+    }
+    merged
+}
+
+fn sibling_parent(a: Ipv4Net, b: Ipv4Net) -> Option<Ipv4Net> {
+    if a.prefix_len != b.prefix_len || a.prefix_len == 0 {
+        return None;
+    }
+    let parent = Ipv4Net {
+        addr: a.addr,
+        prefix_len: a.prefix_len - 1,
+    }
+    .trunc();
+    (parent.addr == a.addr && parent.contains(b.addr)).then_some(parent)
+}
@@ -0,0 +1,177 @@
+//! Startup configuration accepted by `run_network_instance`.
+//!
+//! The extension hands over either raw EasyTier TOML or the JSON-encoded
+//! `TerracottaOptions` from the shared `VPNConfig` key. The format is picked
+//! from the first non-whitespace character.
+
+use easytier::common::config::{ConfigLoader, TomlConfigLoader};
+use serde::Deserialize;
+
+/// Newest options envelope version understood by this core.
+pub const OPTIONS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn detect(cfg_str: &str) -> Self {
+        match cfg_str.trim_start().chars().next() {
+            Some('{') => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+/// Mirror of the Swift `TerracottaOptions` struct.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TerracottaOptions {
+    pub version: u32,
+    /// EasyTier TOML the other fields are applied on top of.
+    pub config: String,
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+    pub mtu: Option<u32>,
+    pub routes: Vec<String>,
+    pub log_level: Option<String>,
+    /// Resolve player and room hostnames with the core's [magic
+    /// DNS](crate::dns).
+    #[serde(rename = "magicDNS")]
+    pub magic_dns: bool,
+    /// DNS servers, forwarded to by the magic DNS if it is enabled.
+    pub dns: Vec<String>,
+    /// Relay Minecraft LAN worlds to the peers, see [`crate::lan`].
+    pub lan_discovery: bool,
+    /// Answer the [scaffolding protocol](crate::scaffolding) as the room
+    /// host.
+    pub scaffolding: Option<ScaffoldingOptions>,
+}
+
+/// The scaffolding server of a room host.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ScaffoldingOptions {
+    /// Port the server listens on, defaults to
+    /// [`DEFAULT_PORT`](crate::scaffolding::DEFAULT_PORT).
+    pub port: u16,
+    /// Port of the Minecraft server guests join, none until set.
+    pub server_port: Option<u16>,
+    /// Name of the hosting player, defaults to the hostname of the config.
+    pub player_name: Option<String>,
+    /// Defaults to the instance id of the config.
+    pub machine_id: Option<String>,
+}
+
+impl Default for ScaffoldingOptions {
+    fn default() -> Self {
+        ScaffoldingOptions {
+            port: crate::scaffolding::DEFAULT_PORT,
+            server_port: None,
+            player_name: None,
+            machine_id: None,
+        }
+    }
+}
+
+impl Default for TerracottaOptions {
+    fn default() -> Self {
+        TerracottaOptions {
+            version: OPTIONS_VERSION,
+            config: String::new(),
+            ipv4: None,
+            ipv6: None,
+            mtu: None,
+            routes: Vec::new(),
+            log_level: None,
+            magic_dns: false,
+            dns: Vec::new(),
+            lan_discovery: false,
+            scaffolding: None,
+        }
+    }
+}
+
+impl TerracottaOptions {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let options: Self =
+            serde_json::from_str(json).map_err(|e| format!("invalid options JSON: {}", e))?;
+        if options.version > OPTIONS_VERSION {
+            return Err(format!(
+                "unsupported options version {}, newest supported is {}",
+                options.version, OPTIONS_VERSION
+            ));
+        }
+        Ok(options)
+    }
+
+    /// Apply the typed fields on top of the embedded TOML config.
+    pub fn apply(&self, cfg: &TomlConfigLoader) -> Result<(), String> {
+        if let Some(ipv4) = &self.ipv4 {
+            let ipv4 = ipv4
+                .parse()
+                .map_err(|e| format!("invalid ipv4 '{}': {}", ipv4, e))?;
+            cfg.set_ipv4(Some(ipv4));
+            cfg.set_dhcp(false);
+        }
+        if let Some(ipv6) = &self.ipv6 {
+            let ipv6 = ipv6
+                .parse()
+                .map_err(|e| format!("invalid ipv6 '{}': {}", ipv6, e))?;
+            cfg.set_ipv6(Some(ipv6));
+        }
+        if !self.routes.is_empty() {
+            let routes = self
+                .routes
+                .iter()
+                .map(|r| {
+                    r.parse()
+                        .map_err(|e| format!("invalid route '{}': {}", r, e))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            cfg.set_routes(Some(routes));
+        }
+
+        let mut flags = cfg.get_flags();
+        if let Some(mtu) = self.mtu {
+            flags.mtu = mtu;
+        }
+        cfg.set_flags(flags);
+
+        if let Some(level) = &self.log_level {
+            crate::logger::set_level(level)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed startup configuration together with the options it came with.
+pub struct LoadedConfig {
+    pub config: TomlConfigLoader,
+    pub options: TerracottaOptions,
+}
+
+/// Parse `cfg_str` as raw TOML or as a `TerracottaOptions` JSON envelope.
+pub fn load(cfg_str: &str) -> Result<LoadedConfig, String> {
+    match ConfigFormat::detect(cfg_str) {
+        ConfigFormat::Toml => {
+            let config = TomlConfigLoader::new_from_str(cfg_str).map_err(|e| e.to_string())?;
+            Ok(LoadedConfig {
+                config,
+                options: TerracottaOptions::default(),
+            })
+        }
+        ConfigFormat::Json => {
+            let options = TerracottaOptions::from_json(cfg_str)?;
+            if options.config.trim().is_empty() {
+                return Err("options.config is empty".to_string());
+            }
+            let config =
+                TomlConfigLoader::new_from_str(&options.config).map_err(|e| e.to_string())?;
+            options.apply(&config)?;
+            Ok(LoadedConfig { config, options })
+        }
+    }
+}
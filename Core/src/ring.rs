@@ -0,0 +1,327 @@
+//! Preallocated packet ring between a packet source and its sink.
+//!
+//! One writer and one reader share a fixed byte buffer and a fixed table of
+//! packet records, nothing is allocated per packet. The writer reads packets
+//! straight into the buffer and the reader hands them on from there, so a
+//! packet taken from a file descriptor is never copied in user space.
+//! Packets that lie back to back in the buffer are handed out as one batch.
+//! When the ring is full the writer either waits, pushing back on its
+//! source, or drops the packet. Both show up in the [`RingStats`].
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+/// Buffer size of the rings used for packet I/O.
+pub const DEFAULT_RING_BYTES: usize = 1024 * 1024;
+
+/// Most packets a ring used for packet I/O holds at once.
+pub const DEFAULT_RING_PACKETS: usize = 2048;
+
+/// Counters of a [`packet_ring`].
+#[derive(Debug, Default)]
+pub struct RingStats {
+    /// Packets committed by the writer.
+    pub packets: AtomicU64,
+    /// Bytes copied into the ring by [`RingWriter::push`], packets read
+    /// straight into the ring are not counted.
+    pub bytes_copied: AtomicU64,
+    /// Times the writer found the ring full.
+    pub full: AtomicU64,
+    /// Packets dropped because the ring was full.
+    pub dropped: AtomicU64,
+    /// Most packets waiting in the ring at once.
+    pub high_water: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RingStatsSnapshot {
+    pub packets: u64,
+    pub bytes_copied: u64,
+    pub full: u64,
+    pub dropped: u64,
+    pub high_water: u64,
+}
+
+impl RingStats {
+    pub fn snapshot(&self) -> RingStatsSnapshot {
+        RingStatsSnapshot {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes_copied: self.bytes_copied.load(Ordering::Relaxed),
+            full: self.full.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            high_water: self.high_water.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Record {
+    /// Start of the space reserved for the packet, the packet itself may
+    /// start later.
+    reserved: usize,
+    start: usize,
+    len: usize,
+}
+
+struct Shared {
+    buf: Box<[UnsafeCell<u8>]>,
+    records: Box<[UnsafeCell<Record>]>,
+    max_packet: usize,
+    /// Records released by the reader, only ever increases.
+    head: AtomicUsize,
+    /// Records committed by the writer, only ever increases.
+    tail: AtomicUsize,
+    readable: Notify,
+    writable: Notify,
+    stats: Arc<RingStats>,
+}
+
+// 写端只写 tail 之后的记录和未被占用的字节, 读端只读 head 到 tail 之间的部分
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn record(&self, index: usize) -> *mut Record {
+        self.records[index % self.records.len()].get()
+    }
+
+    fn bytes(&self, start: usize, len: usize) -> *mut u8 {
+        debug_assert!(start + len <= self.buf.len());
+        UnsafeCell::raw_get(self.buf[start..].as_ptr())
+    }
+}
+
+/// The two ends of a ring of `bytes` bytes holding at most `packets`
+/// packets of up to `max_packet` bytes each.
+pub fn packet_ring(bytes: usize, packets: usize, max_packet: usize) -> (RingWriter, RingReader) {
+    assert!(max_packet > 0 && bytes >= 2 * max_packet && packets > 0);
+    let shared = Arc::new(Shared {
+        buf: (0..bytes).map(|_| UnsafeCell::new(0)).collect(),
+        records: (0..packets)
+            .map(|_| UnsafeCell::new(Record::default()))
+            .collect(),
+        max_packet,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        readable: Notify::new(),
+        writable: Notify::new(),
+        stats: Arc::new(RingStats::default()),
+    });
+    let writer = RingWriter {
+        shared: shared.clone(),
+        pos: 0,
+        reserved: None,
+    };
+    let reader = RingReader {
+        shared,
+        lens: Vec::with_capacity(packets),
+    };
+    (writer, reader)
+}
+
+/// Writing end of a [`packet_ring`].
+pub struct RingWriter {
+    shared: Arc<Shared>,
+    /// Where the next packet goes if it fits.
+    pos: usize,
+    reserved: Option<usize>,
+}
+
+impl RingWriter {
+    pub fn stats(&self) -> Arc<RingStats> {
+        self.shared.stats.clone()
+    }
+
+    pub fn max_packet(&self) -> usize {
+        self.shared.max_packet
+    }
+
+    // 在 pos 处或回绕到开头找一段能放下最大包的连续空间
+    fn find_space(&mut self) -> Option<usize> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Acquire);
+        let tail = shared.tail.load(Ordering::Relaxed);
+        if tail - head == shared.records.len() {
+            return None;
+        }
+        if head == tail {
+            self.pos = 0;
+            return Some(0);
+        }
+        let oldest = unsafe { (*shared.record(head)).reserved };
+        let (max, cap) = (shared.max_packet, shared.buf.len());
+        if oldest < self.pos {
+            if self.pos + max <= cap {
+                Some(self.pos)
+            } else if max <= oldest {
+                Some(0)
+            } else {
+                None
+            }
+        } else if self.pos + max <= oldest {
+            Some(self.pos)
+        } else {
+            None
+        }
+    }
+
+    fn reservation(&mut self, start: usize) -> &mut [u8] {
+        self.reserved = Some(start);
+        let max = self.shared.max_packet;
+        unsafe { std::slice::from_raw_parts_mut(self.shared.bytes(start, max), max) }
+    }
+
+    /// Space for one packet of up to [`max_packet`](Self::max_packet)
+    /// bytes, `None` if the ring is full. Counts as full when it fails.
+    pub fn try_reserve(&mut self) -> Option<&mut [u8]> {
+        match self.find_space() {
+            Some(start) => Some(self.reservation(start)),
+            None => {
+                self.shared.stats.full.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Space for one packet, waits for the reader if the ring is full.
+    pub async fn reserve(&mut self) -> &mut [u8] {
+        let mut waited = false;
+        let start = loop {
+            if let Some(start) = self.find_space() {
+                break start;
+            }
+            if !waited {
+                self.shared.stats.full.fetch_add(1, Ordering::Relaxed);
+                waited = true;
+            }
+            self.shared.writable.notified().await;
+        };
+        self.reservation(start)
+    }
+
+    /// Publish the `len` bytes at `offset` of the last reservation as a
+    /// packet. `len` must not be zero.
+    pub fn commit(&mut self, offset: usize, len: usize) {
+        let reserved = self.reserved.take().expect("commit without reservation");
+        assert!(len > 0 && offset + len <= self.shared.max_packet);
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let start = reserved + offset;
+        unsafe {
+            *shared.record(tail) = Record {
+                reserved,
+                start,
+                len,
+            };
+        }
+        self.pos = start + len;
+        shared.tail.store(tail + 1, Ordering::Release);
+        shared.readable.notify_one();
+
+        let stats = &shared.stats;
+        stats.packets.fetch_add(1, Ordering::Relaxed);
+        let pending = (tail + 1 - shared.head.load(Ordering::Relaxed)) as u64;
+        stats.high_water.fetch_max(pending, Ordering::Relaxed);
+    }
+
+    /// Copy `packet` into the ring, drops it and returns `false` if the
+    /// ring is full or the packet too large.
+    pub fn push(&mut self, packet: &[u8]) -> bool {
+        if packet.is_empty() || packet.len() > self.shared.max_packet {
+            self.shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let Some(slot) = self.try_reserve() else {
+            self.shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        };
+        slot[..packet.len()].copy_from_slice(packet);
+        self.commit(0, packet.len());
+        self.shared
+            .stats
+            .bytes_copied
+            .fetch_add(packet.len() as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Reading end of a [`packet_ring`].
+pub struct RingReader {
+    shared: Arc<Shared>,
+    lens: Vec<usize>,
+}
+
+impl RingReader {
+    pub fn stats(&self) -> Arc<RingStats> {
+        self.shared.stats.clone()
+    }
+
+    /// Packets waiting to be read.
+    pub fn len(&self) -> usize {
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        tail - self.shared.head.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait until a packet is waiting.
+    pub async fn readable(&self) {
+        while self.is_empty() {
+            self.shared.readable.notified().await;
+        }
+    }
+
+    /// The oldest packet, stays in the ring until [consumed](Self::consume).
+    pub fn front(&self) -> Option<&[u8]> {
+        if self.is_empty() {
+            return None;
+        }
+        let shared = &*self.shared;
+        let record = unsafe { *shared.record(shared.head.load(Ordering::Relaxed)) };
+        Some(unsafe {
+            std::slice::from_raw_parts(shared.bytes(record.start, record.len), record.len)
+        })
+    }
+
+    /// Up to `max` of the oldest packets that lie back to back, as one
+    /// buffer and the packet lengths. They stay in the ring until
+    /// [consumed](Self::consume).
+    pub fn batch(&mut self, max: usize) -> Option<(&[u8], &[usize])> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Acquire);
+        let head = shared.head.load(Ordering::Relaxed);
+        if head == tail {
+            return None;
+        }
+        self.lens.clear();
+        let first = unsafe { *shared.record(head) };
+        let mut end = first.start;
+        for index in head..tail.min(head + max) {
+            let record = unsafe { *shared.record(index) };
+            if record.start != end {
+                break;
+            }
+            self.lens.push(record.len);
+            end += record.len;
+        }
+        let len = end - first.start;
+        let buf = unsafe { std::slice::from_raw_parts(shared.bytes(first.start, len), len) };
+        Some((buf, &self.lens))
+    }
+
+    /// Release the `count` oldest packets.
+    pub fn consume(&mut self, count: usize) {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        assert!(count <= shared.tail.load(Ordering::Acquire) - head);
+        shared.head.store(head + count, Ordering::Release);
+        shared.writable.notify_one();
+    }
+}
@@ -0,0 +1,221 @@
+//! Room sessions: turning a room code into a running EasyTier instance.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    host_listeners, Role, RoomConfig, DEFAULT_LISTEN_PORT, HOST_IPV4, NETWORK_PREFIX_LEN,
+};
+use crate::error::{TcError, TcErrorCode};
+use crate::instance::Instance;
+use crate::room_code::{RoomCode, RoomCodeError};
+use crate::scaffolding;
+
+/// Options accepted by `create_room` and `join_room` as a JSON object.
+/// Every field is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RoomOptions {
+    /// Name shown to the other players.
+    pub hostname: Option<String>,
+    /// Peers to connect to, defaults to [`crate::config::DEFAULT_PEERS`].
+    pub peers: Vec<String>,
+    pub mtu: Option<u32>,
+    /// Port the host listens on, defaults to [`DEFAULT_LISTEN_PORT`].
+    pub listen_port: Option<u16>,
+    /// Run without a TUN device, e.g. when the packets are bridged manually.
+    pub no_tun: bool,
+    /// Resolve player and room hostnames with the [magic DNS](crate::dns),
+    /// on unless turned off.
+    pub magic_dns: bool,
+    /// Relay Minecraft LAN worlds to the other players, see [`crate::lan`].
+    /// On unless turned off.
+    pub lan_discovery: bool,
+    /// Answer the [scaffolding protocol](crate::scaffolding) when hosting,
+    /// on unless turned off.
+    pub scaffolding: bool,
+    /// Port of the Minecraft server the host's scaffolding server reports.
+    pub server_port: Option<u16>,
+}
+
+impl Default for RoomOptions {
+    fn default() -> Self {
+        RoomOptions {
+            hostname: None,
+            peers: Vec::new(),
+            mtu: None,
+            listen_port: None,
+            no_tun: false,
+            magic_dns: true,
+            lan_discovery: true,
+            scaffolding: true,
+            server_port: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RoomError {
+    InvalidArgument(String),
+    InvalidCode(RoomCodeError),
+    InvalidOptions(String),
+    Config(String),
+    AlreadyRunning,
+    Start(TcError),
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomError::InvalidArgument(e) => write!(f, "{}", e),
+            RoomError::InvalidCode(e) => write!(f, "{}", e),
+            RoomError::InvalidOptions(e) => write!(f, "invalid room options: {}", e),
+            RoomError::Config(e) => write!(f, "failed to build room config: {}", e),
+            RoomError::AlreadyRunning => write!(f, "a network instance is already running"),
+            RoomError::Start(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RoomError {}
+
+impl RoomError {
+    /// Return value of the FFI call that failed with this error.
+    pub fn code(&self) -> TcErrorCode {
+        match self {
+            RoomError::InvalidArgument(_) => TcErrorCode::InvalidArgument,
+            RoomError::InvalidCode(_) => TcErrorCode::InvalidRoomCode,
+            RoomError::InvalidOptions(_) => TcErrorCode::InvalidOptions,
+            RoomError::Config(_) => TcErrorCode::Config,
+            RoomError::AlreadyRunning => TcErrorCode::AlreadyRunning,
+            RoomError::Start(e) => e.code,
+        }
+    }
+}
+
+impl From<RoomError> for TcError {
+    fn from(e: RoomError) -> Self {
+        match e {
+            RoomError::Start(e) => e,
+            e => TcError::new(e.code(), e.to_string()),
+        }
+    }
+}
+
+impl RoomOptions {
+    pub fn from_json(json: &str) -> Result<Self, RoomError> {
+        if json.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(json).map_err(|e| RoomError::InvalidOptions(e.to_string()))
+    }
+}
+
+/// What `create_room` reports back to the app once the host is running.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomDescriptor {
+    pub code: String,
+    pub name: String,
+    pub network_name: String,
+    pub virtual_ip: String,
+    pub prefix_len: u8,
+    pub listeners: Vec<String>,
+    pub ports: Vec<u16>,
+}
+
+/// Config of an instance taking part in the room identified by `code`.
+pub fn room_config(code: &RoomCode, role: Role, options: &RoomOptions) -> RoomConfig {
+    let mut builder = RoomConfig::for_room(code, role)
+        .peers(options.peers.clone())
+        .no_tun(options.no_tun)
+        .magic_dns(options.magic_dns);
+    // 提供 scaffolding 的房主以约定的主机名标明身份, 玩家名由 scaffolding 服务器报告
+    let hostname = match role {
+        Role::Host if options.scaffolding => {
+            Some(scaffolding::host_hostname(scaffolding::DEFAULT_PORT))
+        }
+        _ => options.hostname.clone(),
+    };
+    if let Some(hostname) = hostname {
+        builder = builder.hostname(hostname);
+    }
+    if let Some(mtu) = options.mtu {
+        builder = builder.mtu(mtu);
+    }
+    if let (Role::Host, Some(port)) = (role, options.listen_port) {
+        builder = builder.listeners(host_listeners(port));
+    }
+    builder.build()
+}
+
+// 房间配置之外的选项通过 TerracottaOptions 信封传入
+// host_player 为提供 scaffolding 的房主的玩家名
+fn start_instance(
+    config: &RoomConfig,
+    options: &RoomOptions,
+    host_player: Option<&str>,
+) -> Result<Instance, RoomError> {
+    let toml = config.to_toml().map_err(RoomError::Config)?;
+    let mut envelope = serde_json::json!({
+        "config": toml,
+        "lanDiscovery": options.lan_discovery,
+    });
+    if let Some(player_name) = host_player {
+        envelope["scaffolding"] = serde_json::json!({
+            "port": scaffolding::DEFAULT_PORT,
+            "serverPort": options.server_port,
+            "playerName": player_name,
+        });
+    }
+    let mut instance =
+        Instance::new(&envelope.to_string()).map_err(|e| RoomError::Config(e.message))?;
+    instance.start().map_err(RoomError::Start)?;
+    Ok(instance)
+}
+
+/// Generate a new room code and start the host instance of that room.
+pub fn create(
+    room_name: &str,
+    options: &RoomOptions,
+) -> Result<(Instance, RoomDescriptor), RoomError> {
+    let code = RoomCode::generate();
+    let config = room_config(&code, Role::Host, options);
+
+    tracing::info!(
+        "hosting room {} on network {}",
+        room_name,
+        code.network_name()
+    );
+    let host_player = options
+        .scaffolding
+        .then(|| options.hostname.as_deref().unwrap_or(room_name));
+    let instance = start_instance(&config, options, host_player)?;
+
+    let mut ports = vec![options.listen_port.unwrap_or(DEFAULT_LISTEN_PORT)];
+    if options.scaffolding {
+        ports.push(scaffolding::DEFAULT_PORT);
+    }
+    let descriptor = RoomDescriptor {
+        code: code.to_string(),
+        name: room_name.to_string(),
+        network_name: code.network_name(),
+        virtual_ip: HOST_IPV4.to_string(),
+        prefix_len: NETWORK_PREFIX_LEN,
+        listeners: config.listeners.clone(),
+        ports,
+    };
+    Ok((instance, descriptor))
+}
+
+/// Decode `room_code` and start a guest instance in that room.
+pub fn join(room_code: &str, options: &RoomOptions) -> Result<Instance, RoomError> {
+    let code = room_code
+        .parse::<RoomCode>()
+        .map_err(RoomError::InvalidCode)?;
+    let config = room_config(&code, Role::Guest, options);
+
+    tracing::info!("joining room network {}", code.network_name());
+    start_instance(&config, options, None)
+}
@@ -0,0 +1,387 @@
+//! Terracotta room code codec.
+//!
+//! A room code looks like `U/NNNN-NNNN-SSSS-SSSC`. The first two groups name
+//! the EasyTier network and the last two groups are its secret, so every
+//! device that is given the same code joins the same network without any
+//! other coordination. The final character `C` is a Luhn mod 34 check
+//! character over the other fifteen, which catches any single mistyped
+//! character and most swapped neighbours.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use serde::Serialize;
+
+/// Prefix shared by every room code.
+pub const PREFIX: &str = "U/";
+
+/// Characters allowed in a room code. `I` and `O` are left out because they
+/// are too easily confused with `1` and `0` when read aloud.
+pub const ALPHABET: &[u8; 34] = b"0123456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Prefix of the EasyTier network name derived from a room code.
+pub const NETWORK_NAME_PREFIX: &str = "terracotta-mc-";
+
+/// Number of payload characters in a room code, dashes excluded.
+pub const CODE_LEN: usize = 16;
+
+const GROUP_LEN: usize = 4;
+const NAME_LEN: usize = CODE_LEN / 2;
+const MAX_SUGGESTIONS: usize = 5;
+const MAX_FORCED_SWAPS: usize = 4;
+
+/// Characters that are commonly misread or misheard as each other.
+const CONFUSABLES: &[&[char]] = &[
+    &['0', 'O', 'D', 'Q'],
+    &['1', 'I', 'L', '7'],
+    &['8', 'B'],
+    &['5', 'S'],
+    &['2', 'Z'],
+    &['6', 'G'],
+    &['U', 'V'],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomCodeError {
+    MissingPrefix,
+    InvalidLength(usize),
+    InvalidCharacter { position: usize, character: char },
+    BadChecksum,
+    InvalidNetworkName(String),
+    InvalidNetworkSecret(String),
+}
+
+impl fmt::Display for RoomCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomCodeError::MissingPrefix => {
+                write!(f, "Invalid room code format. Must start with '{}'", PREFIX)
+            }
+            RoomCodeError::InvalidLength(len) => write!(
+                f,
+                "Invalid room code length: expected {} characters, got {}",
+                CODE_LEN, len
+            ),
+            RoomCodeError::InvalidCharacter {
+                position,
+                character,
+            } => write!(
+                f,
+                "Invalid character '{}' at position {} of room code",
+                character,
+                position + 1
+            ),
+            RoomCodeError::BadChecksum => {
+                write!(f, "Room code checksum mismatch, please check for typos")
+            }
+            RoomCodeError::InvalidNetworkName(name) => {
+                write!(f, "Network name '{}' is not derived from a room code", name)
+            }
+            RoomCodeError::InvalidNetworkSecret(secret) => write!(
+                f,
+                "Network secret '{}' is not derived from a room code",
+                secret
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RoomCodeError {}
+
+impl RoomCodeError {
+    pub fn status(&self) -> CodeStatus {
+        match self {
+            RoomCodeError::BadChecksum => CodeStatus::BadChecksum,
+            _ => CodeStatus::Malformed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeStatus {
+    Valid,
+    Malformed,
+    BadChecksum,
+}
+
+/// Result of checking user input against the room code format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnosis {
+    pub status: CodeStatus,
+    /// Canonical form of the code when it is valid.
+    pub code: Option<String>,
+    pub error: Option<String>,
+    /// Valid codes that differ from the input only by confusable characters.
+    pub suggestions: Vec<String>,
+}
+
+/// A decoded room code. Each entry of `digits` is an index into [`ALPHABET`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RoomCode {
+    digits: [u8; CODE_LEN],
+}
+
+impl RoomCode {
+    /// Generate a new random room code.
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut digits = [0u8; CODE_LEN];
+        for digit in digits[..CODE_LEN - 1].iter_mut() {
+            *digit = rng.gen_range(0..ALPHABET.len()) as u8;
+        }
+        digits[CODE_LEN - 1] = check_digit(&digits[..CODE_LEN - 1]);
+        RoomCode { digits }
+    }
+
+    /// Rebuild a room code from the network identity it derives.
+    pub fn from_identity(network_name: &str, network_secret: &str) -> Result<Self, RoomCodeError> {
+        let name = network_name
+            .strip_prefix(NETWORK_NAME_PREFIX)
+            .and_then(|rest| decode_groups(rest, NAME_LEN))
+            .ok_or_else(|| RoomCodeError::InvalidNetworkName(network_name.to_string()))?;
+        let secret = decode_groups(network_secret, CODE_LEN - NAME_LEN)
+            .ok_or_else(|| RoomCodeError::InvalidNetworkSecret(network_secret.to_string()))?;
+
+        let mut digits = [0u8; CODE_LEN];
+        digits[..NAME_LEN].copy_from_slice(&name);
+        digits[NAME_LEN..].copy_from_slice(&secret);
+        if !checksum_ok(&digits) {
+            return Err(RoomCodeError::BadChecksum);
+        }
+        Ok(RoomCode { digits })
+    }
+
+    /// EasyTier network name, e.g. `terracotta-mc-nnnn-nnnn`.
+    pub fn network_name(&self) -> String {
+        format!(
+            "{}{}",
+            NETWORK_NAME_PREFIX,
+            encode_groups(&self.digits[..NAME_LEN]).to_lowercase()
+        )
+    }
+
+    /// EasyTier network secret, e.g. `ssss-ssss`.
+    pub fn network_secret(&self) -> String {
+        encode_groups(&self.digits[NAME_LEN..]).to_lowercase()
+    }
+}
+
+impl fmt::Display for RoomCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PREFIX, encode_groups(&self.digits))
+    }
+}
+
+impl FromStr for RoomCode {
+    type Err = RoomCodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let body = s
+            .strip_prefix(PREFIX)
+            .or_else(|| s.strip_prefix("u/"))
+            .ok_or(RoomCodeError::MissingPrefix)?;
+
+        let chars: Vec<char> = body.chars().filter(|c| *c != '-').collect();
+        if chars.len() != CODE_LEN {
+            return Err(RoomCodeError::InvalidLength(chars.len()));
+        }
+
+        let mut digits = [0u8; CODE_LEN];
+        for (position, (digit, character)) in digits.iter_mut().zip(chars).enumerate() {
+            *digit = digit_of(character).ok_or(RoomCodeError::InvalidCharacter {
+                position,
+                character,
+            })?;
+        }
+        if !checksum_ok(&digits) {
+            return Err(RoomCodeError::BadChecksum);
+        }
+        Ok(RoomCode { digits })
+    }
+}
+
+/// Check `input` and, when it is not a valid code, look for valid codes
+/// that differ from it only by confusable characters.
+pub fn diagnose(input: &str) -> Diagnosis {
+    match input.parse::<RoomCode>() {
+        Ok(code) => Diagnosis {
+            status: CodeStatus::Valid,
+            code: Some(code.to_string()),
+            error: None,
+            suggestions: Vec::new(),
+        },
+        Err(e) => Diagnosis {
+            status: e.status(),
+            code: None,
+            error: Some(e.to_string()),
+            suggestions: suggest(input),
+        },
+    }
+}
+
+/// Valid room codes reachable from `input` by swapping confusable
+/// characters, fewest substitutions first.
+pub fn suggest(input: &str) -> Vec<String> {
+    let input = input.trim();
+    let body = input
+        .strip_prefix(PREFIX)
+        .or_else(|| input.strip_prefix("u/"))
+        .unwrap_or(input);
+    let chars: Vec<char> = body
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if chars.len() != CODE_LEN {
+        return Vec::new();
+    }
+
+    // 每个位置的候选字符: 原字符 (若合法) 在前, 其余为易混淆字符
+    let mut options: Vec<Vec<(u8, bool)>> = Vec::with_capacity(CODE_LEN);
+    for c in &chars {
+        let mut opts = Vec::new();
+        if let Some(d) = digit_of(*c) {
+            opts.push((d, false));
+        }
+        for alt in confusables_of(*c) {
+            if let Some(d) = digit_of(alt) {
+                opts.push((d, true));
+            }
+        }
+        if opts.is_empty() {
+            return Vec::new();
+        }
+        options.push(opts);
+    }
+
+    // 不合法的字符必须替换, 合法字符最多再替换一个
+    let forced: Vec<usize> = (0..CODE_LEN)
+        .filter(|&i| options[i].first().is_some_and(|(_, swapped)| *swapped))
+        .collect();
+    if forced.len() > MAX_FORCED_SWAPS {
+        return Vec::new();
+    }
+    let mut found: Vec<(usize, RoomCode)> = Vec::new();
+    let mut digits = [0u8; CODE_LEN];
+    for (i, opts) in options.iter().enumerate() {
+        digits[i] = opts[0].0;
+    }
+    search(&options, &forced, 0, &mut digits, forced.len(), &mut found);
+
+    found.sort_by_key(|(cost, _)| *cost);
+    let mut out: Vec<String> = Vec::new();
+    for (_, code) in found {
+        let code = code.to_string();
+        if !out.contains(&code) {
+            out.push(code);
+        }
+        if out.len() == MAX_SUGGESTIONS {
+            break;
+        }
+    }
+    out
+}
+
+fn search(
+    options: &[Vec<(u8, bool)>],
+    forced: &[usize],
+    depth: usize,
+    digits: &mut [u8; CODE_LEN],
+    cost: usize,
+    found: &mut Vec<(usize, RoomCode)>,
+) {
+    if depth < forced.len() {
+        let i = forced[depth];
+        for (d, _) in &options[i] {
+            digits[i] = *d;
+            search(options, forced, depth + 1, digits, cost, found);
+        }
+        return;
+    }
+
+    if checksum_ok(digits) {
+        found.push((cost, RoomCode { digits: *digits }));
+    }
+    for (i, opts) in options.iter().enumerate() {
+        if forced.contains(&i) {
+            continue;
+        }
+        let original = digits[i];
+        for (d, swapped) in opts {
+            if !*swapped {
+                continue;
+            }
+            digits[i] = *d;
+            if checksum_ok(digits) {
+                found.push((cost + 1, RoomCode { digits: *digits }));
+            }
+        }
+        digits[i] = original;
+    }
+}
+
+fn confusables_of(c: char) -> impl Iterator<Item = char> {
+    CONFUSABLES
+        .iter()
+        .filter(move |group| group.contains(&c))
+        .flat_map(|group| group.iter().copied())
+        .filter(move |alt| *alt != c)
+}
+
+// Luhn mod N, N = 34
+fn luhn_sum(digits: &[u8], double_first: bool) -> usize {
+    let n = ALPHABET.len();
+    let mut double = double_first;
+    let mut sum = 0;
+    for digit in digits.iter().rev() {
+        let mut addend = *digit as usize;
+        if double {
+            addend *= 2;
+        }
+        double = !double;
+        sum += addend / n + addend % n;
+    }
+    sum
+}
+
+fn check_digit(payload: &[u8]) -> u8 {
+    let n = ALPHABET.len();
+    ((n - luhn_sum(payload, true) % n) % n) as u8
+}
+
+fn checksum_ok(digits: &[u8; CODE_LEN]) -> bool {
+    luhn_sum(digits, false).is_multiple_of(ALPHABET.len())
+}
+
+/// Alphabet index of `c`, case-insensitively.
+pub(crate) fn digit_of(c: char) -> Option<u8> {
+    let upper = c.to_ascii_uppercase();
+    ALPHABET
+        .iter()
+        .position(|&a| a as char == upper)
+        .map(|i| i as u8)
+}
+
+// 每4个字符用 '-' 分隔
+fn encode_groups(digits: &[u8]) -> String {
+    let mut out = String::with_capacity(digits.len() + digits.len() / GROUP_LEN);
+    for (i, digit) in digits.iter().enumerate() {
+        if i > 0 && i % GROUP_LEN == 0 {
+            out.push('-');
+        }
+        out.push(ALPHABET[*digit as usize] as char);
+    }
+    out
+}
+
+fn decode_groups(s: &str, len: usize) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| *c != '-')
+        .map(digit_of)
+        .collect::<Option<_>>()?;
+    (digits.len() == len).then_some(digits)
+}
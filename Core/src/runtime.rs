@@ -0,0 +1,111 @@
+//! The one tokio runtime shared by every FFI call.
+//!
+//! The Network Extension runs under a tight memory limit, so instead of a
+//! runtime per call or a thread per callback all work runs on a single,
+//! small multi-threaded runtime created on first use.
+
+use std::ffi::{c_char, c_void, CString};
+use std::future::Future;
+use std::sync::LazyLock;
+
+use tokio::runtime::{Builder, Handle, Runtime, RuntimeFlavor};
+
+use crate::error::{TcError, TcErrorCode};
+
+const WORKER_THREADS: usize = 2;
+const MAX_BLOCKING_THREADS: usize = 4;
+const THREAD_STACK_SIZE: usize = 512 * 1024;
+
+static RUNTIME: LazyLock<std::io::Result<Runtime>> = LazyLock::new(|| {
+    Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .max_blocking_threads(MAX_BLOCKING_THREADS)
+        .thread_stack_size(THREAD_STACK_SIZE)
+        .thread_name("terracotta-core")
+        .enable_all()
+        .build()
+});
+
+/// The shared runtime, created on first use.
+pub fn get() -> Result<&'static Runtime, TcError> {
+    RUNTIME.as_ref().map_err(|e| {
+        TcError::from_error(TcErrorCode::Io, e).context("failed to create core runtime")
+    })
+}
+
+/// Run `future` to completion on the shared runtime.
+///
+/// Safe to call from a callback that already runs on a runtime: a worker
+/// of a multi-threaded runtime is handed off while it blocks, anything
+/// else blocks on a helper thread.
+pub fn block_on<F>(future: F) -> Result<F::Output, TcError>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    let runtime = get()?;
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => {
+            Ok(tokio::task::block_in_place(|| runtime.block_on(future)))
+        }
+        Ok(_) => std::thread::scope(|s| s.spawn(|| runtime.block_on(future)).join())
+            .map_err(|_| TcError::internal("blocking task panicked")),
+        Err(_) => Ok(runtime.block_on(future)),
+    }
+}
+
+/// Run `future` in the background on the shared runtime.
+pub fn spawn<F>(future: F) -> Result<(), TcError>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    get()?.spawn(future);
+    Ok(())
+}
+
+/// Completion of an async FFI call.
+///
+/// `result` holds the JSON result on success and `err_msg` the error message
+/// otherwise. Both are only valid until the callback returns. The callback
+/// runs on a core runtime thread.
+pub type CompletionCallback = extern "C" fn(
+    user_data: *mut c_void,
+    code: TcErrorCode,
+    result: *const c_char,
+    err_msg: *const c_char,
+);
+
+/// A completion callback together with the `user_data` it is called with.
+pub struct Completion {
+    callback: CompletionCallback,
+    user_data: *mut c_void,
+}
+
+// user_data 由调用方保证可在任意线程使用
+unsafe impl Send for Completion {}
+
+impl Completion {
+    pub fn new(callback: CompletionCallback, user_data: *mut c_void) -> Self {
+        Completion {
+            callback,
+            user_data,
+        }
+    }
+
+    pub fn complete(self, result: Result<String, TcError>) {
+        let (code, result, err_msg) = match result {
+            Ok(result) => (TcErrorCode::Ok, CString::new(result).ok(), None),
+            Err(e) => (e.code, None, CString::new(e.to_string()).ok()),
+        };
+        let as_ptr = |s: &Option<CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+        (self.callback)(self.user_data, code, as_ptr(&result), as_ptr(&err_msg));
+    }
+}
+
+/// Run `future` on the shared runtime and report its result to `completion`.
+pub fn spawn_completion<F>(future: F, completion: Completion) -> Result<(), TcError>
+where
+    F: Future<Output = Result<String, TcError>> + Send + 'static,
+{
+    spawn(async move { completion.complete(future.await) })
+}
@@ -0,0 +1,601 @@
+//! Client and server of the Terracotta scaffolding protocol.
+//!
+//! The room host answers scaffolding requests over TCP, by default on port
+//! 13448 of its virtual address. A request is the length of its kind as a
+//! `u8`, the kind, e.g. `c:server_port`, the length of its body as a
+//! big-endian `u32` and the body. A response is a status `u8`, the length
+//! of its body as a big-endian `u32` and the body, an error message unless
+//! the status is [`STATUS_OK`]. A connection carries any number of requests
+//! one after the other.
+//!
+//! When this device hosts, [`Server`] answers the requests of the other
+//! players with the port of the game, the players that sent a heartbeat
+//! recently and the kinds it speaks.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::{TcError, TcErrorCode};
+use crate::instance::lock;
+use crate::shutdown::StopState;
+
+pub const DEFAULT_PORT: u16 = 13448;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server closes connections that send no request for this long.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Echoes the body back.
+pub const PING: &str = "c:ping";
+/// Takes the kinds the client speaks separated by `\0`, answers with the
+/// kinds the host speaks.
+pub const PROTOCOLS: &str = "c:protocols";
+/// Answers with the port of the host's Minecraft server as a big-endian
+/// `u16`, [`STATUS_NOT_FOUND`] while there is none.
+pub const SERVER_PORT: &str = "c:server_port";
+/// Heartbeat of a player, takes its [`PlayerProfile`] as JSON.
+pub const PLAYER_PING: &str = "c:player_ping";
+/// Answers with the [`PlayerProfile`]s of the room as a JSON array.
+pub const PLAYER_PROFILES_LIST: &str = "c:player_profiles_list";
+
+/// Request kinds this implementation speaks.
+pub const PROTOCOL_KINDS: [&str; 5] = [
+    PING,
+    PROTOCOLS,
+    SERVER_PORT,
+    PLAYER_PING,
+    PLAYER_PROFILES_LIST,
+];
+
+pub const STATUS_OK: u8 = 0;
+/// What was asked for does not exist, e.g. no Minecraft server is running.
+pub const STATUS_NOT_FOUND: u8 = 32;
+/// The request failed, the body says why.
+pub const STATUS_ERROR: u8 = 255;
+
+/// Players are dropped from the room after this long without a heartbeat,
+/// send [`PLAYER_PING`] at least twice as often.
+pub const PLAYER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Start of the EasyTier hostname of a room host, followed by the port of
+/// its scaffolding server. Guests find the host of the room by it.
+pub const HOST_HOSTNAME_PREFIX: &str = "scaffolding-mc-server-";
+
+/// Vendor reported for players of this app.
+pub const VENDOR: &str = "Terracotta-iOS";
+
+/// Longest request or response body accepted.
+pub const MAX_BODY_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PlayerKind {
+    Host,
+    Guest,
+}
+
+/// A player as the protocol describes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerProfile {
+    pub name: String,
+    /// Identifies the device across reconnects.
+    pub machine_id: String,
+    #[serde(default = "default_vendor")]
+    pub vendor: String,
+    /// Set by the host in [`PLAYER_PROFILES_LIST`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<PlayerKind>,
+}
+
+fn default_vendor() -> String {
+    VENDOR.to_string()
+}
+
+#[derive(Debug)]
+pub enum ScaffoldingError {
+    /// The host could not be reached or dropped the connection.
+    Io(io::Error),
+    Timeout,
+    /// The peer sent something that is not a scaffolding message.
+    Protocol(String),
+    /// The host answered with an error status.
+    Status {
+        status: u8,
+        message: String,
+    },
+}
+
+impl fmt::Display for ScaffoldingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaffoldingError::Io(e) => write!(f, "failed to reach the host: {}", e),
+            ScaffoldingError::Timeout => write!(f, "the host did not answer in time"),
+            ScaffoldingError::Protocol(e) => write!(f, "invalid scaffolding message: {}", e),
+            ScaffoldingError::Status { status, message } => {
+                write!(f, "the host failed the request ({}): {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScaffoldingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScaffoldingError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScaffoldingError {
+    fn from(e: io::Error) -> Self {
+        ScaffoldingError::Io(e)
+    }
+}
+
+impl From<ScaffoldingError> for TcError {
+    fn from(e: ScaffoldingError) -> Self {
+        TcError::from_error(TcErrorCode::Network, &e)
+    }
+}
+
+fn protocol_error(message: impl Into<String>) -> ScaffoldingError {
+    ScaffoldingError::Protocol(message.into())
+}
+
+async fn read_body<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, ScaffoldingError> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_BODY_LEN {
+        return Err(protocol_error(format!("body of {} bytes is too long", len)));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+fn body_len(body: &[u8]) -> Result<u32, ScaffoldingError> {
+    u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_BODY_LEN)
+        .ok_or_else(|| protocol_error(format!("body of {} bytes is too long", body.len())))
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: &str,
+    body: &[u8],
+) -> Result<(), ScaffoldingError> {
+    let kind_len = u8::try_from(kind.len())
+        .map_err(|_| protocol_error(format!("kind {} is too long", kind)))?;
+    let mut frame = Vec::with_capacity(1 + kind.len() + 4 + body.len());
+    frame.push(kind_len);
+    frame.extend_from_slice(kind.as_bytes());
+    frame.extend_from_slice(&body_len(body)?.to_be_bytes());
+    frame.extend_from_slice(body);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read a request, `None` once the client closed the connection.
+pub async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<(String, Vec<u8>)>, ScaffoldingError> {
+    let kind_len = match reader.read_u8().await {
+        Ok(len) => usize::from(len),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut kind = vec![0u8; kind_len];
+    reader.read_exact(&mut kind).await?;
+    let kind = String::from_utf8(kind).map_err(|_| protocol_error("kind is not UTF-8"))?;
+    let body = read_body(reader).await?;
+    Ok(Some((kind, body)))
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u8,
+    body: &[u8],
+) -> Result<(), ScaffoldingError> {
+    let mut frame = Vec::with_capacity(5 + body.len());
+    frame.push(status);
+    frame.extend_from_slice(&body_len(body)?.to_be_bytes());
+    frame.extend_from_slice(body);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+pub async fn read_response<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<(u8, Vec<u8>), ScaffoldingError> {
+    let status = reader.read_u8().await?;
+    let body = read_body(reader).await?;
+    Ok((status, body))
+}
+
+/// EasyTier hostname of a room host serving scaffolding on `port`.
+pub fn host_hostname(port: u16) -> String {
+    format!("{}{}", HOST_HOSTNAME_PREFIX, port)
+}
+
+/// Scaffolding port of the peer with EasyTier hostname `hostname`, `None`
+/// unless it hosts the room.
+pub fn host_port(hostname: &str) -> Option<u16> {
+    hostname.strip_prefix(HOST_HOSTNAME_PREFIX)?.parse().ok()
+}
+
+/// Kinds in a [`PROTOCOLS`] body.
+pub fn parse_protocols(body: &[u8]) -> Vec<String> {
+    body.split(|b| *b == 0)
+        .filter(|kind| !kind.is_empty())
+        .map(|kind| String::from_utf8_lossy(kind).into_owned())
+        .collect()
+}
+
+pub fn encode_protocols<'a>(kinds: impl IntoIterator<Item = &'a str>) -> Vec<u8> {
+    kinds
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join("\0")
+        .into_bytes()
+}
+
+/// A connection to a scaffolding server, every request has to be answered
+/// within the timeout given to [`connect`](Self::connect).
+pub struct Client {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr, timeout: Duration) -> Result<Self, ScaffoldingError> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| ScaffoldingError::Timeout)??;
+        stream.set_nodelay(true)?;
+        Ok(Client { stream, timeout })
+    }
+
+    /// Send a request and return the status and body of the response.
+    pub async fn call(
+        &mut self,
+        kind: &str,
+        body: &[u8],
+    ) -> Result<(u8, Vec<u8>), ScaffoldingError> {
+        let stream = &mut self.stream;
+        let exchange = async move {
+            write_request(stream, kind, body).await?;
+            read_response(stream).await
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| ScaffoldingError::Timeout)?
+    }
+
+    /// [`call`](Self::call) that fails unless the host answers with
+    /// [`STATUS_OK`].
+    pub async fn request(&mut self, kind: &str, body: &[u8]) -> Result<Vec<u8>, ScaffoldingError> {
+        match self.call(kind, body).await? {
+            (STATUS_OK, body) => Ok(body),
+            (status, body) => Err(ScaffoldingError::Status {
+                status,
+                message: String::from_utf8_lossy(&body).into_owned(),
+            }),
+        }
+    }
+
+    /// Round-trip time of a [`PING`].
+    pub async fn ping(&mut self) -> Result<Duration, ScaffoldingError> {
+        let payload = rand::random::<u64>().to_be_bytes();
+        let started = Instant::now();
+        let echo = self.request(PING, &payload).await?;
+        if echo != payload {
+            return Err(protocol_error("ping was not echoed"));
+        }
+        Ok(started.elapsed())
+    }
+
+    /// Tell the host the kinds this client speaks, returns the kinds the
+    /// host speaks.
+    pub async fn protocols(&mut self) -> Result<Vec<String>, ScaffoldingError> {
+        let body = self
+            .request(PROTOCOLS, &encode_protocols(PROTOCOL_KINDS))
+            .await?;
+        Ok(parse_protocols(&body))
+    }
+
+    /// Port of the host's Minecraft server, `None` while it has none.
+    pub async fn server_port(&mut self) -> Result<Option<u16>, ScaffoldingError> {
+        match self.call(SERVER_PORT, &[]).await? {
+            (STATUS_OK, body) => {
+                let port: [u8; 2] = body
+                    .try_into()
+                    .map_err(|_| protocol_error("server port is not a u16"))?;
+                Ok(Some(u16::from_be_bytes(port)))
+            }
+            (STATUS_NOT_FOUND, _) => Ok(None),
+            (status, body) => Err(ScaffoldingError::Status {
+                status,
+                message: String::from_utf8_lossy(&body).into_owned(),
+            }),
+        }
+    }
+
+    /// Heartbeat of `profile`, keeps the player in the room.
+    pub async fn player_ping(&mut self, profile: &PlayerProfile) -> Result<(), ScaffoldingError> {
+        let body = serde_json::to_vec(profile).map_err(|e| protocol_error(e.to_string()))?;
+        self.request(PLAYER_PING, &body).await?;
+        Ok(())
+    }
+
+    pub async fn player_profiles(&mut self) -> Result<Vec<PlayerProfile>, ScaffoldingError> {
+        let body = self.request(PLAYER_PROFILES_LIST, &[]).await?;
+        serde_json::from_slice(&body).map_err(|e| protocol_error(format!("bad player list: {}", e)))
+    }
+}
+
+/// What a guest learns about the room from the host.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostInfo {
+    pub latency_ms: u64,
+    pub protocols: Vec<String>,
+    pub server_port: Option<u16>,
+    pub players: Vec<PlayerProfile>,
+}
+
+/// Ask the scaffolding server at `addr` for everything a guest needs.
+pub async fn query(addr: SocketAddr, timeout: Duration) -> Result<HostInfo, ScaffoldingError> {
+    let mut client = Client::connect(addr, timeout).await?;
+    let latency = client.ping().await?;
+    let protocols = client.protocols().await?;
+    let server_port = client.server_port().await?;
+    let players = if protocols.iter().any(|kind| kind == PLAYER_PROFILES_LIST) {
+        client.player_profiles().await?
+    } else {
+        Vec::new()
+    };
+    Ok(HostInfo {
+        latency_ms: latency.as_millis() as u64,
+        protocols,
+        server_port,
+        players,
+    })
+}
+
+/// [`query`] with the result as JSON for the FFI.
+pub async fn query_json(addr: SocketAddr, timeout: Duration) -> Result<String, TcError> {
+    let info = query(addr, timeout).await?;
+    serde_json::to_string(&info).map_err(|e| TcError::from_error(TcErrorCode::Internal, &e))
+}
+
+/// Send one heartbeat of `profile` to the scaffolding server at `addr`.
+pub async fn send_heartbeat(
+    addr: SocketAddr,
+    profile: &PlayerProfile,
+    timeout: Duration,
+) -> Result<(), ScaffoldingError> {
+    Client::connect(addr, timeout)
+        .await?
+        .player_ping(profile)
+        .await
+}
+
+/// Most guests the host keeps track of, heartbeats of further players are
+/// refused.
+pub const MAX_GUESTS: usize = 128;
+
+/// The room as the host's scaffolding server describes it.
+pub struct Host {
+    profile: PlayerProfile,
+    server_port: Mutex<Option<u16>>,
+    /// Guests by machine id, with the time of their last heartbeat.
+    guests: Mutex<HashMap<String, (PlayerProfile, Instant)>>,
+}
+
+impl Host {
+    /// Room hosted by the player `profile`.
+    pub fn new(mut profile: PlayerProfile) -> Self {
+        profile.kind = Some(PlayerKind::Host);
+        Host {
+            profile,
+            server_port: Mutex::new(None),
+            guests: Mutex::default(),
+        }
+    }
+
+    pub fn profile(&self) -> &PlayerProfile {
+        &self.profile
+    }
+
+    pub fn server_port(&self) -> Option<u16> {
+        *lock(&self.server_port)
+    }
+
+    /// Port of the Minecraft server guests join, `None` while there is none.
+    pub fn set_server_port(&self, port: Option<u16>) {
+        *lock(&self.server_port) = port;
+    }
+
+    /// Keep the guest `profile` in the room for another [`PLAYER_TIMEOUT`].
+    pub fn heartbeat(&self, mut profile: PlayerProfile) -> Result<(), String> {
+        if profile.name.trim().is_empty() {
+            return Err("player name is empty".to_string());
+        }
+        if profile.machine_id.is_empty() {
+            return Err("machine id is empty".to_string());
+        }
+        if profile.machine_id == self.profile.machine_id {
+            return Err("machine id belongs to the host".to_string());
+        }
+        profile.kind = Some(PlayerKind::Guest);
+        let mut guests = lock(&self.guests);
+        guests.retain(|_, (_, seen)| seen.elapsed() < PLAYER_TIMEOUT);
+        if guests.len() >= MAX_GUESTS && !guests.contains_key(&profile.machine_id) {
+            return Err("the room is full".to_string());
+        }
+        guests.insert(profile.machine_id.clone(), (profile, Instant::now()));
+        Ok(())
+    }
+
+    /// The host followed by the guests heard from within
+    /// [`PLAYER_TIMEOUT`], sorted by name.
+    pub fn players(&self) -> Vec<PlayerProfile> {
+        let mut guests: Vec<_> = {
+            let mut guests = lock(&self.guests);
+            guests.retain(|_, (_, seen)| seen.elapsed() < PLAYER_TIMEOUT);
+            guests
+                .values()
+                .map(|(profile, _)| profile.clone())
+                .collect()
+        };
+        guests.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.machine_id.cmp(&b.machine_id))
+        });
+        let mut players = Vec::with_capacity(guests.len() + 1);
+        players.push(self.profile.clone());
+        players.extend(guests);
+        players
+    }
+
+    /// Status and body of the response to a request.
+    pub fn answer(&self, kind: &str, body: &[u8]) -> (u8, Vec<u8>) {
+        match kind {
+            PING => (STATUS_OK, body.to_vec()),
+            PROTOCOLS => (STATUS_OK, encode_protocols(PROTOCOL_KINDS)),
+            SERVER_PORT => match self.server_port() {
+                Some(port) => (STATUS_OK, port.to_be_bytes().to_vec()),
+                None => (STATUS_NOT_FOUND, Vec::new()),
+            },
+            PLAYER_PING => {
+                let heartbeat = serde_json::from_slice(body)
+                    .map_err(|e| format!("invalid player profile: {}", e))
+                    .and_then(|profile| self.heartbeat(profile));
+                match heartbeat {
+                    Ok(()) => (STATUS_OK, Vec::new()),
+                    Err(e) => (STATUS_ERROR, e.into_bytes()),
+                }
+            }
+            PLAYER_PROFILES_LIST => match serde_json::to_vec(&self.players()) {
+                Ok(body) => (STATUS_OK, body),
+                Err(e) => (STATUS_ERROR, e.to_string().into_bytes()),
+            },
+            _ => (
+                STATUS_ERROR,
+                format!("unknown request {}", kind).into_bytes(),
+            ),
+        }
+    }
+}
+
+/// Answers the scaffolding requests of the other players for a [`Host`].
+pub struct Server {
+    listener: TcpListener,
+    host: Arc<Host>,
+    timeout: Duration,
+    idle_timeout: Duration,
+}
+
+impl Server {
+    pub async fn bind(addr: SocketAddr, host: Arc<Host>) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr).await?,
+            host,
+            timeout: DEFAULT_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        })
+    }
+
+    /// Close connections that take longer than `timeout` to send a request
+    /// once it started or to take its response, or that send no request
+    /// for `idle_timeout`.
+    pub fn timeouts(mut self, timeout: Duration, idle_timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answer connections until the run ends, open connections are closed
+    /// with it.
+    pub async fn run(self, stop_state: Arc<StopState>) {
+        loop {
+            let accepted = tokio::select! {
+                _ = stop_state.wait() => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // 文件描述符耗尽等错误会立即重现, 稍等再接受新连接
+                    tracing::warn!("failed to accept scaffolding connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let host = self.host.clone();
+            let stop_state = stop_state.clone();
+            let (timeout, idle_timeout) = (self.timeout, self.idle_timeout);
+            tokio::spawn(async move {
+                let served = tokio::select! {
+                    _ = stop_state.wait() => return,
+                    served = serve_connection(stream, &host, timeout, idle_timeout) => served,
+                };
+                if let Err(e) = served {
+                    tracing::debug!("closed scaffolding connection from {}: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+// 每个请求在第一个字节到达后必须在 timeout 内读完, 响应也必须在 timeout 内写完
+async fn serve_connection(
+    mut stream: TcpStream,
+    host: &Host,
+    timeout: Duration,
+    idle_timeout: Duration,
+) -> Result<(), ScaffoldingError> {
+    stream.set_nodelay(true)?;
+    let mut first = [0u8; 1];
+    loop {
+        match tokio::time::timeout(idle_timeout, stream.peek(&mut first)).await {
+            Err(_) => return Err(ScaffoldingError::Timeout),
+            Ok(Ok(0)) => return Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+        }
+        let request = tokio::time::timeout(timeout, read_request(&mut stream))
+            .await
+            .map_err(|_| ScaffoldingError::Timeout)??;
+        let Some((kind, body)) = request else {
+            return Ok(());
+        };
+        let (status, response) = host.answer(&kind, &body);
+        tokio::time::timeout(timeout, write_response(&mut stream, status, &response))
+            .await
+            .map_err(|_| ScaffoldingError::Timeout)??;
+    }
+}
+
+/// Serve `host` on `addr` until the run ends.
+pub async fn serve(host: Arc<Host>, addr: SocketAddr, stop_state: Arc<StopState>) {
+    match Server::bind(addr, host).await {
+        Ok(server) => server.run(stop_state).await,
+        Err(e) => tracing::warn!("scaffolding server is unavailable on {}: {}", addr, e),
+    }
+}
@@ -0,0 +1,246 @@
+//! Awaited shutdown of a network instance.
+//!
+//! Dropping an EasyTier instance stops it and joins its runtime thread.
+//! Before the thread exits EasyTier clears the instance's resources: it
+//! closes every peer connection, which the peers see as the end of the
+//! tunnel, and every listener. That drop runs on a blocking thread and is
+//! awaited up to a timeout, after which the listener ports are checked
+//! until they can be bound again, so a restart does not race sockets that
+//! are still bound. Every run of an instance ends exactly once, with a
+//! [`StopReason`] reported to its stop callbacks and event consumers.
+
+use std::ffi::c_void;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use easytier::launcher::NetworkInstance;
+use easytier::proto::{api::instance::ListPeerRequest, rpc_types::controller::BaseController};
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::error::TcError;
+use crate::instance::lock;
+use crate::runtime;
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often released listener ports are checked for during a shutdown.
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Why a run of an instance ended, the numeric values are part of the C ABI.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// Stopped on request, all sockets were closed before the timeout.
+    Clean = 0,
+    /// Stopped on request, the timeout passed before the instance shut down.
+    Forced = 1,
+    /// The instance stopped on its own, see the latest error message.
+    Failed = 2,
+}
+
+/// Called once with the reason a run ended, on whichever thread ended it.
+pub type StopCallbackFn = extern "C" fn(user_data: *mut c_void, reason: StopReason);
+
+pub struct StopCallback {
+    callback: StopCallbackFn,
+    user_data: *mut c_void,
+}
+
+// user_data 由调用方保证可在任意线程使用
+unsafe impl Send for StopCallback {}
+
+impl StopCallback {
+    pub fn new(callback: StopCallbackFn, user_data: *mut c_void) -> Self {
+        StopCallback {
+            callback,
+            user_data,
+        }
+    }
+
+    fn call(self, reason: StopReason) {
+        (self.callback)(self.user_data, reason);
+    }
+}
+
+/// End of one run of an instance.
+#[derive(Default)]
+pub struct StopState {
+    requested: AtomicBool,
+    reason: OnceLock<StopReason>,
+    callbacks: Mutex<Vec<StopCallback>>,
+    finished: Notify,
+}
+
+impl StopState {
+    pub fn new() -> Arc<Self> {
+        Arc::new(StopState::default())
+    }
+
+    /// Mark the stop as requested, a stop notification that follows is
+    /// not a failure.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn reason(&self) -> Option<StopReason> {
+        self.reason.get().copied()
+    }
+
+    /// Record how the run ended and fire the callbacks. Only the first call
+    /// has an effect, the reason that won is returned.
+    pub fn finish(&self, reason: StopReason) -> StopReason {
+        if self.reason.set(reason).is_err() {
+            return self.reason().unwrap_or(reason);
+        }
+        let callbacks = std::mem::take(&mut *lock(&self.callbacks));
+        for callback in callbacks {
+            callback.call(reason);
+        }
+        self.finished.notify_waiters();
+        reason
+    }
+
+    /// Call `callback` once the run ends, right away if it already has.
+    pub fn on_stop(&self, callback: StopCallback) {
+        let mut callbacks = lock(&self.callbacks);
+        match self.reason() {
+            Some(reason) => {
+                drop(callbacks);
+                callback.call(reason);
+            }
+            None => callbacks.push(callback),
+        }
+    }
+
+    /// Wait until the run ends.
+    pub async fn wait(&self) -> StopReason {
+        loop {
+            let finished = self.finished.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+            if let Some(reason) = self.reason() {
+                return reason;
+            }
+            finished.await;
+        }
+    }
+}
+
+/// Report a run that ends without being asked to as failed.
+pub async fn monitor(stop: Arc<Notify>, state: Arc<StopState>) {
+    tokio::select! {
+        _ = stop.notified() => {
+            if !state.is_requested() {
+                state.finish(StopReason::Failed);
+            }
+        }
+        _ = state.wait() => {}
+    }
+}
+
+/// Port bound by a listener of an instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerPort {
+    Tcp(u16),
+    Udp(u16),
+}
+
+impl ListenerPort {
+    /// Ports bound by the listener `urls` of a config, listeners on port
+    /// `0` or with unknown schemes are left out.
+    pub fn from_urls<'a>(urls: impl IntoIterator<Item = &'a url::Url>) -> Vec<Self> {
+        urls.into_iter()
+            .filter_map(|url| {
+                let port = url.port().filter(|port| *port != 0)?;
+                match url.scheme() {
+                    "tcp" | "ws" | "wss" => Some(ListenerPort::Tcp(port)),
+                    "udp" | "wg" | "quic" => Some(ListenerPort::Udp(port)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the port can be bound again.
+    pub fn is_released(&self) -> bool {
+        match *self {
+            ListenerPort::Tcp(port) => TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok(),
+            ListenerPort::Udp(port) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok(),
+        }
+    }
+}
+
+/// A running instance detached from its handle to be shut down.
+pub struct Running {
+    pub inst: NetworkInstance,
+    pub stop_state: Arc<StopState>,
+    /// Ports the listeners of the run bind.
+    pub listeners: Vec<ListenerPort>,
+}
+
+// 停止前的对等连接数, 仅用于日志
+async fn peer_connections(inst: &NetworkInstance) -> usize {
+    let Some(api_service) = inst.get_api_service() else {
+        return 0;
+    };
+    api_service
+        .get_peer_manage_service()
+        .list_peer(BaseController::default(), ListPeerRequest::default())
+        .await
+        .map_or(0, |peers| {
+            peers.peers.iter().map(|peer| peer.conns.len()).sum()
+        })
+}
+
+/// Wait until every port in `listeners` can be bound again, false if one
+/// is still bound at `deadline`.
+pub async fn wait_released(listeners: &[ListenerPort], deadline: tokio::time::Instant) -> bool {
+    loop {
+        if listeners.iter().all(ListenerPort::is_released) {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(RELEASE_POLL_INTERVAL).await;
+    }
+}
+
+/// Stop `running` and wait up to `timeout` for its peer connections to be
+/// torn down and its listener ports to be released.
+pub fn shutdown(running: Running, timeout: Duration) -> Result<StopReason, TcError> {
+    let Running {
+        inst,
+        stop_state,
+        listeners,
+    } = running;
+    stop_state.request();
+    if let Some(stop) = inst.get_stop_notifier() {
+        stop.notify_waiters();
+    }
+    let reason = runtime::block_on(async move {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let conns = peer_connections(&inst).await;
+        // 实例析构时 EasyTier 关闭所有对等连接和监听, 并等待运行线程退出
+        let dropping = tokio::task::spawn_blocking(move || drop(inst));
+        if tokio::time::timeout_at(deadline, dropping).await.is_err() {
+            tracing::warn!("instance did not shut down within {:?}", timeout);
+            return StopReason::Forced;
+        }
+        if !wait_released(&listeners, deadline).await {
+            tracing::warn!("listener ports were not released within {:?}", timeout);
+            return StopReason::Forced;
+        }
+        tracing::info!("instance shut down, closed {} peer connections", conns);
+        StopReason::Clean
+    })?;
+    Ok(stop_state.finish(reason))
+}
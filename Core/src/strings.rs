@@ -0,0 +1,68 @@
+//! Ownership of strings handed out across the FFI boundary.
+//!
+//! Every `const char **` out-parameter receives a string allocated by Rust.
+//! The caller owns it and must release it with `tc_string_free`, never with
+//! libc `free()`: the Rust allocator is not guaranteed to be malloc.
+//!
+//! Debug builds record every live string so tests can assert that a call
+//! sequence does not leak, and so that freeing a pointer Rust did not hand
+//! out is caught instead of corrupting the heap.
+
+use std::ffi::{c_char, CString};
+
+#[cfg(debug_assertions)]
+mod tracker {
+    use std::collections::HashSet;
+    use std::sync::{LazyLock, Mutex};
+
+    use crate::instance::lock;
+
+    static LIVE: LazyLock<Mutex<HashSet<usize>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+    pub fn track(ptr: *const std::ffi::c_char) {
+        lock(&LIVE).insert(ptr as usize);
+    }
+
+    /// Returns false if `ptr` was not handed out by [`super::into_raw`].
+    pub fn untrack(ptr: *const std::ffi::c_char) -> bool {
+        lock(&LIVE).remove(&(ptr as usize))
+    }
+
+    pub fn live() -> usize {
+        lock(&LIVE).len()
+    }
+}
+
+/// Give up ownership of `s` so it can be returned through an out-parameter.
+pub fn into_raw(s: CString) -> *const c_char {
+    let ptr = s.into_raw() as *const c_char;
+    #[cfg(debug_assertions)]
+    tracker::track(ptr);
+    ptr
+}
+
+/// Reclaim and drop a string returned by [`into_raw`].
+///
+/// # Safety
+/// `ptr` must be null or come from [`into_raw`] and not be freed before.
+pub unsafe fn free(ptr: *const c_char) {
+    if ptr.is_null() {
+        return;
+    }
+    #[cfg(debug_assertions)]
+    if !tracker::untrack(ptr) {
+        // 调试版本中拒绝释放未知指针, 避免破坏堆
+        tracing::error!("tc_string_free called with unknown pointer {:p}", ptr);
+        return;
+    }
+    drop(CString::from_raw(ptr as *mut c_char));
+}
+
+/// Number of strings handed out and not yet freed. Only tracked in debug
+/// builds, `None` otherwise.
+pub fn live_count() -> Option<usize> {
+    #[cfg(debug_assertions)]
+    return Some(tracker::live());
+    #[cfg(not(debug_assertions))]
+    None
+}
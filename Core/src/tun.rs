@@ -0,0 +1,394 @@
+//! Packet I/O between the system tunnel and EasyTier.
+//!
+//! A utun fd prefixes every packet with a 4-byte big-endian address family,
+//! EasyTier's virtual NIC expects bare IP packets. The adapter owns the
+//! utun fd and hands EasyTier one end of a datagram socketpair instead,
+//! moving packets between the two and stripping or adding the header on
+//! the way.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::dns::{Interceptor, MagicDns};
+use crate::error::{TcError, TcErrorCode};
+use crate::lan::{Echo, Overlay};
+use crate::ring::{
+    self, RingReader, RingStats, RingStatsSnapshot, RingWriter, DEFAULT_RING_BYTES,
+    DEFAULT_RING_PACKETS,
+};
+use crate::runtime;
+
+/// Length of the address family header utun puts before every packet.
+pub const UTUN_HEADER_LEN: usize = 4;
+
+/// Largest packet moved through the adapter.
+pub const MAX_PACKET_SIZE: usize = 65535;
+
+/// Packets the core makes itself that may wait for the system.
+const INJECT_QUEUE_LEN: usize = 64;
+
+/// Socket buffer size of the [`nic_pair`], the platform defaults only hold
+/// a couple of packets.
+const NIC_BUFFER_SIZE: libc::c_int = 512 * 1024;
+
+/// Sends packets the core makes itself, such as DNS replies, to the system
+/// as if EasyTier had sent them.
+pub type Injector = mpsc::Sender<Vec<u8>>;
+
+pub(crate) fn injector() -> (Injector, mpsc::Receiver<Vec<u8>>) {
+    mpsc::channel(INJECT_QUEUE_LEN)
+}
+
+/// Address family header for an IP packet, `None` if it is neither IPv4
+/// nor IPv6.
+pub fn utun_header(packet: &[u8]) -> Option<[u8; UTUN_HEADER_LEN]> {
+    let family = match packet.first()? >> 4 {
+        4 => libc::AF_INET,
+        6 => libc::AF_INET6,
+        _ => return None,
+    };
+    Some((family as u32).to_be_bytes())
+}
+
+/// Strip the address family header, `None` for a frame without payload.
+pub fn strip_utun_header(frame: &[u8]) -> Option<&[u8]> {
+    frame
+        .get(UTUN_HEADER_LEN..)
+        .filter(|packet| !packet.is_empty())
+}
+
+/// A connected pair of datagram sockets, one packet per datagram. The first
+/// end is handed to EasyTier, the second stays in the core.
+pub fn nic_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_DGRAM, 0, fds.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let pair = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in [&pair.0, &pair.1] {
+        set_cloexec(fd)?;
+        for option in [libc::SO_SNDBUF, libc::SO_RCVBUF] {
+            // 失败时保留系统默认值
+            unsafe {
+                libc::setsockopt(
+                    fd.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    option,
+                    (&NIC_BUFFER_SIZE as *const libc::c_int).cast(),
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                );
+            }
+        }
+    }
+    set_nonblocking(&pair.1)?;
+    Ok(pair)
+}
+
+fn fcntl_set(
+    fd: &OwnedFd,
+    get: libc::c_int,
+    set: libc::c_int,
+    flag: libc::c_int,
+) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), get);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), set, flags | flag) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    fcntl_set(fd, libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)
+}
+
+pub fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    fcntl_set(fd, libc::F_GETFL, libc::F_SETFL, libc::O_NONBLOCK)
+}
+
+fn read_packet(fd: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+fn write_packet(fd: &OwnedFd, packet: &[u8]) -> io::Result<usize> {
+    let n = unsafe { libc::write(fd.as_raw_fd(), packet.as_ptr().cast(), packet.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+/// Non-blocking packet fd driven by the core runtime.
+pub struct PacketFd(AsyncFd<OwnedFd>);
+
+impl PacketFd {
+    /// Must be called from within the core runtime.
+    pub fn new(fd: OwnedFd) -> io::Result<Self> {
+        set_nonblocking(&fd)?;
+        Ok(PacketFd(AsyncFd::new(fd)?))
+    }
+
+    /// Read one packet into `buf`.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .async_io(Interest::READABLE, |fd| read_packet(fd, buf))
+            .await
+    }
+
+    /// Write `packet` as one datagram.
+    pub async fn send(&self, packet: &[u8]) -> io::Result<usize> {
+        self.0
+            .async_io(Interest::WRITABLE, |fd| write_packet(fd, packet))
+            .await
+    }
+}
+
+/// Packet counters of a [`TunAdapter`] or [`PacketBridge`](crate::bridge::PacketBridge).
+#[derive(Debug, Default)]
+pub struct TunStats {
+    /// Packets read from the tunnel and passed to EasyTier.
+    pub inbound_packets: AtomicU64,
+    pub inbound_bytes: AtomicU64,
+    /// Packets from EasyTier written to the tunnel.
+    pub outbound_packets: AtomicU64,
+    pub outbound_bytes: AtomicU64,
+    /// Empty frames or packets that are neither IPv4 nor IPv6.
+    pub malformed: AtomicU64,
+    /// Ring between the tunnel and EasyTier.
+    pub inbound_ring: Arc<RingStats>,
+    /// Ring between EasyTier and the tunnel.
+    pub outbound_ring: Arc<RingStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TunStatsSnapshot {
+    pub inbound_packets: u64,
+    pub inbound_bytes: u64,
+    pub outbound_packets: u64,
+    pub outbound_bytes: u64,
+    pub malformed: u64,
+    pub inbound_ring: RingStatsSnapshot,
+    pub outbound_ring: RingStatsSnapshot,
+}
+
+impl TunStats {
+    pub(crate) fn new(inbound: &RingWriter, outbound: &RingWriter) -> Self {
+        TunStats {
+            inbound_ring: inbound.stats(),
+            outbound_ring: outbound.stats(),
+            ..TunStats::default()
+        }
+    }
+
+    pub(crate) fn count(packets: &AtomicU64, bytes: &AtomicU64, len: usize) {
+        packets.fetch_add(1, Ordering::Relaxed);
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TunStatsSnapshot {
+        TunStatsSnapshot {
+            inbound_packets: self.inbound_packets.load(Ordering::Relaxed),
+            inbound_bytes: self.inbound_bytes.load(Ordering::Relaxed),
+            outbound_packets: self.outbound_packets.load(Ordering::Relaxed),
+            outbound_bytes: self.outbound_bytes.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            inbound_ring: self.inbound_ring.snapshot(),
+            outbound_ring: self.outbound_ring.snapshot(),
+        }
+    }
+}
+
+/// A ring sized for packet I/O, every slot can take a framed packet of the
+/// largest size.
+pub(crate) fn io_ring() -> (RingWriter, RingReader) {
+    ring::packet_ring(
+        DEFAULT_RING_BYTES,
+        DEFAULT_RING_PACKETS,
+        UTUN_HEADER_LEN + MAX_PACKET_SIZE,
+    )
+}
+
+/// Moves packets between a utun fd and the core end of a [`nic_pair`],
+/// buffering each direction in a [`packet_ring`](ring::packet_ring).
+/// Queries for the [`MagicDns`] are answered by the adapter and never reach
+/// EasyTier. With an [`Overlay`], announcements of the peers' LAN worlds
+/// are also multicast to the system, see [`Echo`]. Dropping the adapter
+/// stops it and closes both fds.
+pub struct TunAdapter {
+    injector: Injector,
+    stats: Arc<TunStats>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TunAdapter {
+    pub fn start(
+        utun: OwnedFd,
+        nic: OwnedFd,
+        dns: Option<Arc<MagicDns>>,
+        lan: Option<Arc<Overlay>>,
+    ) -> Result<Self, TcError> {
+        let runtime = runtime::get()?;
+        let _guard = runtime.enter();
+        let io_error = |e: io::Error| TcError::from_error(TcErrorCode::Io, &e);
+        let utun = Arc::new(PacketFd::new(utun).map_err(io_error)?);
+        let nic = Arc::new(PacketFd::new(nic).map_err(io_error)?);
+        let (inbound_writer, inbound_reader) = io_ring();
+        let (outbound_writer, outbound_reader) = io_ring();
+        let stats = Arc::new(TunStats::new(&inbound_writer, &outbound_writer));
+        let (injector, injected) = injector();
+        let interceptor = dns
+            .map(|dns| Interceptor::new(dns, injector.clone()))
+            .transpose()?;
+        let echo = lan.map(|overlay| Echo::new(overlay, injector.clone()));
+
+        let inbound = stats.clone();
+        let outbound = stats.clone();
+        let tasks = vec![
+            runtime.spawn(read_utun(
+                utun.clone(),
+                inbound_writer,
+                interceptor,
+                stats.clone(),
+            )),
+            runtime.spawn(drain(inbound_reader, nic.clone(), move |len| {
+                TunStats::count(&inbound.inbound_packets, &inbound.inbound_bytes, len)
+            })),
+            runtime.spawn(read_nic(nic, outbound_writer, true, echo, stats.clone())),
+            runtime.spawn(drain(outbound_reader, utun.clone(), move |len| {
+                let len = len - UTUN_HEADER_LEN;
+                TunStats::count(&outbound.outbound_packets, &outbound.outbound_bytes, len)
+            })),
+            runtime.spawn(send_injected(injected, utun)),
+        ];
+        Ok(TunAdapter {
+            injector,
+            stats,
+            tasks,
+        })
+    }
+
+    /// Sender of packets for the system, written to the utun fd.
+    pub fn injector(&self) -> Injector {
+        self.injector.clone()
+    }
+
+    pub fn stats(&self) -> TunStatsSnapshot {
+        self.stats.snapshot()
+    }
+}
+
+impl Drop for TunAdapter {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// utun -> 环, 直接读入环中并去掉地址族头
+async fn read_utun(
+    utun: Arc<PacketFd>,
+    mut writer: RingWriter,
+    interceptor: Option<Interceptor>,
+    stats: Arc<TunStats>,
+) {
+    loop {
+        let slot = writer.reserve().await;
+        let n = match utun.recv(slot).await {
+            Ok(n) => n,
+            Err(e) => {
+                tracing::error!("failed to read from tun: {}", e);
+                break;
+            }
+        };
+        if n <= UTUN_HEADER_LEN {
+            stats.malformed.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        // 发给魔法 DNS 的查询不提交, 预留的空间留给下一个包
+        if let Some(interceptor) = &interceptor {
+            if interceptor.intercept(&slot[UTUN_HEADER_LEN..n]) {
+                continue;
+            }
+        }
+        writer.commit(UTUN_HEADER_LEN, n - UTUN_HEADER_LEN);
+    }
+}
+
+// 核心生成的包 (魔法 DNS 的应答等) -> utun
+async fn send_injected(mut injected: mpsc::Receiver<Vec<u8>>, utun: Arc<PacketFd>) {
+    while let Some(packet) = injected.recv().await {
+        let Some(header) = utun_header(&packet) else {
+            continue;
+        };
+        let frame = [&header[..], &packet].concat();
+        if let Err(e) = utun.send(&frame).await {
+            tracing::warn!("failed to write injected packet: {}", e);
+        }
+    }
+}
+
+/// Read EasyTier's packets straight into `writer` until the socket fails.
+/// With `framed` every packet is preceded by its utun header. `echo` sees
+/// every packet before it is committed.
+pub(crate) async fn read_nic(
+    nic: Arc<PacketFd>,
+    mut writer: RingWriter,
+    framed: bool,
+    echo: Option<Echo>,
+    stats: Arc<TunStats>,
+) {
+    let offset = if framed { UTUN_HEADER_LEN } else { 0 };
+    loop {
+        let slot = writer.reserve().await;
+        let n = match nic.recv(&mut slot[offset..]).await {
+            Ok(n) => n,
+            Err(e) => {
+                tracing::error!("failed to read from EasyTier: {}", e);
+                break;
+            }
+        };
+        let Some(header) = utun_header(&slot[offset..offset + n]) else {
+            stats.malformed.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+        if let Some(echo) = &echo {
+            echo.observe(&slot[offset..offset + n]);
+        }
+        if framed {
+            slot[..UTUN_HEADER_LEN].copy_from_slice(&header);
+        }
+        writer.commit(0, offset + n);
+    }
+}
+
+/// Write every packet of `reader` to `fd` straight from the ring, `sent`
+/// is called with the length of each packet written.
+pub(crate) async fn drain(mut reader: RingReader, fd: Arc<PacketFd>, mut sent: impl FnMut(usize)) {
+    loop {
+        reader.readable().await;
+        while let Some(packet) = reader.front() {
+            let len = packet.len();
+            match fd.send(packet).await {
+                Ok(_) => sent(len),
+                Err(e) => tracing::warn!("failed to write packet: {}", e),
+            }
+            reader.consume(1);
+        }
+    }
+}
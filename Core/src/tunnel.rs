@@ -0,0 +1,243 @@
+//! Network settings the packet tunnel applies to the utun interface.
+//!
+//! Addresses come from the running instance, so a guest reports the address
+//! EasyTier's DHCP handed out rather than a guess derived from the config.
+//! Only the virtual network is routed through the tunnel. The public
+//! endpoints of the peers are excluded, so the connections carrying the
+//! tunnel never loop back into it. With magic DNS the system resolver is
+//! pointed at the core's [resolver](crate::dns) once the address is known.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use easytier::proto::api::instance::{NodeInfo, PeerInfo, Route};
+use easytier::proto::common::FlagsInConfig;
+use serde::Serialize;
+
+use crate::config::DEFAULT_MTU;
+use crate::dns;
+use crate::net::{self, Ipv4Net};
+use crate::options::TerracottaOptions;
+
+/// Bumped whenever a field changes meaning.
+pub const TUNNEL_SETTINGS_VERSION: u32 = 1;
+
+/// Values fixed at startup that are not part of EasyTier's running state.
+#[derive(Debug, Clone)]
+pub struct TunnelParams {
+    pub mtu: u32,
+    /// DNS servers, the upstream servers of the magic DNS if it is enabled.
+    pub dns: Vec<String>,
+    /// Extra routes requested by the profile, in CIDR form.
+    pub routes: Vec<String>,
+    pub magic_dns: bool,
+}
+
+impl Default for TunnelParams {
+    fn default() -> Self {
+        TunnelParams {
+            mtu: DEFAULT_MTU,
+            dns: Vec::new(),
+            routes: Vec::new(),
+            magic_dns: false,
+        }
+    }
+}
+
+impl TunnelParams {
+    /// `flags` are the effective flags of the instance config, which
+    /// already include any override from `options`. Magic DNS is on with
+    /// `magicDNS` or EasyTier's `accept_dns`.
+    pub fn new(flags: &FlagsInConfig, options: &TerracottaOptions) -> Self {
+        TunnelParams {
+            mtu: flags.mtu,
+            dns: options.dns.clone(),
+            routes: options.routes.clone(),
+            magic_dns: flags.accept_dns || options.magic_dns,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ipv4Setting {
+    pub address: String,
+    pub prefix_len: u8,
+    pub subnet_mask: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ipv6Setting {
+    pub address: String,
+    pub prefix_len: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ipv4Route {
+    pub destination: String,
+    pub prefix_len: u8,
+    pub subnet_mask: String,
+}
+
+impl From<Ipv4Net> for Ipv4Route {
+    fn from(net: Ipv4Net) -> Self {
+        Ipv4Route {
+            destination: net.network().to_string(),
+            prefix_len: net.prefix_len,
+            subnet_mask: net.netmask().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ipv6Route {
+    pub destination: String,
+    pub prefix_len: u8,
+}
+
+/// JSON returned by `get_tunnel_settings`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelSettings {
+    pub version: u32,
+    /// None until DHCP has assigned an address.
+    pub ipv4: Option<Ipv4Setting>,
+    pub ipv6: Option<Ipv6Setting>,
+    /// Virtual subnet, peers and proxied subnets, aggregated.
+    pub routes: Vec<Ipv4Route>,
+    /// Public endpoints of the peers as host routes.
+    pub excluded_routes: Vec<Ipv4Route>,
+    pub excluded_ipv6_routes: Vec<Ipv6Route>,
+    /// Only the magic DNS address while it is enabled and known.
+    pub dns: Vec<String>,
+    pub mtu: u32,
+}
+
+fn parse_ipv6(inet: &str) -> Option<Ipv6Setting> {
+    let (addr, len) = inet.split_once('/').unwrap_or((inet, "128"));
+    let addr: std::net::Ipv6Addr = addr.parse().ok()?;
+    let prefix_len = len.parse::<u8>().ok().filter(|len| *len <= 128)?;
+    Some(Ipv6Setting {
+        address: addr.to_string(),
+        prefix_len,
+    })
+}
+
+// 对端连接的远端地址, 域名和回环地址不需要排除
+fn endpoint_addr(url: &str) -> Option<IpAddr> {
+    let addr = match url::Url::parse(url).ok()?.host()? {
+        url::Host::Ipv4(addr) => IpAddr::V4(addr),
+        url::Host::Ipv6(addr) => IpAddr::V6(addr),
+        // tcp:// 等非标准协议的 IPv4 主机按域名解析
+        url::Host::Domain(host) => IpAddr::V4(host.parse().ok()?),
+    };
+    (!addr.is_loopback() && !addr.is_unspecified()).then_some(addr)
+}
+
+/// Public addresses of the connections to `peers`, sorted and deduplicated.
+pub fn peer_endpoints(peers: &[PeerInfo]) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>) {
+    let (mut v4, mut v6) = (Vec::new(), Vec::new());
+    let remotes = peers
+        .iter()
+        .flat_map(|peer| &peer.conns)
+        .filter_map(|conn| conn.tunnel.as_ref()?.remote_addr.as_ref());
+    for remote in remotes {
+        match endpoint_addr(&remote.url) {
+            Some(IpAddr::V4(addr)) => v4.push(addr),
+            Some(IpAddr::V6(addr)) => v6.push(addr),
+            None => {}
+        }
+    }
+    v4.sort();
+    v4.dedup();
+    v6.sort();
+    v6.dedup();
+    (v4, v6)
+}
+
+/// Compute the settings from the local node, the current route table, the
+/// connected peers and the startup parameters.
+pub fn compute(
+    node: Option<&NodeInfo>,
+    routes: &[Route],
+    peers: &[PeerInfo],
+    params: &TunnelParams,
+) -> TunnelSettings {
+    let local = node
+        .map(|n| n.ipv4_addr.as_str())
+        .filter(|addr| !addr.is_empty())
+        .and_then(|addr| addr.parse::<Ipv4Net>().ok())
+        .filter(|net| net.addr != Ipv4Addr::UNSPECIFIED);
+
+    // 虚拟网段本身, 对端地址和代理网段, 网段内的地址在聚合时去掉
+    let mut included: Vec<Ipv4Net> = local.into_iter().collect();
+    for route in routes {
+        if let Some(peer) = route
+            .ipv4_addr
+            .as_ref()
+            .and_then(|addr| addr.to_string().parse::<Ipv4Net>().ok())
+        {
+            included.push(Ipv4Net {
+                addr: peer.addr,
+                prefix_len: 32,
+            });
+        }
+        for cidr in &route.proxy_cidrs {
+            match cidr.parse::<Ipv4Net>() {
+                Ok(net) => included.push(net),
+                Err(e) => tracing::warn!("ignoring proxy cidr of peer {}: {}", route.peer_id, e),
+            }
+        }
+    }
+    for cidr in &params.routes {
+        match cidr.parse::<Ipv4Net>() {
+            Ok(net) => included.push(net),
+            Err(e) => tracing::warn!("ignoring route {}: {}", cidr, e),
+        }
+    }
+
+    let (v4_endpoints, v6_endpoints) = peer_endpoints(peers);
+    let excluded_routes = v4_endpoints
+        .into_iter()
+        .filter(|addr| !local.is_some_and(|local| local.contains(*addr)))
+        .map(|addr| {
+            Ipv4Route::from(Ipv4Net {
+                addr,
+                prefix_len: 32,
+            })
+        })
+        .collect();
+    let excluded_ipv6_routes = v6_endpoints
+        .into_iter()
+        .map(|addr| Ipv6Route {
+            destination: addr.to_string(),
+            prefix_len: 128,
+        })
+        .collect();
+    let magic_dns = local
+        .filter(|_| params.magic_dns)
+        .and_then(dns::magic_address);
+
+    TunnelSettings {
+        version: TUNNEL_SETTINGS_VERSION,
+        ipv4: local.map(|local| Ipv4Setting {
+            address: local.addr.to_string(),
+            prefix_len: local.prefix_len,
+            subnet_mask: local.netmask().to_string(),
+        }),
+        ipv6: node.and_then(|n| n.ipv6.as_deref()).and_then(parse_ipv6),
+        routes: net::aggregate(included)
+            .into_iter()
+            .map(Ipv4Route::from)
+            .collect(),
+        excluded_routes,
+        excluded_ipv6_routes,
+        dns: match magic_dns {
+            Some(addr) => vec![addr.to_string()],
+            None => params.dns.clone(),
+        },
+        mtu: params.mtu,
+    }
+}
@@ -0,0 +1,94 @@
+//! IPv4 UDP packets for the services the core answers itself.
+//!
+//! Services such as the [magic DNS](crate::dns) talk to the system and to
+//! EasyTier in bare IP packets, these are the few helpers they need to take
+//! a datagram apart and build one.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const PROTO_UDP: u8 = 17;
+
+/// A UDP datagram inside an IPv4 packet.
+pub struct Datagram<'a> {
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub payload: &'a [u8],
+}
+
+/// The UDP datagram in an IPv4 `packet`, `None` for anything else.
+/// Fragments are not reassembled and are skipped as well.
+pub fn parse(packet: &[u8]) -> Option<Datagram<'_>> {
+    let ihl = usize::from(packet.first()? & 0x0f) * 4;
+    if packet[0] >> 4 != 4 || ihl < IPV4_HEADER_LEN || packet.len() < ihl + UDP_HEADER_LEN {
+        return None;
+    }
+    let total = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+    let fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff;
+    if packet[9] != PROTO_UDP
+        || fragment != 0
+        || total > packet.len()
+        || total < ihl + UDP_HEADER_LEN
+    {
+        return None;
+    }
+    let udp = &packet[ihl..total];
+    let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+    if udp_len < UDP_HEADER_LEN || udp_len > udp.len() {
+        return None;
+    }
+    let addr =
+        |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+    let port = |at: usize| u16::from_be_bytes([udp[at], udp[at + 1]]);
+    Some(Datagram {
+        src: SocketAddrV4::new(addr(12), port(0)),
+        dst: SocketAddrV4::new(addr(16), port(2)),
+        payload: &udp[UDP_HEADER_LEN..udp_len],
+    })
+}
+
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for chunk in part.chunks(2) {
+            sum += u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]));
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// An IPv4 packet carrying `payload` from `src` to `dst`.
+pub fn packet(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+    let total = IPV4_HEADER_LEN as u16 + udp_len;
+    let mut packet = Vec::with_capacity(usize::from(total));
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total.to_be_bytes());
+    // 标识, DF, TTL, 协议, 校验和
+    packet.extend_from_slice(&[0, 0, 0x40, 0, 64, PROTO_UDP, 0, 0]);
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dst.ip().octets());
+    let ip_checksum = checksum(&[&packet]);
+    packet[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dst.port().to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    let mut pseudo = [0u8; 12];
+    pseudo[..4].copy_from_slice(&src.ip().octets());
+    pseudo[4..8].copy_from_slice(&dst.ip().octets());
+    pseudo[9] = PROTO_UDP;
+    pseudo[10..].copy_from_slice(&udp_len.to_be_bytes());
+    let udp_checksum = match checksum(&[&pseudo, &packet[IPV4_HEADER_LEN..]]) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[IPV4_HEADER_LEN + 6..IPV4_HEADER_LEN + 8].copy_from_slice(&udp_checksum.to_be_bytes());
+    packet
+}
@@ -0,0 +1,350 @@
+//! Static checks of a startup configuration, run before any tunnel exists.
+//!
+//! Accepts the same input as `run_network_instance`. Findings inside the
+//! TOML carry the key path and the line/column of the offending value;
+//! findings on envelope fields carry the JSON field name.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::net::Ipv4Net;
+use crate::options::{ConfigFormat, TerracottaOptions};
+
+/// Smallest MTU every IPv4 host must accept.
+pub const MIN_MTU: u32 = 576;
+/// Anything above this is fragmented on typical Wi-Fi and cellular links.
+pub const MAX_MTU: u32 = 1500;
+
+/// Transports EasyTier can listen on.
+pub const LISTENER_SCHEMES: &[&str] = &["tcp", "udp", "ws", "wss", "wg", "quic"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Dotted key path such as `flags.mtu` or `listeners[1]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 1-based position inside the TOML text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// False if at least one diagnostic is an error.
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+    }
+}
+
+// 只解析需要检查的字段, 其余字段交给 EasyTier 处理
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RawConfig {
+    ipv4: Option<Spanned<String>>,
+    listeners: Vec<Spanned<String>>,
+    network_identity: Option<Spanned<RawIdentity>>,
+    proxy_network: Vec<RawProxyNetwork>,
+    flags: Option<RawFlags>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RawIdentity {
+    network_name: Option<Spanned<String>>,
+    network_secret: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+struct RawProxyNetwork {
+    cidr: Spanned<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RawFlags {
+    mtu: Option<Spanned<i64>>,
+}
+
+struct Checker<'a> {
+    toml: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn push(
+        &mut self,
+        severity: Severity,
+        path: impl Into<String>,
+        span: Option<Range<usize>>,
+        message: String,
+    ) {
+        let (line, column) = match span {
+            Some(span) => {
+                let (line, column) = line_column(self.toml, span.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path: Some(path.into()).filter(|p: &String| !p.is_empty()),
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn error(&mut self, path: impl Into<String>, span: Option<Range<usize>>, message: String) {
+        self.push(Severity::Error, path, span, message);
+    }
+
+    fn warning(&mut self, path: impl Into<String>, span: Option<Range<usize>>, message: String) {
+        self.push(Severity::Warning, path, span, message);
+    }
+
+    fn check_toml(&mut self, options: Option<&TerracottaOptions>) {
+        let raw: RawConfig = match toml::from_str(self.toml) {
+            Ok(raw) => raw,
+            Err(e) => {
+                let message = e.message().to_string();
+                self.error("", e.span(), message);
+                return;
+            }
+        };
+
+        for (i, listener) in raw.listeners.iter().enumerate() {
+            if let Err(e) = check_listener(listener.get_ref()) {
+                self.error(format!("listeners[{}]", i), Some(listener.span()), e);
+            }
+        }
+
+        self.check_identity(raw.network_identity.as_ref());
+
+        let mtu_overridden = options.is_some_and(|o| o.mtu.is_some());
+        if let Some(mtu) = raw.flags.as_ref().and_then(|f| f.mtu.as_ref()) {
+            if !mtu_overridden && !mtu_in_range(*mtu.get_ref()) {
+                self.error("flags.mtu", Some(mtu.span()), mtu_message(*mtu.get_ref()));
+            }
+        }
+
+        self.check_subnets(&raw, options);
+    }
+
+    fn check_identity(&mut self, identity: Option<&Spanned<RawIdentity>>) {
+        let Some(identity) = identity else {
+            self.error(
+                "network_identity",
+                None,
+                "missing [network_identity] table".to_string(),
+            );
+            return;
+        };
+        let inner = identity.get_ref();
+        match &inner.network_secret {
+            Some(secret) if secret.get_ref().is_empty() => self.error(
+                "network_identity.network_secret",
+                Some(secret.span()),
+                "network_secret is empty".to_string(),
+            ),
+            Some(_) => {}
+            None => self.error(
+                "network_identity.network_secret",
+                Some(identity.span()),
+                "network_secret is missing, anyone knowing the network name could join".to_string(),
+            ),
+        }
+        if inner
+            .network_name
+            .as_ref()
+            .is_none_or(|n| n.get_ref().is_empty())
+        {
+            self.warning(
+                "network_identity.network_name",
+                Some(identity.span()),
+                "network_name is not set, EasyTier falls back to \"default\"".to_string(),
+            );
+        }
+    }
+
+    /// The virtual subnet DHCP allocates from must not collide with any proxied
+    /// subnet, and proxied subnets must not collide with each other.
+    fn check_subnets(&mut self, raw: &RawConfig, options: Option<&TerracottaOptions>) {
+        let mut subnets: Vec<(String, Option<Range<usize>>, Ipv4Net)> = Vec::new();
+
+        // 外层 JSON 中的 ipv4 会覆盖 TOML 中的值
+        match options.and_then(|o| o.ipv4.as_ref()) {
+            Some(ipv4) => {
+                if let Ok(net) = ipv4.parse::<Ipv4Net>() {
+                    subnets.push(("ipv4".to_string(), None, net));
+                }
+            }
+            None => {
+                if let Some(ipv4) = &raw.ipv4 {
+                    match ipv4.get_ref().parse::<Ipv4Net>() {
+                        Ok(net) => subnets.push(("ipv4".to_string(), Some(ipv4.span()), net)),
+                        Err(e) => self.error("ipv4", Some(ipv4.span()), e),
+                    }
+                }
+            }
+        }
+
+        for (i, proxy) in raw.proxy_network.iter().enumerate() {
+            let path = format!("proxy_network[{}].cidr", i);
+            match proxy.cidr.get_ref().parse::<Ipv4Net>() {
+                Ok(net) => subnets.push((path, Some(proxy.cidr.span()), net)),
+                Err(e) => self.error(path, Some(proxy.cidr.span()), e),
+            }
+        }
+
+        for i in 0..subnets.len() {
+            for j in i + 1..subnets.len() {
+                let (first, _, a) = &subnets[i];
+                let (path, span, b) = &subnets[j];
+                if a.overlaps(b) {
+                    let message = format!(
+                        "{} ({}) overlaps {} ({})",
+                        path,
+                        b.trunc(),
+                        first,
+                        a.trunc()
+                    );
+                    self.error(path.clone(), span.clone(), message);
+                }
+            }
+        }
+    }
+
+    fn check_options(&mut self, options: &TerracottaOptions) {
+        if let Some(ipv4) = &options.ipv4 {
+            if let Err(e) = ipv4.parse::<Ipv4Net>() {
+                self.error("ipv4", None, e);
+            }
+        }
+        if let Some(ipv6) = &options.ipv6 {
+            if let Err(e) = check_ipv6(ipv6) {
+                self.error("ipv6", None, e);
+            }
+        }
+        if let Some(mtu) = options.mtu {
+            if !mtu_in_range(mtu as i64) {
+                self.error("mtu", None, mtu_message(mtu as i64));
+            }
+        }
+        for (i, route) in options.routes.iter().enumerate() {
+            if let Err(e) = route.parse::<Ipv4Net>() {
+                self.error(format!("routes[{}]", i), None, e);
+            }
+        }
+        for (i, dns) in options.dns.iter().enumerate() {
+            if dns.parse::<std::net::IpAddr>().is_err() {
+                self.error(
+                    format!("dns[{}]", i),
+                    None,
+                    format!("'{}' is not an IP address", dns),
+                );
+            }
+        }
+    }
+}
+
+fn check_listener(listener: &str) -> Result<(), String> {
+    let url = url::Url::parse(listener)
+        .map_err(|e| format!("'{}' is not a valid URL: {}", listener, e))?;
+    if !LISTENER_SCHEMES.contains(&url.scheme()) {
+        return Err(format!(
+            "unsupported listener scheme '{}', expected one of {}",
+            url.scheme(),
+            LISTENER_SCHEMES.join(", ")
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(format!("listener '{}' has no host", listener));
+    }
+    if url.port().is_none() {
+        return Err(format!("listener '{}' has no port", listener));
+    }
+    Ok(())
+}
+
+fn check_ipv6(ipv6: &str) -> Result<(), String> {
+    let (addr, len) = ipv6.split_once('/').unwrap_or((ipv6, "128"));
+    addr.parse::<std::net::Ipv6Addr>()
+        .map_err(|_| format!("'{}' is not an IPv6 address", addr))?;
+    match len.parse::<u8>() {
+        Ok(len) if len <= 128 => Ok(()),
+        _ => Err(format!("'{}' is not a valid prefix length", len)),
+    }
+}
+
+fn mtu_in_range(mtu: i64) -> bool {
+    (MIN_MTU as i64..=MAX_MTU as i64).contains(&mtu)
+}
+
+fn mtu_message(mtu: i64) -> String {
+    format!(
+        "mtu {} is out of range, expected {}..={}",
+        mtu, MIN_MTU, MAX_MTU
+    )
+}
+
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, column)
+}
+
+/// Check `cfg_str`, given either as EasyTier TOML or a `TerracottaOptions`
+/// JSON envelope.
+pub fn validate(cfg_str: &str) -> Report {
+    let mut checker = Checker {
+        toml: cfg_str,
+        diagnostics: Vec::new(),
+    };
+
+    match ConfigFormat::detect(cfg_str) {
+        ConfigFormat::Toml => checker.check_toml(None),
+        ConfigFormat::Json => match TerracottaOptions::from_json(cfg_str) {
+            Ok(options) => {
+                checker.check_options(&options);
+                if options.config.trim().is_empty() {
+                    checker.error("config", None, "config is empty".to_string());
+                } else {
+                    let mut inner = Checker {
+                        toml: &options.config,
+                        diagnostics: std::mem::take(&mut checker.diagnostics),
+                    };
+                    inner.check_toml(Some(&options));
+                    checker.diagnostics = inner.diagnostics;
+                }
+            }
+            Err(e) => checker.error("", None, e),
+        },
+    }
+
+    let diagnostics = checker.diagnostics;
+    Report {
+        valid: !diagnostics.iter().any(|d| d.severity == Severity::Error),
+        diagnostics,
+    }
+}
@@ -0,0 +1,182 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+
+use easytier::proto::api::instance::{NodeInfo, Route};
+use easytier::proto::common::{self, Ipv4Inet};
+use terracotta_ios::bedrock::{self, BedrockRelay, Ping, Pong, ServerInfo};
+use terracotta_ios::lan::{self, Overlay};
+use terracotta_ios::shutdown::{StopReason, StopState};
+use terracotta_ios::{runtime, tun, udp};
+use tokio::sync::mpsc;
+
+const PONG_TEXT: &str =
+    "MCPE;Steve's world;712;1.21.20;1;8;11893756245723415243;Bedrock level;Survival;1;19132;19133;";
+
+fn route(addr: [u8; 4]) -> Route {
+    Route {
+        ipv4_addr: Some(Ipv4Inet {
+            address: Some(common::Ipv4Addr {
+                addr: u32::from_be_bytes(addr),
+            }),
+            network_length: 16,
+        }),
+        ..Default::default()
+    }
+}
+
+/// The overlay of the guest `10.14.0.2` next to the host `10.14.0.1`.
+fn overlay() -> Arc<Overlay> {
+    let overlay = Arc::new(Overlay::default());
+    let node = NodeInfo {
+        ipv4_addr: "10.14.0.2/16".to_string(),
+        ..Default::default()
+    };
+    overlay.update(Some(&node), &[route([10, 14, 0, 1])]);
+    overlay
+}
+
+fn host_pong() -> Pong {
+    Pong {
+        time: 1234,
+        server_guid: 0x0102_0304_0506_0708,
+        server: ServerInfo::parse(PONG_TEXT).unwrap(),
+    }
+}
+
+#[test]
+fn parses_pings_and_pongs() {
+    let info = ServerInfo::parse(PONG_TEXT).unwrap();
+    assert_eq!(info.motd, "Steve's world");
+    assert_eq!((info.protocol, info.version.as_str()), (712, "1.21.20"));
+    assert_eq!((info.players, info.max_players), (1, 8));
+    assert_eq!(info.level_name, "Bedrock level");
+    assert_eq!((info.port_v4, info.port_v6), (Some(19132), Some(19133)));
+    assert_eq!(info.to_string(), PONG_TEXT);
+
+    // 旧版本只有前六个字段
+    let old = ServerInfo::parse("MCPE;Old world;390;1.14.60;0;10").unwrap();
+    assert_eq!((old.level_name.as_str(), old.port_v4), ("", None));
+    assert_eq!(ServerInfo::parse("MCPE;x;712;1.21.20;one;8;"), None);
+    assert_eq!(ServerInfo::parse("Java;x;712;1.21.20;1;8;"), None);
+
+    let ping = Ping {
+        time: 42,
+        client_guid: -7,
+    };
+    let bytes = ping.to_bytes();
+    assert_eq!((bytes[0], bytes.len()), (0x01, 33));
+    assert_eq!(bytes[9..25], bedrock::MAGIC);
+    assert_eq!(Ping::parse(&bytes), Some(ping));
+
+    let pong = host_pong();
+    let bytes = pong.to_bytes();
+    assert_eq!(bytes[0], 0x1c);
+    assert_eq!(bytes[17..33], bedrock::MAGIC);
+    assert_eq!(Pong::parse(&bytes), Some(pong));
+
+    let mut wrong_magic = ping.to_bytes();
+    wrong_magic[12] ^= 0xff;
+    assert_eq!(Ping::parse(&wrong_magic), None);
+    assert_eq!(Pong::parse(&ping.to_bytes()), None);
+}
+
+#[test]
+fn pings_every_peer() {
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let easytier = UnixDatagram::from(easytier);
+    easytier
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let overlay = overlay();
+    overlay.attach(core).unwrap();
+    let relay = BedrockRelay::new(overlay);
+
+    assert_eq!(runtime::block_on(relay.ping_peers(50000)).unwrap(), 1);
+    let mut buf = [0u8; 1500];
+    let n = easytier.recv(&mut buf).unwrap();
+    let datagram = udp::parse(&buf[..n]).unwrap();
+    assert_eq!(
+        (datagram.src, datagram.dst),
+        (
+            SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 2), 50000),
+            SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 1), bedrock::BEDROCK_PORT)
+        )
+    );
+    assert!(Ping::parse(datagram.payload).is_some());
+}
+
+#[test]
+fn answers_local_pings_for_peer_worlds() {
+    let relay = BedrockRelay::new(overlay());
+    let (injector, mut system) = mpsc::channel(8);
+    relay.attach_system(injector);
+
+    let host = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 1), bedrock::BEDROCK_PORT);
+    let stranger = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 9), bedrock::BEDROCK_PORT);
+    assert!(!relay.record(stranger, &host_pong().to_bytes()));
+    assert!(!relay.record(host, b"not a pong"));
+    assert!(relay.record(host, &host_pong().to_bytes()));
+    assert_eq!(relay.worlds(), [(host, host_pong())]);
+
+    let client = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 53000);
+    let ping = Ping {
+        time: 99,
+        client_guid: 5,
+    };
+    assert_eq!(
+        runtime::block_on(relay.answer(client, &ping.to_bytes())).unwrap(),
+        1
+    );
+    assert_eq!(
+        runtime::block_on(relay.answer(client, b"\x05 open connection")).unwrap(),
+        0
+    );
+
+    let packet = system.try_recv().unwrap();
+    let datagram = udp::parse(&packet).unwrap();
+    assert_eq!((datagram.src, datagram.dst), (host, client));
+    let pong = Pong::parse(datagram.payload).unwrap();
+    assert_eq!(pong.time, 99, "the client's time is echoed");
+    assert_eq!(pong.server, host_pong().server);
+    assert!(system.try_recv().is_err());
+}
+
+#[test]
+fn leaves_unicast_pings_to_a_local_server() {
+    // 像游戏一样共享端口, 中继若也绑上 19132 就会分走单播
+    let server = lan::shared_socket(SocketAddrV4::new(
+        Ipv4Addr::UNSPECIFIED,
+        bedrock::BEDROCK_PORT,
+    ))
+    .unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert!(bedrock::local_server_running());
+
+    let stop_state = StopState::new();
+    let relay = Arc::new(BedrockRelay::new(overlay()));
+    runtime::spawn(bedrock::run(relay, stop_state.clone())).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let ping = Ping {
+        time: 7,
+        client_guid: 3,
+    };
+    let mut buf = [0u8; 1500];
+    for _ in 0..8 {
+        // 每次换一个源端口, 端口按四元组分配时也能覆盖到
+        let peer = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.send_to(
+            &ping.to_bytes(),
+            (Ipv4Addr::LOCALHOST, bedrock::BEDROCK_PORT),
+        )
+        .unwrap();
+        let (n, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(from, peer.local_addr().unwrap());
+        assert_eq!(Ping::parse(&buf[..n]), Some(ping));
+    }
+    stop_state.finish(StopReason::Clean);
+}
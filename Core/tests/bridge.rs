@@ -0,0 +1,180 @@
+//! EasyTier's side of the packet bridge is the other end of a
+//! [`tun::nic_pair`].
+
+use std::ffi::c_void;
+use std::os::unix::net::UnixDatagram;
+use std::ptr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use terracotta_ios::bridge::{self, OutboundCallback, PacketBridge};
+use terracotta_ios::config::{Role, RoomConfig};
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::instance::{self, Instance};
+use terracotta_ios::room_code::RoomCode;
+use terracotta_ios::tun::{self, TunStatsSnapshot};
+use terracotta_ios::*;
+
+const IPV4_PACKET: [u8; 20] = [
+    0x45, 0, 0, 20, 0, 0, 0x40, 0, 64, 17, 0, 0, 10, 144, 144, 1, 10, 144, 144, 2,
+];
+
+type Batch = Vec<Vec<u8>>;
+
+extern "C" fn on_packets(user_data: *mut c_void, buf: *const u8, lens: *const usize, count: usize) {
+    let tx = unsafe { &*(user_data as *const mpsc::Sender<Batch>) };
+    let lens = unsafe { std::slice::from_raw_parts(lens, count) };
+    let buf = unsafe { std::slice::from_raw_parts(buf, lens.iter().sum()) };
+    let packets = bridge::split_packets(buf, lens).unwrap();
+    tx.send(packets.into_iter().map(<[u8]>::to_vec).collect())
+        .unwrap();
+}
+
+fn outbound_callback() -> (OutboundCallback, mpsc::Receiver<Batch>) {
+    let (tx, rx) = mpsc::channel();
+    let user_data = Box::leak(Box::new(tx)) as *mut mpsc::Sender<Batch> as *mut c_void;
+    (OutboundCallback::new(on_packets, user_data), rx)
+}
+
+fn bridge() -> (PacketBridge, UnixDatagram, mpsc::Receiver<Batch>) {
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let (callback, rx) = outbound_callback();
+    let bridge = PacketBridge::start(core, callback, None, None).unwrap();
+    let easytier = UnixDatagram::from(easytier);
+    easytier
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (bridge, easytier, rx)
+}
+
+/// Counters are updated after the packet is sent, poll until they settle.
+fn wait_for(bridge: &PacketBridge, done: impl Fn(&TunStatsSnapshot) -> bool) -> TunStatsSnapshot {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stats = bridge.stats();
+        if done(&stats) {
+            return stats;
+        }
+        assert!(Instant::now() < deadline, "{:?}", stats);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn ipv6_packet(len: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; len];
+    packet[0] = 0x60;
+    packet
+}
+
+#[test]
+fn splits_packets_by_length() {
+    let buf = [1, 2, 3, 4, 5, 6];
+    let packets = bridge::split_packets(&buf, &[1, 0, 3]).unwrap();
+    assert_eq!(packets, [&[1][..], &[][..], &[2, 3, 4][..]]);
+    assert!(bridge::split_packets(&buf, &[4, 3]).is_none());
+}
+
+#[test]
+fn pushed_packets_reach_easytier() {
+    let (bridge, easytier, _rx) = bridge();
+    let ipv6 = ipv6_packet(48);
+    bridge.push([&IPV4_PACKET[..], &[0x10, 0, 0][..], &[][..], &ipv6[..]]);
+
+    let mut buf = [0u8; 2048];
+    let n = easytier.recv(&mut buf).unwrap();
+    assert_eq!(buf[..n], IPV4_PACKET);
+    let n = easytier.recv(&mut buf).unwrap();
+    assert_eq!(buf[..n], ipv6[..]);
+
+    let stats = wait_for(&bridge, |s| s.inbound_packets == 2);
+    assert_eq!(stats.inbound_packets, 2);
+    assert_eq!(stats.inbound_bytes, (IPV4_PACKET.len() + ipv6.len()) as u64);
+    assert_eq!(stats.malformed, 2);
+    assert_eq!(stats.inbound_ring.dropped, 0);
+    assert_eq!(
+        stats.inbound_ring.bytes_copied,
+        (IPV4_PACKET.len() + ipv6.len()) as u64
+    );
+}
+
+#[test]
+fn easytier_packets_arrive_in_batches() {
+    let (bridge, easytier, rx) = bridge();
+    let mut sent = Vec::new();
+    for i in 0..100u8 {
+        let mut packet = ipv6_packet(40 + i as usize);
+        packet[1] = i;
+        easytier.send(&packet).unwrap();
+        sent.push(packet);
+    }
+    easytier.send(&[0x20]).unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < sent.len() {
+        let batch = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!batch.is_empty() && batch.len() <= bridge::OUTBOUND_BATCH);
+        received.extend(batch);
+    }
+    assert_eq!(received, sent);
+
+    let stats = wait_for(&bridge, |s| s.malformed == 1 && s.outbound_packets == 100);
+    // 出站包直接从环中交给回调
+    assert_eq!(stats.outbound_ring.bytes_copied, 0);
+}
+
+#[test]
+fn push_is_dropped_when_easytier_lags() {
+    let (bridge, _easytier, _rx) = bridge();
+    let packet = ipv6_packet(1400);
+    // 对端不读取, socket 缓冲区和环都写满后丢包
+    let packets = vec![&packet[..]; 4096];
+    bridge.push(packets);
+
+    let ring = bridge.stats().inbound_ring;
+    assert!(ring.dropped > 0 && ring.full > 0);
+    assert_eq!(ring.packets + ring.dropped, 4096);
+}
+
+#[test]
+fn push_requires_an_attached_bridge() {
+    let code: RoomCode = "U/AB12-CD34-EF56-GH70".parse().unwrap();
+    let toml = RoomConfig::for_room(&code, Role::Guest)
+        .build()
+        .to_toml()
+        .unwrap();
+    let handle = instance::insert(Instance::new(&toml).unwrap());
+
+    let lens = [IPV4_PACKET.len()];
+    let mut err = ptr::null();
+    let code =
+        tc_instance_push_inbound_packets(handle, IPV4_PACKET.as_ptr(), lens.as_ptr(), 1, &mut err);
+    assert_eq!(code, TcErrorCode::NotRunning);
+    tc_string_free(err);
+
+    let mut err = ptr::null();
+    let code = tc_instance_set_outbound_packet_callback(handle, None, ptr::null_mut(), &mut err);
+    assert_eq!(code, TcErrorCode::InvalidArgument);
+    tc_string_free(err);
+
+    let mut err = ptr::null();
+    let code = tc_instance_push_inbound_packets(handle, ptr::null(), lens.as_ptr(), 1, &mut err);
+    assert_eq!(code, TcErrorCode::InvalidArgument);
+    tc_string_free(err);
+
+    // 空批次不需要任何参数
+    let code =
+        tc_instance_push_inbound_packets(handle, ptr::null(), ptr::null(), 0, ptr::null_mut());
+    assert_eq!(code, TcErrorCode::Ok);
+
+    assert!(instance::free(handle));
+}
+
+#[test]
+fn stats_require_packet_io() {
+    let mut stats = ptr::null();
+    let mut err = ptr::null();
+    let code = tc_instance_get_tun_stats(u64::MAX, &mut stats, &mut err);
+    assert_eq!(code, TcErrorCode::InvalidHandle);
+    assert!(stats.is_null());
+    tc_string_free(err);
+}
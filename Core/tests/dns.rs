@@ -0,0 +1,281 @@
+use std::ffi::c_void;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use easytier::proto::api::instance::{NodeInfo, Route};
+use easytier::proto::common::{self, Ipv4Inet};
+use terracotta_ios::bridge::{self, OutboundCallback, PacketBridge};
+use terracotta_ios::dns::{self, Lookup, MagicDns};
+use terracotta_ios::net::Ipv4Net;
+use terracotta_ios::runtime;
+use terracotta_ios::scaffolding;
+use terracotta_ios::tun;
+
+const MAGIC_DNS: Ipv4Addr = Ipv4Addr::new(10, 14, 255, 254);
+
+fn route(hostname: &str, addr: [u8; 4]) -> Route {
+    Route {
+        hostname: hostname.to_string(),
+        ipv4_addr: Some(Ipv4Inet {
+            address: Some(common::Ipv4Addr {
+                addr: u32::from_be_bytes(addr),
+            }),
+            network_length: 16,
+        }),
+        ..Default::default()
+    }
+}
+
+/// A guest in room `ab12-cd34` next to the host, the player Steve and the
+/// player Alex.
+fn magic_dns(upstreams: Vec<SocketAddr>) -> MagicDns {
+    let dns = MagicDns::new(dns::room_label("terracotta-mc-ab12-cd34"), upstreams);
+    let node = NodeInfo {
+        hostname: "Guest".to_string(),
+        ipv4_addr: "10.14.23.7/16".to_string(),
+        ..Default::default()
+    };
+    let routes = [
+        route(&scaffolding::host_hostname(13448), [10, 14, 0, 1]),
+        route("Steve", [10, 14, 0, 3]),
+        route("Alex's iPhone", [10, 14, 0, 2]),
+    ];
+    dns.update(Some(&node), &routes);
+    dns
+}
+
+fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut msg = id.to_be_bytes().to_vec();
+    msg.extend_from_slice(&[0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&1u16.to_be_bytes());
+    msg
+}
+
+fn answer(dns: &MagicDns, query: &[u8]) -> Vec<u8> {
+    runtime::block_on(dns.answer(query)).unwrap().unwrap()
+}
+
+fn rcode(reply: &[u8]) -> u8 {
+    reply[3] & 0x0f
+}
+
+fn answers(reply: &[u8]) -> u16 {
+    u16::from_be_bytes([reply[6], reply[7]])
+}
+
+fn answered_addr(reply: &[u8]) -> Ipv4Addr {
+    let addr: [u8; 4] = reply[reply.len() - 4..].try_into().unwrap();
+    Ipv4Addr::from(addr)
+}
+
+/// Internet checksum over `data` folded into 16 bits, `0xffff` when the
+/// embedded checksum is right.
+fn ones_sum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+fn udp_packet(src: [u8; 4], dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+    let total = (28 + payload.len()) as u16;
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&total.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+    packet.extend_from_slice(&src);
+    packet.extend_from_slice(&dst.octets());
+    packet.extend_from_slice(&40000u16.to_be_bytes());
+    packet.extend_from_slice(&53u16.to_be_bytes());
+    packet.extend_from_slice(&(total - 20).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+extern "C" fn on_packets(user_data: *mut c_void, buf: *const u8, lens: *const usize, count: usize) {
+    let tx = unsafe { &*(user_data as *const mpsc::Sender<Vec<u8>>) };
+    let lens = unsafe { std::slice::from_raw_parts(lens, count) };
+    let buf = unsafe { std::slice::from_raw_parts(buf, lens.iter().sum()) };
+    for packet in bridge::split_packets(buf, lens).unwrap() {
+        tx.send(packet.to_vec()).unwrap();
+    }
+}
+
+#[test]
+fn derives_labels_and_addresses() {
+    assert_eq!(dns::dns_label("Alex's iPhone").unwrap(), "alex-s-iphone");
+    assert_eq!(dns::dns_label("  Steve--42 ").unwrap(), "steve-42");
+    assert_eq!(dns::dns_label("史蒂夫"), None);
+    assert_eq!(
+        dns::room_label("terracotta-mc-ab12-cd34").unwrap(),
+        "ab12-cd34"
+    );
+
+    let subnet = |cidr: &str| cidr.parse::<Ipv4Net>().unwrap();
+    assert_eq!(dns::magic_address(subnet("10.14.23.7/16")), Some(MAGIC_DNS));
+    assert_eq!(
+        dns::magic_address(subnet("192.168.7.1/24")),
+        Some(Ipv4Addr::new(192, 168, 7, 254))
+    );
+    assert_eq!(dns::magic_address(subnet("10.0.0.1/31")), None);
+
+    let servers = ["10.0.0.1:5353", "8.8.8.8", "bogus"].map(String::from);
+    assert_eq!(
+        dns::upstreams(&servers),
+        [
+            "10.0.0.1:5353".parse().unwrap(),
+            "8.8.8.8:53".parse::<SocketAddr>().unwrap()
+        ]
+    );
+    assert_eq!(dns::upstreams(&[]).len(), dns::DEFAULT_UPSTREAMS.len());
+}
+
+#[test]
+fn resolves_players_and_the_host() {
+    let dns = magic_dns(Vec::new());
+    assert_eq!(dns.address(), Some(MAGIC_DNS));
+
+    let found = |addr: [u8; 4]| Lookup::Found(addr.into());
+    assert_eq!(dns.lookup("host.terracotta"), found([10, 14, 0, 1]));
+    assert_eq!(
+        dns.lookup("HOST.ab12-cd34.terracotta."),
+        found([10, 14, 0, 1])
+    );
+    assert_eq!(dns.lookup("steve.terracotta"), found([10, 14, 0, 3]));
+    assert_eq!(
+        dns.lookup("alex-s-iphone.ab12-cd34.terracotta"),
+        found([10, 14, 0, 2])
+    );
+    assert_eq!(dns.lookup("guest.terracotta"), found([10, 14, 23, 7]));
+
+    assert_eq!(dns.lookup("herobrine.terracotta"), Lookup::NotFound);
+    assert_eq!(dns.lookup("host.zz99-zz99.terracotta"), Lookup::NotFound);
+    assert_eq!(dns.lookup("terracotta"), Lookup::NotFound);
+    assert_eq!(dns.lookup("example.com"), Lookup::Forward);
+    assert_eq!(dns.lookup("notterracotta"), Lookup::Forward);
+}
+
+#[test]
+fn finds_the_host_by_hostname_not_address() {
+    let dns = MagicDns::new(None, Vec::new());
+    let node = NodeInfo {
+        hostname: "Guest".to_string(),
+        ipv4_addr: "10.144.144.2/24".to_string(),
+        ..Default::default()
+    };
+    // PC 房主通过 DHCP 获得地址, 10.14.0.1 上的玩家不是房主
+    let routes = [
+        route("Steve", [10, 14, 0, 1]),
+        route("scaffolding-mc-server-40123", [10, 144, 144, 7]),
+    ];
+    dns.update(Some(&node), &routes);
+    assert_eq!(
+        dns.lookup("host.terracotta"),
+        Lookup::Found([10, 144, 144, 7].into())
+    );
+
+    dns.update(Some(&node), &routes[..1]);
+    assert_eq!(dns.lookup("host.terracotta"), Lookup::NotFound);
+    assert_eq!(scaffolding::host_port("scaffolding-mc-server-x"), None);
+}
+
+#[test]
+fn answers_magic_names() {
+    let dns = magic_dns(Vec::new());
+
+    let reply = answer(&dns, &query(0x1234, "host.terracotta", 1));
+    assert_eq!(reply[..2], [0x12, 0x34]);
+    assert_eq!(reply[2] & 0x85, 0x85, "response, authoritative, RD kept");
+    assert_eq!((rcode(&reply), answers(&reply)), (0, 1));
+    assert_eq!(answered_addr(&reply), Ipv4Addr::new(10, 14, 0, 1));
+
+    // AAAA 和 SRV 查询得到没有记录的成功应答
+    for qtype in [28, 33] {
+        let reply = answer(&dns, &query(7, "steve.terracotta", qtype));
+        assert_eq!((rcode(&reply), answers(&reply)), (0, 0));
+    }
+
+    let reply = answer(&dns, &query(8, "herobrine.terracotta", 1));
+    assert_eq!((rcode(&reply), answers(&reply)), (3, 0));
+
+    // 应答不再回答
+    let mut response = query(9, "host.terracotta", 1);
+    response[2] |= 0x80;
+    assert_eq!(runtime::block_on(dns.answer(&response)).unwrap(), None);
+}
+
+#[test]
+fn forwards_other_names_upstream() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let (n, from) = upstream.recv_from(&mut buf).unwrap();
+        let mut reply = buf[..n].to_vec();
+        reply[2] |= 0x80;
+        reply.extend_from_slice(b"upstream");
+        upstream.send_to(&reply, from).unwrap();
+        buf[..n].to_vec()
+    });
+
+    let dns = magic_dns(vec![upstream_addr]);
+    let query = query(42, "example.com", 1);
+    let reply = answer(&dns, &query);
+    assert_eq!(server.join().unwrap(), query);
+    assert!(reply.ends_with(b"upstream"));
+    assert_eq!(reply[..2], query[..2]);
+}
+
+#[test]
+fn fails_when_no_upstream_answers() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let dns = magic_dns(vec![silent.local_addr().unwrap()]);
+    let reply = answer(&dns, &query(5, "example.com", 1));
+    assert_eq!((rcode(&reply), answers(&reply)), (2, 0));
+}
+
+#[test]
+fn bridge_answers_queries_instead_of_easytier() {
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let user_data = Box::leak(Box::new(tx)) as *mut mpsc::Sender<Vec<u8>> as *mut c_void;
+    let callback = OutboundCallback::new(on_packets, user_data);
+    let bridge =
+        PacketBridge::start(core, callback, Some(Arc::new(magic_dns(Vec::new()))), None).unwrap();
+
+    let packet = udp_packet([10, 14, 23, 7], MAGIC_DNS, &query(3, "host.terracotta", 1));
+    bridge.push([&packet[..]]);
+    let reply = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert_eq!(reply[..1], [0x45]);
+    assert_eq!(reply[12..16], MAGIC_DNS.octets());
+    assert_eq!(reply[16..20], [10, 14, 23, 7]);
+    assert_eq!(reply[20..24], [0, 53, 0x9c, 0x40]);
+    assert_eq!(ones_sum(&reply[..20]), 0xffff);
+    let pseudo = [&reply[12..20], &[0, 17], &reply[24..26], &reply[20..]].concat();
+    assert_eq!(ones_sum(&pseudo), 0xffff);
+    assert_eq!(answered_addr(&reply), Ipv4Addr::new(10, 14, 0, 1));
+
+    // 查询没有交给 EasyTier, 其他包照常转发
+    let other = udp_packet([10, 14, 23, 7], Ipv4Addr::new(10, 14, 0, 1), b"ping");
+    bridge.push([&other[..]]);
+    let easytier = UnixDatagram::from(easytier);
+    easytier
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buf = [0u8; 1500];
+    let n = easytier.recv(&mut buf).unwrap();
+    assert_eq!(buf[..n], other[..]);
+}
@@ -0,0 +1,113 @@
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use terracotta_ios::error::{self, TcError, TcErrorCode};
+use terracotta_ios::*;
+
+fn take(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let s = unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned();
+    tc_string_free(ptr);
+    Some(s)
+}
+
+#[derive(Debug)]
+struct Inner;
+
+impl std::fmt::Display for Inner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connection refused")
+    }
+}
+
+impl std::error::Error for Inner {}
+
+#[derive(Debug)]
+struct Outer(Inner);
+
+impl std::fmt::Display for Outer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to connect to peer")
+    }
+}
+
+impl std::error::Error for Outer {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[test]
+fn cause_chain_is_kept() {
+    let e = TcError::from_error(TcErrorCode::Network, &Outer(Inner)).context("failed to start");
+    assert_eq!(e.message, "failed to start");
+    assert_eq!(
+        e.causes,
+        ["failed to connect to peer", "connection refused"]
+    );
+    assert_eq!(
+        e.to_string(),
+        "failed to start: failed to connect to peer: connection refused"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&e.to_json()).unwrap();
+    assert_eq!(json["code"], -6);
+    assert_eq!(json["name"], "network");
+    assert_eq!(json["causes"][1], "connection refused");
+}
+
+#[test]
+fn exports_return_specific_codes() {
+    let mut err = ptr::null();
+    assert_eq!(
+        tc_instance_start(u64::MAX, &mut err),
+        TcErrorCode::InvalidHandle
+    );
+    assert!(take(err).unwrap().contains("invalid instance handle"));
+
+    let json: serde_json::Value = serde_json::from_str(&take(tc_last_error()).unwrap()).unwrap();
+    assert_eq!(json["code"], -8);
+    assert_eq!(json["name"], "invalid_handle");
+
+    let mut err = ptr::null();
+    assert_eq!(
+        run_network_instance(ptr::null(), &mut err),
+        TcErrorCode::InvalidArgument
+    );
+    take(err);
+
+    let cfg = CString::new(r#"{"version": 99, "config": "x = 1"}"#).unwrap();
+    let mut handle = 1;
+    let mut err = ptr::null();
+    assert_eq!(
+        tc_instance_new(cfg.as_ptr(), &mut handle, &mut err),
+        TcErrorCode::Config
+    );
+    assert_eq!(handle, 0);
+    take(err);
+
+    let code = CString::new("U/AB12-CD34-EF56-GH7O").unwrap();
+    let mut err = ptr::null();
+    assert_eq!(
+        join_room(code.as_ptr(), ptr::null(), &mut err),
+        TcErrorCode::InvalidRoomCode
+    );
+    take(err);
+    assert_eq!(
+        error::last_error().unwrap().code,
+        TcErrorCode::InvalidRoomCode
+    );
+
+    assert_eq!(tc_instance_free(u64::MAX), TcErrorCode::InvalidHandle);
+}
+
+#[test]
+fn last_error_is_per_thread() {
+    std::thread::spawn(|| assert!(tc_last_error().is_null()))
+        .join()
+        .unwrap();
+}
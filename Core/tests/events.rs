@@ -0,0 +1,164 @@
+use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use easytier::common::global_ctx::GlobalCtxEvent;
+use serde_json::json;
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::events::{self, Event, EventPayload, EventQueue};
+use terracotta_ios::shutdown::{StopReason, StopState};
+use terracotta_ios::*;
+use tokio::sync::broadcast;
+
+fn json_of(event: &GlobalCtxEvent) -> serde_json::Value {
+    let event = Event::new(7, EventPayload::from(event));
+    serde_json::from_str(&event.to_json()).unwrap()
+}
+
+#[test]
+fn events_are_versioned_and_flattened() {
+    let value = json_of(&GlobalCtxEvent::PeerAdded(42));
+    assert_eq!(value["version"], events::EVENTS_VERSION);
+    assert_eq!(value["instance"], 7);
+    assert!(value["timestampMs"].as_u64().unwrap() > 0);
+    assert_eq!(value["type"], "peer_added");
+    assert_eq!(value["peerId"], 42);
+
+    let url = "tcp://0.0.0.0:11010".parse().unwrap();
+    let value = json_of(&GlobalCtxEvent::ListenerAddFailed(
+        url,
+        "address in use".into(),
+    ));
+    assert_eq!(value["type"], "listener_add_failed");
+    assert_eq!(value["url"], "tcp://0.0.0.0:11010");
+    assert_eq!(value["error"], "address in use");
+
+    let value = json_of(&GlobalCtxEvent::VpnPortalClientConnected(
+        "wg".into(),
+        "10.14.14.2".into(),
+    ));
+    assert_eq!(value["type"], "vpn_portal_client_connected");
+    assert_eq!(value["client"], "10.14.14.2");
+}
+
+#[tokio::test]
+async fn lagging_subscriber_reports_dropped_events() {
+    let (tx, rx) = broadcast::channel(2);
+    for peer_id in 1..=5 {
+        tx.send(GlobalCtxEvent::PeerAdded(peer_id)).unwrap();
+    }
+    drop(tx);
+
+    let mut received = Vec::new();
+    events::forward(1, rx, |event| received.push(event.payload)).await;
+    assert_eq!(
+        received,
+        [
+            EventPayload::EventsDropped { count: 3 },
+            EventPayload::PeerAdded { peer_id: 4 },
+            EventPayload::PeerAdded { peer_id: 5 },
+        ]
+    );
+    assert_eq!(
+        serde_json::to_value(&received[0]).unwrap(),
+        json!({"type": "events_dropped", "count": 3})
+    );
+}
+
+#[test]
+fn subscribe_requires_callback_and_instance() {
+    extern "C" fn ignore(_: *mut std::ffi::c_void, _: *const std::ffi::c_char) {}
+
+    let mut err = ptr::null();
+    assert_eq!(
+        tc_instance_subscribe_events(1, None, ptr::null_mut(), &mut err),
+        TcErrorCode::InvalidArgument
+    );
+    tc_string_free(err);
+
+    let mut err = ptr::null();
+    assert_eq!(
+        tc_instance_subscribe_events(u64::MAX, Some(ignore), ptr::null_mut(), &mut err),
+        TcErrorCode::InvalidHandle
+    );
+    tc_string_free(err);
+}
+
+fn payloads(events: Vec<Event>) -> Vec<EventPayload> {
+    events.into_iter().map(|event| event.payload).collect()
+}
+
+#[test]
+fn full_queue_drops_oldest_and_reports_count() {
+    let queue = EventQueue::new(2);
+    queue.set_instance(3);
+    for peer_id in 1..=4 {
+        queue.push(Event::new(0, EventPayload::PeerAdded { peer_id }));
+    }
+    // 接收端落后时丢失的事件同样计数
+    queue.push(Event::new(0, EventPayload::EventsDropped { count: 5 }));
+
+    let events = queue.poll(2, Duration::ZERO);
+    assert_eq!(events[0].instance, 3);
+    assert_eq!(
+        payloads(events),
+        [
+            EventPayload::EventsDropped { count: 7 },
+            EventPayload::PeerAdded { peer_id: 3 },
+        ]
+    );
+    assert_eq!(
+        payloads(queue.poll(10, Duration::ZERO)),
+        [EventPayload::PeerAdded { peer_id: 4 }]
+    );
+    assert_eq!(queue.dropped(), 7);
+}
+
+#[test]
+fn poll_waits_for_events_until_timeout() {
+    let queue = Arc::new(EventQueue::default());
+    let start = Instant::now();
+    assert!(queue.poll(8, Duration::from_millis(50)).is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let pusher = queue.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        pusher.push(Event::new(0, EventPayload::PeerRemoved { peer_id: 9 }));
+    });
+    assert_eq!(
+        payloads(queue.poll(8, Duration::from_secs(5))),
+        [EventPayload::PeerRemoved { peer_id: 9 }]
+    );
+}
+
+#[tokio::test]
+async fn feed_ends_with_stop_reason() {
+    let queue = Arc::new(EventQueue::default());
+    let (tx, rx) = broadcast::channel(8);
+    tx.send(GlobalCtxEvent::TunDeviceReady("utun4".into()))
+        .unwrap();
+    drop(tx);
+    let stop_state = StopState::new();
+    let feeding = tokio::spawn(events::feed(queue.clone(), rx, stop_state.clone()));
+    stop_state.finish(StopReason::Failed);
+    feeding.await.unwrap();
+
+    let value = serde_json::to_value(queue.poll(8, Duration::ZERO)).unwrap();
+    assert_eq!(value[0]["type"], "tun_device_ready");
+    assert_eq!(value[0]["device"], "utun4");
+    assert_eq!(value[1]["type"], "instance_stopped");
+    assert_eq!(value[1]["reason"], "failed");
+}
+
+#[test]
+fn poll_rejects_zero_max() {
+    let mut events = ptr::null();
+    let mut err = ptr::null();
+    assert_eq!(
+        tc_instance_poll_events(u64::MAX, 0, 0, &mut events, &mut err),
+        TcErrorCode::InvalidArgument
+    );
+    tc_string_free(err);
+    assert!(events.is_null());
+}
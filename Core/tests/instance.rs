@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use terracotta_ios::config::{Role, RoomConfig};
+use terracotta_ios::instance::{self, Instance, INVALID_HANDLE};
+use terracotta_ios::room_code::RoomCode;
+
+fn guest_toml() -> String {
+    let code: RoomCode = "U/AB12-CD34-EF56-GH70".parse().unwrap();
+    RoomConfig::for_room(&code, Role::Guest)
+        .build()
+        .to_toml()
+        .unwrap()
+}
+
+#[test]
+fn handles_are_unique_and_freed_once() {
+    let a = instance::insert(Instance::new(&guest_toml()).unwrap());
+    let b = instance::insert(Instance::new(&guest_toml()).unwrap());
+    assert_ne!(a, INVALID_HANDLE);
+    assert_ne!(a, b);
+
+    assert_eq!(instance::with(a, |i| i.is_running()), Ok(false));
+    // 未启动的实例也可以停止
+    assert_eq!(instance::stop(a, Duration::ZERO), Ok(None));
+    assert!(instance::running_info(a).is_err());
+
+    assert!(instance::free(a));
+    assert!(!instance::free(a));
+    assert!(instance::get(a).is_err());
+    assert!(instance::get(b).is_ok());
+    assert!(instance::free(b));
+}
+
+#[test]
+fn rejects_invalid_config_before_registering() {
+    assert!(Instance::new(r#"{"version": 99, "config": "x = 1"}"#).is_err());
+    assert!(Instance::new(r#"{"config": ""}"#).is_err());
+}
+
+#[test]
+fn unknown_handle_is_an_error() {
+    assert!(instance::start(INVALID_HANDLE).is_err());
+    assert!(instance::tunnel_settings(u64::MAX).is_err());
+}
+
+#[test]
+fn a_new_default_replaces_the_stopped_one() {
+    let start = || Ok::<_, &str>(Instance::new(&guest_toml()).unwrap());
+    let old = instance::start_default(|| "running", start).unwrap();
+    assert_eq!(instance::default_handle(), Ok(old));
+    assert!(!instance::default_is_running());
+
+    let new = instance::start_default(|| "running", start).unwrap();
+    assert!(instance::get(old).is_err(), "the stopped default is freed");
+    assert_eq!(instance::default_handle(), Ok(new));
+
+    assert_eq!(
+        instance::start_default(|| "running", || Err("failed")),
+        Err::<u64, _>("failed")
+    );
+    assert!(instance::get(new).is_err());
+    assert!(instance::default_handle().is_err());
+}
@@ -0,0 +1,129 @@
+//! EasyTier's side of the relay is the other end of a [`tun::nic_pair`].
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+
+use easytier::proto::api::instance::{NodeInfo, Route};
+use easytier::proto::common::{self, Ipv4Inet};
+use terracotta_ios::lan::{self, Announcement, Echo, LanRelay, Overlay};
+use terracotta_ios::{runtime, tun, udp};
+use tokio::sync::mpsc;
+
+fn route(addr: [u8; 4]) -> Route {
+    Route {
+        ipv4_addr: Some(Ipv4Inet {
+            address: Some(common::Ipv4Addr {
+                addr: u32::from_be_bytes(addr),
+            }),
+            network_length: 16,
+        }),
+        ..Default::default()
+    }
+}
+
+fn node(ipv4: &str) -> NodeInfo {
+    NodeInfo {
+        ipv4_addr: ipv4.to_string(),
+        ..Default::default()
+    }
+}
+
+fn world() -> Announcement {
+    Announcement {
+        motd: "Steve - New World".to_string(),
+        port: 51234,
+    }
+}
+
+#[test]
+fn parses_announcements() {
+    let data = b"[MOTD]Steve - New World[/MOTD][AD]51234[/AD]";
+    assert_eq!(Announcement::parse(data), Some(world()));
+    assert_eq!(world().to_string().as_bytes(), data);
+
+    assert_eq!(Announcement::parse(b"[MOTD]x[/MOTD]"), None);
+    assert_eq!(Announcement::parse(b"[MOTD]x[/MOTD][AD]port[/AD]"), None);
+    assert_eq!(Announcement::parse(b"[AD]25565[/AD]"), None);
+    assert_eq!(Announcement::parse(&[0xff, 0xfe]), None);
+}
+
+#[test]
+fn relays_to_every_peer() {
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let easytier = UnixDatagram::from(easytier);
+    easytier
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let overlay = Arc::new(Overlay::default());
+    overlay.attach(core).unwrap();
+    let relay = LanRelay::new(overlay.clone());
+    // 还没有地址时不转发
+    assert_eq!(runtime::block_on(relay.forward(&world())).unwrap(), 0);
+
+    let routes = [
+        route([10, 14, 0, 3]),
+        route([10, 14, 0, 1]),
+        route([10, 14, 0, 2]),
+    ];
+    overlay.update(Some(&node("10.14.0.1/16")), &routes);
+    assert_eq!(overlay.local_address(), Some(Ipv4Addr::new(10, 14, 0, 1)));
+    assert_eq!(
+        overlay.peers(),
+        [Ipv4Addr::new(10, 14, 0, 2), Ipv4Addr::new(10, 14, 0, 3)]
+    );
+    assert!(overlay.contains(Ipv4Addr::new(10, 14, 0, 1)));
+    assert!(!overlay.contains(Ipv4Addr::new(10, 14, 0, 4)));
+    assert_eq!(runtime::block_on(relay.forward(&world())).unwrap(), 2);
+
+    let mut buf = [0u8; 1500];
+    for peer in [2, 3] {
+        let n = easytier.recv(&mut buf).unwrap();
+        let datagram = udp::parse(&buf[..n]).unwrap();
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 1), lan::LAN_PORT);
+        let to = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, peer), lan::LAN_PORT);
+        assert_eq!((datagram.src, datagram.dst), (from, to));
+        assert_eq!(Announcement::parse(datagram.payload), Some(world()));
+    }
+}
+
+#[test]
+fn relay_without_packet_io_sends_nothing() {
+    let overlay = Arc::new(Overlay::default());
+    overlay.update(Some(&node("10.14.0.1/16")), &[route([10, 14, 0, 2])]);
+    let relay = LanRelay::new(overlay);
+    assert_eq!(runtime::block_on(relay.forward(&world())).unwrap(), 0);
+}
+
+#[test]
+fn echoes_peer_announcements_to_the_group() {
+    let overlay = Arc::new(Overlay::default());
+    overlay.update(Some(&node("10.14.0.2/16")), &[route([10, 14, 0, 1])]);
+    let (injector, mut system) = mpsc::channel(8);
+    let echo = Echo::new(overlay, injector);
+
+    let host = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 1), lan::LAN_PORT);
+    let guest = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 2), lan::LAN_PORT);
+    let stranger = SocketAddrV4::new(Ipv4Addr::new(10, 14, 0, 9), lan::LAN_PORT);
+    let announcement = world().to_string();
+    assert!(!echo.observe(&udp::packet(stranger, guest, announcement.as_bytes())));
+    assert!(!echo.observe(&udp::packet(host, stranger, announcement.as_bytes())));
+    assert!(!echo.observe(&udp::packet(host, guest, b"not an announcement")));
+    assert!(system.try_recv().is_err());
+
+    assert!(echo.observe(&udp::packet(host, guest, announcement.as_bytes())));
+    let packet = system.try_recv().unwrap();
+    let datagram = udp::parse(&packet).unwrap();
+    let group = SocketAddrV4::new(lan::LAN_GROUP, lan::LAN_PORT);
+    assert_eq!((datagram.src, datagram.dst), (host, group));
+    assert_eq!(Announcement::parse(datagram.payload), Some(world()));
+}
+
+#[test]
+fn recognizes_local_addresses() {
+    assert!(lan::is_local(Ipv4Addr::LOCALHOST).unwrap());
+    // TEST-NET-1 不会分配给本机的网卡
+    assert!(!lan::is_local(Ipv4Addr::new(192, 0, 2, 123)).unwrap());
+}
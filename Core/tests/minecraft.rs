@@ -0,0 +1,223 @@
+//! The probe against a fake Minecraft server on loopback.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ptr;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::minecraft::{self, Player, ProbeError};
+use terracotta_ios::runtime;
+use terracotta_ios::*;
+
+const STATUS: &str = r#"{
+    "version": {"name": "1.21.1", "protocol": 767},
+    "players": {"max": 8, "online": 1,
+                "sample": [{"name": "Steve", "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5"}]},
+    "description": {"text": "§aSteve's ", "extra": [{"text": "world", "bold": true}]},
+    "favicon": "data:image/png;base64,AAAA"
+}"#;
+
+fn read_varint(stream: &mut TcpStream) -> i32 {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).unwrap();
+        value |= u32::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    value as i32
+}
+
+fn varint(value: usize) -> Vec<u8> {
+    let mut value = value as u32;
+    let mut buf = Vec::new();
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+    buf
+}
+
+fn read_packet(stream: &mut TcpStream) -> Vec<u8> {
+    let len = read_varint(stream) as usize;
+    let mut packet = vec![0u8; len];
+    stream.read_exact(&mut packet).unwrap();
+    packet
+}
+
+fn write_packet(stream: &mut TcpStream, id: u8, body: &[u8]) {
+    let mut packet = varint(body.len() + 1);
+    packet.push(id);
+    packet.extend_from_slice(body);
+    stream.write_all(&packet).unwrap();
+}
+
+enum Pong {
+    Echo,
+    Close,
+}
+
+/// Serve one status exchange with `status` as the JSON, returns the port
+/// and the handshake the client sent.
+fn fake_server(status: &'static str, pong: Pong) -> (u16, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let handshake = read_packet(&mut stream);
+        assert_eq!(read_packet(&mut stream), [0x00], "status request");
+
+        let mut body = varint(status.len());
+        body.extend_from_slice(status.as_bytes());
+        write_packet(&mut stream, 0x00, &body);
+
+        if let Pong::Echo = pong {
+            let ping = read_packet(&mut stream);
+            assert_eq!((ping[0], ping.len()), (0x01, 9));
+            write_packet(&mut stream, 0x01, &ping[1..]);
+        }
+        handshake
+    });
+    (port, server)
+}
+
+fn probe(port: u16) -> Result<minecraft::Status, ProbeError> {
+    runtime::block_on(minecraft::probe("127.0.0.1", port, Duration::from_secs(5))).unwrap()
+}
+
+type Reply = (TcErrorCode, Option<String>, Option<String>);
+
+fn string(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+extern "C" fn on_complete(
+    user_data: *mut c_void,
+    code: TcErrorCode,
+    result: *const c_char,
+    err_msg: *const c_char,
+) {
+    let tx = unsafe { Box::from_raw(user_data as *mut mpsc::Sender<Reply>) };
+    tx.send((code, string(result), string(err_msg))).unwrap();
+}
+
+#[test]
+fn reports_status_of_a_server() {
+    let (port, server) = fake_server(STATUS, Pong::Echo);
+    let status = probe(port).unwrap();
+
+    let mut handshake = vec![0x00, 0xff, 0xff, 0xff, 0xff, 0x0f, 9];
+    handshake.extend_from_slice(b"127.0.0.1");
+    handshake.extend_from_slice(&port.to_be_bytes());
+    handshake.push(1);
+    assert_eq!(server.join().unwrap(), handshake);
+
+    assert_eq!(status.version.name, "1.21.1");
+    assert_eq!(status.version.protocol, 767);
+    assert_eq!(status.motd, "Steve's world");
+    assert_eq!((status.players.online, status.players.max), (1, 8));
+    assert_eq!(
+        status.players.sample,
+        [Player {
+            name: "Steve".to_string(),
+            id: "069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string(),
+        }]
+    );
+    assert!(status.latency_ms < 5000);
+}
+
+#[test]
+fn tolerates_servers_without_pong() {
+    let (port, server) = fake_server(r#"{"description": "A Minecraft Server"}"#, Pong::Close);
+    let status = probe(port).unwrap();
+    server.join().unwrap();
+
+    assert_eq!(status.motd, "A Minecraft Server");
+    assert_eq!(status.version.protocol, 0);
+    assert!(status.players.sample.is_empty());
+}
+
+#[test]
+fn rejects_other_responses() {
+    let (port, server) = fake_server("[]", Pong::Close);
+    assert!(matches!(probe(port), Err(ProbeError::Protocol(_))));
+    server.join().unwrap();
+
+    assert!(matches!(
+        minecraft::parse_status("<html>"),
+        Err(ProbeError::Protocol(_))
+    ));
+}
+
+#[test]
+fn gives_up_on_silent_servers() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let result = runtime::block_on(minecraft::probe(
+        "127.0.0.1",
+        port,
+        Duration::from_millis(200),
+    ))
+    .unwrap();
+    assert!(matches!(result, Err(ProbeError::Timeout)));
+}
+
+#[test]
+fn probe_ffi_returns_status_json() {
+    let (port, server) = fake_server(STATUS, Pong::Echo);
+    let addr = CString::new("127.0.0.1").unwrap();
+    let mut err = ptr::null();
+    let mut result = ptr::null();
+    let code = tc_probe_minecraft(addr.as_ptr(), port, 0, &mut err, &mut result);
+    assert_eq!(code, TcErrorCode::Ok, "{:?}", string(err));
+    server.join().unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&string(result).unwrap()).unwrap();
+    tc_string_free(result);
+    assert_eq!(json["version"]["protocol"], 767);
+    assert_eq!(json["motd"], "Steve's world");
+    assert_eq!(json["players"]["sample"][0]["name"], "Steve");
+    assert!(json["latencyMs"].is_u64());
+}
+
+#[test]
+fn probe_ffi_reports_unreachable_servers() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = CString::new("127.0.0.1").unwrap();
+
+    let (tx, rx) = mpsc::channel::<Reply>();
+    let user_data = Box::into_raw(Box::new(tx)) as *mut c_void;
+    let mut err = ptr::null();
+    let code = tc_probe_minecraft_async(
+        addr.as_ptr(),
+        port,
+        1000,
+        Some(on_complete),
+        user_data,
+        &mut err,
+    );
+    assert_eq!(code, TcErrorCode::Ok);
+    let (code, result, err_msg) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(code, TcErrorCode::Network);
+    assert!(result.is_none());
+    assert!(err_msg.unwrap().contains("failed to reach the server"));
+
+    let code = tc_probe_minecraft(ptr::null(), port, 0, &mut err, ptr::null_mut());
+    assert_eq!(code, TcErrorCode::InvalidArgument);
+    tc_string_free(err);
+}
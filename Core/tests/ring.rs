@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use terracotta_ios::ring;
+
+fn packet(seq: u32, len: usize) -> Vec<u8> {
+    let mut packet = vec![seq as u8; len];
+    packet[..4].copy_from_slice(&seq.to_be_bytes());
+    packet
+}
+
+#[test]
+fn packets_survive_wrapping() {
+    let (mut writer, mut reader) = ring::packet_ring(1024, 16, 256);
+    let mut next = 0u32;
+    for seq in 0..500u32 {
+        let len = 4 + (seq as usize * 37) % 200;
+        assert!(writer.push(&packet(seq, len)));
+        // 读端落后若干个包, 让写端不断回绕
+        if reader.len() > 3 {
+            let front = reader.front().unwrap();
+            assert_eq!(front, packet(next, front.len()));
+            reader.consume(1);
+            next += 1;
+        }
+    }
+    while let Some(front) = reader.front() {
+        assert_eq!(front, packet(next, front.len()));
+        reader.consume(1);
+        next += 1;
+    }
+    assert_eq!(next, 500);
+    let stats = writer.stats().snapshot();
+    assert_eq!((stats.packets, stats.dropped), (500, 0));
+    assert_eq!(stats.high_water, 4);
+}
+
+#[test]
+fn batches_are_contiguous() {
+    let (mut writer, mut reader) = ring::packet_ring(1024, 16, 256);
+    for seq in 0..3 {
+        assert!(writer.push(&packet(seq, 100)));
+    }
+    let (buf, lens) = reader.batch(2).unwrap();
+    assert_eq!(lens, [100, 100]);
+    assert_eq!(buf[..100], packet(0, 100));
+    assert_eq!(buf[100..], packet(1, 100));
+    reader.consume(2);
+
+    // 带偏移提交的包与前一个包不相邻, 单独成批
+    let slot = writer.try_reserve().unwrap();
+    slot[..4].copy_from_slice(b"hdr!");
+    slot[4..8].copy_from_slice(b"body");
+    writer.commit(4, 4);
+    let (buf, lens) = reader.batch(8).unwrap();
+    assert_eq!((buf.len(), lens), (100, &[100][..]));
+    reader.consume(1);
+    let (buf, lens) = reader.batch(8).unwrap();
+    assert_eq!((buf, lens), (&b"body"[..], &[4][..]));
+    reader.consume(1);
+    assert!(reader.batch(8).is_none());
+}
+
+#[test]
+fn full_ring_drops_and_counts() {
+    let (mut writer, mut reader) = ring::packet_ring(1024, 4, 256);
+    for seq in 0..4 {
+        assert!(writer.push(&packet(seq, 64)));
+    }
+    // 记录表已满
+    assert!(!writer.push(&packet(4, 64)));
+    assert!(!writer.push(&[]));
+    assert!(!writer.push(&vec![0; 257]));
+    reader.consume(4);
+    assert!(writer.push(&packet(5, 64)));
+
+    let stats = writer.stats().snapshot();
+    assert_eq!(stats.packets, 5);
+    assert_eq!(stats.dropped, 3);
+    assert_eq!(stats.full, 1);
+    assert_eq!(stats.bytes_copied, 5 * 64);
+    assert_eq!(stats.high_water, 4);
+}
+
+#[test]
+fn byte_space_is_reused_once_released() {
+    let (mut writer, mut reader) = ring::packet_ring(1024, 64, 256);
+    let mut pushed = 0;
+    while writer.push(&packet(pushed, 200)) {
+        pushed += 1;
+    }
+    // 每次预留都需要 256 字节的连续空间
+    assert_eq!(pushed, 4);
+    reader.consume(2);
+    assert!(writer.push(&packet(pushed, 200)));
+    assert_eq!(reader.front().unwrap(), packet(2, 200));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn reserve_waits_for_the_reader() {
+    let (mut writer, mut reader) = ring::packet_ring(1024, 2, 256);
+    assert!(writer.push(&packet(0, 64)));
+    assert!(writer.push(&packet(1, 64)));
+
+    let writing = tokio::spawn(async move {
+        let slot = writer.reserve().await;
+        slot[..64].copy_from_slice(&packet(2, 64));
+        writer.commit(0, 64);
+        writer
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!writing.is_finished());
+    reader.consume(1);
+
+    let writer = tokio::time::timeout(Duration::from_secs(5), writing)
+        .await
+        .unwrap()
+        .unwrap();
+    reader.readable().await;
+    assert_eq!(reader.len(), 2);
+    reader.consume(1);
+    assert_eq!(reader.front().unwrap(), packet(2, 64));
+    assert_eq!(writer.stats().snapshot().full, 1);
+}
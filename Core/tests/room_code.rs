@@ -0,0 +1,122 @@
+use terracotta_ios::room_code::{
+    diagnose, CodeStatus, RoomCode, RoomCodeError, NETWORK_NAME_PREFIX,
+};
+
+#[test]
+fn string_round_trip() {
+    for _ in 0..256 {
+        let code = RoomCode::generate();
+        let text = code.to_string();
+        assert_eq!(text.len(), "U/XXXX-XXXX-XXXX-XXXX".len());
+        assert_eq!(text.parse::<RoomCode>().unwrap(), code);
+    }
+}
+
+#[test]
+fn identity_round_trip() {
+    for _ in 0..256 {
+        let code = RoomCode::generate();
+        let name = code.network_name();
+        let secret = code.network_secret();
+        assert!(name.starts_with(NETWORK_NAME_PREFIX));
+        assert_eq!(RoomCode::from_identity(&name, &secret).unwrap(), code);
+    }
+}
+
+#[test]
+fn same_code_same_identity() {
+    let host: RoomCode = "U/AB12-CD34-EF56-GH70".parse().unwrap();
+    let guest: RoomCode = "u/ab12-cd34-ef56-gh70".parse().unwrap();
+    assert_eq!(host, guest);
+    assert_eq!(host.network_name(), "terracotta-mc-ab12-cd34");
+    assert_eq!(host.network_secret(), "ef56-gh70");
+    assert_eq!(guest.network_name(), host.network_name());
+    assert_eq!(guest.network_secret(), host.network_secret());
+}
+
+#[test]
+fn rejects_malformed_codes() {
+    assert_eq!(
+        "AB12-CD34-EF56-GH78".parse::<RoomCode>(),
+        Err(RoomCodeError::MissingPrefix)
+    );
+    assert_eq!(
+        "U/AB12-CD34-EF56".parse::<RoomCode>(),
+        Err(RoomCodeError::InvalidLength(12))
+    );
+    assert_eq!(
+        "U/AB12-CD34-EF56-GHO0".parse::<RoomCode>(),
+        Err(RoomCodeError::InvalidCharacter {
+            position: 14,
+            character: 'O'
+        })
+    );
+}
+
+#[test]
+fn detects_single_typos() {
+    let code = RoomCode::generate().to_string();
+    let chars: Vec<char> = code.chars().collect();
+    for i in 2..chars.len() {
+        if chars[i] == '-' {
+            continue;
+        }
+        for &b in terracotta_ios::room_code::ALPHABET {
+            let c = b as char;
+            if c == chars[i] {
+                continue;
+            }
+            let mut typo = chars.clone();
+            typo[i] = c;
+            let typo: String = typo.into_iter().collect();
+            assert_eq!(typo.parse::<RoomCode>(), Err(RoomCodeError::BadChecksum));
+        }
+    }
+}
+
+#[test]
+fn diagnose_statuses() {
+    let valid = diagnose("u/ab12-cd34-ef56-gh70");
+    assert_eq!(valid.status, CodeStatus::Valid);
+    assert_eq!(valid.code.as_deref(), Some("U/AB12-CD34-EF56-GH70"));
+
+    assert_eq!(diagnose("U/AB12-CD34").status, CodeStatus::Malformed);
+    assert_eq!(
+        diagnose("U/AB12-CD34-EF56-GH71").status,
+        CodeStatus::BadChecksum
+    );
+}
+
+#[test]
+fn suggests_confusable_corrections() {
+    // O 不在字符表中, 必须替换为 0
+    let letter_o = diagnose("U/AB12-CD34-EF56-GH7O");
+    assert_eq!(letter_o.status, CodeStatus::Malformed);
+    assert_eq!(
+        letter_o.suggestions.first().map(String::as_str),
+        Some("U/AB12-CD34-EF56-GH70")
+    );
+
+    // 8 与 B 都合法, 依靠校验位发现错误
+    let eight = diagnose("U/A812-CD34-EF56-GH70");
+    assert_eq!(eight.status, CodeStatus::BadChecksum);
+    assert!(eight
+        .suggestions
+        .contains(&"U/AB12-CD34-EF56-GH70".to_string()));
+}
+
+#[test]
+fn rejects_foreign_identity() {
+    assert!(matches!(
+        RoomCode::from_identity("my-network", "ef56-gh78"),
+        Err(RoomCodeError::InvalidNetworkName(_))
+    ));
+    assert!(matches!(
+        RoomCode::from_identity("terracotta-mc-ab12-cd34", "secret"),
+        Err(RoomCodeError::InvalidNetworkSecret(_))
+    ));
+    assert_eq!(
+        RoomCode::from_identity("terracotta-mc-ab12-cd34", "ef56-gh71"),
+        Err(RoomCodeError::BadChecksum)
+    );
+}
@@ -0,0 +1,119 @@
+use easytier::common::config::TomlConfigLoader;
+use terracotta_ios::config::{Role, RoomConfig};
+use terracotta_ios::room::{self, RoomOptions};
+use terracotta_ios::room_code::RoomCode;
+
+const CODE: &str = "U/AB12-CD34-EF56-GH70";
+
+fn golden(name: &str) -> String {
+    let path = format!("{}/tests/golden/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path, e))
+}
+
+fn assert_golden(toml: &str, name: &str) {
+    assert_eq!(
+        toml,
+        golden(name),
+        "output differs from tests/golden/{}",
+        name
+    );
+    if let Err(e) = TomlConfigLoader::new_from_str(toml) {
+        panic!("EasyTier rejected tests/golden/{}: {}", name, e);
+    }
+}
+
+#[test]
+fn host_config() {
+    let code: RoomCode = CODE.parse().unwrap();
+    let toml = RoomConfig::for_room(&code, Role::Host)
+        .hostname("Steve")
+        .build()
+        .to_toml()
+        .unwrap();
+    assert_golden(&toml, "host.toml");
+}
+
+#[test]
+fn guest_config() {
+    let code: RoomCode = CODE.parse().unwrap();
+    let toml = RoomConfig::for_room(&code, Role::Guest)
+        .hostname("Alex")
+        .build()
+        .to_toml()
+        .unwrap();
+    assert_golden(&toml, "guest.toml");
+}
+
+#[test]
+fn custom_config_from_json() {
+    let json = r#"{
+        "role": "host",
+        "code": "U/AB12-CD34-EF56-GH70",
+        "listenPort": 25000,
+        "peers": ["udp://relay.example.com:11010"],
+        "proxyNetworks": ["192.168.1.0/24"],
+        "rpcPortal": "127.0.0.1:15888",
+        "mtu": 1280,
+        "noTun": true,
+        "magicDns": true
+    }"#;
+    let toml = RoomConfig::from_json(json).unwrap().to_toml().unwrap();
+    assert_golden(&toml, "custom.toml");
+}
+
+#[test]
+fn rejects_invalid_room_code() {
+    assert!(RoomConfig::from_json(r#"{"code": "U/AB12-CD34-EF56-GH71"}"#).is_err());
+}
+
+#[test]
+fn rooms_enable_magic_dns_by_default() {
+    let code: RoomCode = CODE.parse().unwrap();
+    let config = room::room_config(&code, Role::Guest, &RoomOptions::default());
+    assert!(config.magic_dns);
+
+    let options = RoomOptions::from_json(r#"{"magicDns": false}"#).unwrap();
+    assert!(!room::room_config(&code, Role::Guest, &options).magic_dns);
+    assert!(
+        RoomOptions::from_json(r#"{"mtu": 1280}"#)
+            .unwrap()
+            .magic_dns
+    );
+}
+
+#[test]
+fn scaffolding_hosts_use_the_host_hostname() {
+    let code: RoomCode = CODE.parse().unwrap();
+    let options = RoomOptions::from_json(r#"{"hostname": "Steve"}"#).unwrap();
+    let host = room::room_config(&code, Role::Host, &options);
+    assert_eq!(
+        host.hostname.as_deref(),
+        Some("scaffolding-mc-server-13448")
+    );
+    let guest = room::room_config(&code, Role::Guest, &options);
+    assert_eq!(guest.hostname.as_deref(), Some("Steve"));
+
+    let options = RoomOptions::from_json(r#"{"hostname": "Steve", "scaffolding": false}"#).unwrap();
+    let host = room::room_config(&code, Role::Host, &options);
+    assert_eq!(host.hostname.as_deref(), Some("Steve"));
+}
+
+#[test]
+fn plural_setters_replace_and_singular_ones_append() {
+    let code: RoomCode = CODE.parse().unwrap();
+    let relay = "udp://relay.example.com:11010".to_string();
+    let config = RoomConfig::for_room(&code, Role::Host)
+        .peer("tcp://old.example.com:11010")
+        .peers(vec![relay.clone()])
+        .peer("tcp://extra.example.com:11010")
+        .proxy_networks(vec!["10.0.0.0/8".to_string()])
+        .proxy_networks(vec!["192.168.1.0/24".to_string()])
+        .listeners(vec!["tcp://0.0.0.0:25000".to_string()])
+        .build();
+    assert_eq!(
+        config.peers,
+        [relay, "tcp://extra.example.com:11010".to_string()]
+    );
+    assert_eq!(config.proxy_networks, ["192.168.1.0/24"]);
+    assert_eq!(config.listeners, ["tcp://0.0.0.0:25000"]);
+}
@@ -0,0 +1,70 @@
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::runtime;
+use terracotta_ios::*;
+
+type Reply = (TcErrorCode, Option<String>, Option<String>);
+
+fn string(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+extern "C" fn on_complete(
+    user_data: *mut c_void,
+    code: TcErrorCode,
+    result: *const c_char,
+    err_msg: *const c_char,
+) {
+    let tx = unsafe { Box::from_raw(user_data as *mut mpsc::Sender<Reply>) };
+    tx.send((code, string(result), string(err_msg))).unwrap();
+}
+
+fn sender() -> (*mut c_void, mpsc::Receiver<Reply>) {
+    let (tx, rx) = mpsc::channel();
+    (Box::into_raw(Box::new(tx)) as *mut c_void, rx)
+}
+
+#[test]
+fn async_query_reports_errors_through_completion() {
+    let (user_data, rx) = sender();
+    let mut err = ptr::null();
+    let code = tc_instance_get_running_info_async(u64::MAX, Some(on_complete), user_data, &mut err);
+    assert_eq!(code, TcErrorCode::Ok);
+
+    let (code, result, err_msg) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(code, TcErrorCode::InvalidHandle);
+    assert!(result.is_none());
+    assert!(err_msg.unwrap().contains("invalid instance handle"));
+}
+
+#[test]
+fn async_query_requires_callback() {
+    let mut err = ptr::null();
+    let code = get_tunnel_settings_async(None, ptr::null_mut(), &mut err);
+    assert_eq!(code, TcErrorCode::InvalidArgument);
+    tc_string_free(err);
+}
+
+#[test]
+fn block_on_works_from_runtime_threads() {
+    let (tx, rx) = mpsc::channel();
+    runtime::spawn(async move {
+        tx.send(runtime::block_on(async { 42 }).unwrap()).unwrap();
+    })
+    .unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 42);
+}
+
+#[tokio::test]
+async fn block_on_works_from_current_thread_runtime() {
+    assert_eq!(runtime::block_on(async { 7 }).unwrap(), 7);
+    assert!(instance::tunnel_settings(u64::MAX).is_err());
+}
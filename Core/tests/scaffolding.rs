@@ -0,0 +1,369 @@
+//! The client against a hand-written scaffolding host and against the
+//! core's own server on loopback.
+
+use std::ffi::{c_char, CStr, CString};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ptr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::runtime;
+use terracotta_ios::scaffolding::{
+    self, Client, Host, PlayerKind, PlayerProfile, ScaffoldingError, Server, STATUS_NOT_FOUND,
+    STATUS_OK,
+};
+use terracotta_ios::shutdown::{StopReason, StopState};
+use terracotta_ios::*;
+
+const PLAYERS: &str = r#"[
+    {"name": "Steve", "machine_id": "a1", "vendor": "Terracotta 0.3", "kind": "HOST"},
+    {"name": "Alex", "machine_id": "b2", "vendor": "Terracotta-iOS", "kind": "GUEST"}
+]"#;
+
+type Request = (String, Vec<u8>);
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut kind_len = [0u8];
+    stream.read_exact(&mut kind_len).ok()?;
+    let mut kind = vec![0u8; kind_len[0] as usize];
+    stream.read_exact(&mut kind).unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    Some((String::from_utf8(kind).unwrap(), body))
+}
+
+fn write_response(stream: &mut TcpStream, status: u8, body: &[u8]) {
+    let mut frame = vec![status];
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).unwrap();
+}
+
+/// Serve one connection as a host with its game on `server_port`, returns
+/// the requests it got.
+fn fake_host(server_port: Option<u16>) -> (SocketAddr, JoinHandle<Vec<Request>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let host = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut requests = Vec::new();
+        while let Some((kind, body)) = read_request(&mut stream) {
+            match kind.as_str() {
+                "c:ping" => write_response(&mut stream, STATUS_OK, &body),
+                "c:protocols" => write_response(
+                    &mut stream,
+                    STATUS_OK,
+                    b"c:ping\0c:protocols\0c:server_port\0c:player_ping\0c:player_profiles_list",
+                ),
+                "c:server_port" => match server_port {
+                    Some(port) => write_response(&mut stream, STATUS_OK, &port.to_be_bytes()),
+                    None => write_response(&mut stream, STATUS_NOT_FOUND, b""),
+                },
+                "c:player_ping" => write_response(&mut stream, STATUS_OK, b""),
+                "c:player_profiles_list" => {
+                    write_response(&mut stream, STATUS_OK, PLAYERS.as_bytes())
+                }
+                _ => write_response(&mut stream, 255, b"unknown request"),
+            }
+            requests.push((kind, body));
+        }
+        requests
+    });
+    (addr, host)
+}
+
+fn string(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+#[test]
+fn frames_requests_and_responses() {
+    let mut frame = Vec::new();
+    runtime::block_on(scaffolding::write_request(&mut frame, "c:ping", b"hi"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame, b"\x06c:ping\x00\x00\x00\x02hi");
+    let mut reader = &frame[..];
+    let request = runtime::block_on(scaffolding::read_request(&mut reader))
+        .unwrap()
+        .unwrap();
+    assert_eq!(request.unwrap(), ("c:ping".to_string(), b"hi".to_vec()));
+    let mut empty: &[u8] = &[];
+    assert!(runtime::block_on(scaffolding::read_request(&mut empty))
+        .unwrap()
+        .unwrap()
+        .is_none());
+
+    let mut frame = Vec::new();
+    runtime::block_on(scaffolding::write_response(&mut frame, 32, b""))
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame, [32, 0, 0, 0, 0]);
+
+    let oversized = [0xff, 0xff, 0xff, 0xff, 0xff];
+    assert!(matches!(
+        runtime::block_on(scaffolding::read_response(&mut &oversized[..])).unwrap(),
+        Err(ScaffoldingError::Protocol(_))
+    ));
+
+    let kinds = scaffolding::encode_protocols(["c:ping", "c:server_port"]);
+    assert_eq!(kinds, b"c:ping\0c:server_port");
+    assert_eq!(
+        scaffolding::parse_protocols(&kinds),
+        ["c:ping", "c:server_port"]
+    );
+}
+
+#[test]
+fn queries_the_host() {
+    let (addr, host) = fake_host(Some(51234));
+    let info = runtime::block_on(scaffolding::query(addr, Duration::from_secs(5)))
+        .unwrap()
+        .unwrap();
+    let requests = host.join().unwrap();
+
+    let kinds: Vec<_> = requests.iter().map(|(kind, _)| kind.as_str()).collect();
+    assert_eq!(
+        kinds,
+        [
+            "c:ping",
+            "c:protocols",
+            "c:server_port",
+            "c:player_profiles_list"
+        ]
+    );
+    assert_eq!(
+        scaffolding::parse_protocols(&requests[1].1),
+        scaffolding::PROTOCOL_KINDS
+    );
+    assert_eq!(info.server_port, Some(51234));
+    assert_eq!(info.protocols.len(), 5);
+    assert_eq!(info.players.len(), 2);
+    assert_eq!(info.players[0].name, "Steve");
+    assert_eq!(info.players[0].kind, Some(PlayerKind::Host));
+    assert_eq!(info.players[1].machine_id, "b2");
+}
+
+#[test]
+fn reports_missing_server_and_errors() {
+    let (addr, host) = fake_host(None);
+    runtime::block_on(async {
+        let mut client = Client::connect(addr, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(client.server_port().await.unwrap(), None);
+        assert!(matches!(
+            client.request("c:unknown", b"").await,
+            Err(ScaffoldingError::Status { status: 255, message }) if message == "unknown request"
+        ));
+
+        let profile = PlayerProfile {
+            name: "Guest".to_string(),
+            machine_id: "c3".to_string(),
+            vendor: scaffolding::VENDOR.to_string(),
+            kind: None,
+        };
+        client.player_ping(&profile).await.unwrap();
+    })
+    .unwrap();
+
+    let requests = host.join().unwrap();
+    let (kind, body) = requests.last().unwrap();
+    assert_eq!(kind, "c:player_ping");
+    let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"name": "Guest", "machine_id": "c3", "vendor": "Terracotta-iOS"})
+    );
+}
+
+#[test]
+fn times_out_on_silent_hosts() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let result = runtime::block_on(async {
+        let mut client = Client::connect(addr, Duration::from_millis(200)).await?;
+        client.ping().await
+    })
+    .unwrap();
+    assert!(matches!(result, Err(ScaffoldingError::Timeout)));
+}
+
+#[test]
+fn scaffolding_ffi() {
+    let (addr, host) = fake_host(Some(25565));
+    let ip = CString::new(addr.ip().to_string()).unwrap();
+    let mut err = ptr::null();
+    let mut result = ptr::null();
+    let code = tc_scaffolding_query(ip.as_ptr(), addr.port(), 0, &mut err, &mut result);
+    assert_eq!(code, TcErrorCode::Ok, "{:?}", string(err));
+    host.join().unwrap();
+
+    let info: serde_json::Value = serde_json::from_str(&string(result).unwrap()).unwrap();
+    tc_string_free(result);
+    assert_eq!(info["serverPort"], 25565);
+    assert_eq!(info["players"][1]["name"], "Alex");
+    assert!(info["latencyMs"].is_u64());
+
+    let (addr, host) = fake_host(None);
+    let profile = CString::new(r#"{"name": "Guest", "machine_id": "c3"}"#).unwrap();
+    let code = tc_scaffolding_player_ping(ip.as_ptr(), addr.port(), profile.as_ptr(), 0, &mut err);
+    assert_eq!(code, TcErrorCode::Ok, "{:?}", string(err));
+    let requests = host.join().unwrap();
+    let sent: PlayerProfile = serde_json::from_slice(&requests[0].1).unwrap();
+    assert_eq!(sent.vendor, scaffolding::VENDOR);
+
+    let bad = CString::new("host.terracotta").unwrap();
+    let code = tc_scaffolding_query(bad.as_ptr(), 0, 0, &mut err, ptr::null_mut());
+    assert_eq!(code, TcErrorCode::InvalidArgument);
+    tc_string_free(err);
+}
+
+fn profile(name: &str, machine_id: &str) -> PlayerProfile {
+    PlayerProfile {
+        name: name.to_string(),
+        machine_id: machine_id.to_string(),
+        vendor: scaffolding::VENDOR.to_string(),
+        kind: None,
+    }
+}
+
+/// Run the core's server for `host` on loopback until `stop_state` ends.
+fn start_server(
+    host: Arc<Host>,
+    timeout: Duration,
+    idle_timeout: Duration,
+) -> (SocketAddr, Arc<StopState>) {
+    let server = runtime::block_on(Server::bind("127.0.0.1:0".parse().unwrap(), host))
+        .unwrap()
+        .unwrap()
+        .timeouts(timeout, idle_timeout);
+    let addr = server.local_addr().unwrap();
+    let stop_state = StopState::new();
+    runtime::spawn(server.run(stop_state.clone())).unwrap();
+    (addr, stop_state)
+}
+
+#[test]
+fn serves_the_room_over_loopback() {
+    let host = Arc::new(Host::new(profile("Steve", "a1")));
+    host.set_server_port(Some(51234));
+    let (addr, stop_state) =
+        start_server(host.clone(), Duration::from_secs(5), Duration::from_secs(5));
+
+    let guest = profile("Alex", "b2");
+    runtime::block_on(scaffolding::send_heartbeat(
+        addr,
+        &guest,
+        Duration::from_secs(5),
+    ))
+    .unwrap()
+    .unwrap();
+    let info = runtime::block_on(scaffolding::query(addr, Duration::from_secs(5)))
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.server_port, Some(51234));
+    assert_eq!(info.protocols, scaffolding::PROTOCOL_KINDS);
+    let players: Vec<_> = info
+        .players
+        .iter()
+        .map(|p| (p.name.as_str(), p.kind))
+        .collect();
+    assert_eq!(
+        players,
+        [
+            ("Steve", Some(PlayerKind::Host)),
+            ("Alex", Some(PlayerKind::Guest))
+        ]
+    );
+
+    host.set_server_port(None);
+    runtime::block_on(async {
+        let mut client = Client::connect(addr, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(client.server_port().await.unwrap(), None);
+        assert!(matches!(
+            client.request("c:unknown", b"").await,
+            Err(ScaffoldingError::Status { status: 255, .. })
+        ));
+        // 与主机相同的机器 ID 和空名字都会被拒绝
+        assert!(client.player_ping(&profile("Mallory", "a1")).await.is_err());
+        assert!(client.player_ping(&profile(" ", "c3")).await.is_err());
+        assert!(matches!(
+            client.request("c:player_ping", b"not json").await,
+            Err(ScaffoldingError::Status { status: 255, .. })
+        ));
+        // 出错后连接仍可继续使用
+        assert!(client.ping().await.is_ok());
+    })
+    .unwrap();
+    assert_eq!(host.players().len(), 2);
+
+    stop_state.finish(StopReason::Clean);
+    let result = runtime::block_on(async {
+        // 监听 socket 在停止后异步关闭
+        for _ in 0..50 {
+            if Client::connect(addr, Duration::from_secs(1)).await.is_err() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    })
+    .unwrap();
+    assert!(result, "server still accepts connections after the stop");
+}
+
+#[test]
+fn closes_idle_and_stalled_connections() {
+    let host = Arc::new(Host::new(profile("Steve", "a1")));
+    let (addr, stop_state) =
+        start_server(host, Duration::from_millis(200), Duration::from_millis(300));
+
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut buf = [0u8; 1];
+    assert_eq!(idle.read(&mut buf).unwrap(), 0);
+
+    // 只发送请求的开头
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    stalled
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stalled.write_all(b"\x06c:pi").unwrap();
+    assert_eq!(stalled.read(&mut buf).unwrap(), 0);
+
+    // 空闲超时之前发送的请求会得到应答
+    let mut active = TcpStream::connect(addr).unwrap();
+    for _ in 0..3 {
+        std::thread::sleep(Duration::from_millis(100));
+        active.write_all(b"\x06c:ping\x00\x00\x00\x01x").unwrap();
+        let mut response = [0u8; 6];
+        active.read_exact(&mut response).unwrap();
+        assert_eq!(response, [STATUS_OK, 0, 0, 0, 1, b'x']);
+    }
+    stop_state.finish(StopReason::Clean);
+}
+
+#[test]
+fn lists_the_host_before_its_guests() {
+    let host = Host::new(profile("Steve", "a1"));
+    host.heartbeat(profile("Alex", "b2")).unwrap();
+    host.heartbeat(profile("Alex", "b2")).unwrap();
+    host.heartbeat(profile("Zoe", "c3")).unwrap();
+    let names: Vec<_> = host.players().into_iter().map(|p| p.name).collect();
+    assert_eq!(names, ["Steve", "Alex", "Zoe"]);
+
+    let (status, body) = host.answer("c:player_profiles_list", b"");
+    assert_eq!(status, STATUS_OK);
+    let listed: Vec<PlayerProfile> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(listed[0].kind, Some(PlayerKind::Host));
+    assert_eq!(listed[0].vendor, scaffolding::VENDOR);
+    assert_eq!(host.answer("c:server_port", b"").0, STATUS_NOT_FOUND);
+}
@@ -0,0 +1,150 @@
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::instance::{self, Instance};
+use terracotta_ios::shutdown::{self, ListenerPort, StopCallback, StopReason, StopState};
+use terracotta_ios::{tc_stop_network_instance, tc_string_free};
+use tokio::sync::Notify;
+
+const CONFIG: &str = r#"instance_name = "shutdown"
+dhcp = true
+listeners = []
+
+[network_identity]
+network_name = "terracotta-mc-ab12-cd34"
+network_secret = "ef56-gh70"
+
+[flags]
+no_tun = true
+"#;
+
+// 低两位记录原因, 其余位计数
+extern "C" fn record(user_data: *mut c_void, reason: StopReason) {
+    let calls = unsafe { &*(user_data as *const AtomicUsize) };
+    calls.fetch_add(4 + reason as usize, Ordering::SeqCst);
+}
+
+fn counter() -> &'static AtomicUsize {
+    Box::leak(Box::new(AtomicUsize::new(0)))
+}
+
+fn callback(calls: &'static AtomicUsize) -> StopCallback {
+    StopCallback::new(record, calls as *const AtomicUsize as *mut c_void)
+}
+
+#[test]
+fn stop_callbacks_fire_exactly_once() {
+    let state = StopState::new();
+    let early = counter();
+    state.on_stop(callback(early));
+
+    assert_eq!(state.finish(StopReason::Forced), StopReason::Forced);
+    // 第二次结束不会覆盖原因, 也不会再次回调
+    assert_eq!(state.finish(StopReason::Clean), StopReason::Forced);
+    assert_eq!(
+        early.load(Ordering::SeqCst),
+        4 + StopReason::Forced as usize
+    );
+
+    let late = counter();
+    state.on_stop(callback(late));
+    assert_eq!(late.load(Ordering::SeqCst), 4 + StopReason::Forced as usize);
+}
+
+#[tokio::test]
+async fn unrequested_stop_is_a_failure() {
+    let stop = Arc::new(Notify::new());
+    let state = StopState::new();
+    let monitor = tokio::spawn(shutdown::monitor(stop.clone(), state.clone()));
+    tokio::task::yield_now().await;
+    stop.notify_waiters();
+    monitor.await.unwrap();
+    assert_eq!(state.wait().await, StopReason::Failed);
+}
+
+#[tokio::test]
+async fn requested_stop_is_left_to_shutdown() {
+    let stop = Arc::new(Notify::new());
+    let state = StopState::new();
+    state.request();
+    let monitor = tokio::spawn(shutdown::monitor(stop.clone(), state.clone()));
+    tokio::task::yield_now().await;
+    stop.notify_waiters();
+    tokio::task::yield_now().await;
+    assert_eq!(state.reason(), None);
+
+    state.finish(StopReason::Clean);
+    monitor.await.unwrap();
+}
+
+#[test]
+fn stop_waits_and_reports_clean_shutdown() {
+    let handle = instance::insert(Instance::new(CONFIG).unwrap());
+    instance::start(handle).unwrap();
+    let calls = counter();
+    instance::on_stop(handle, callback(calls)).unwrap();
+
+    let reason = instance::stop(handle, Duration::from_secs(5)).unwrap();
+    assert_eq!(reason, Some(StopReason::Clean));
+    assert_eq!(calls.load(Ordering::SeqCst), 4 + StopReason::Clean as usize);
+
+    // 已停止的实例不会再次回调
+    assert_eq!(instance::stop(handle, Duration::ZERO), Ok(None));
+    assert!(instance::free(handle));
+    assert_eq!(calls.load(Ordering::SeqCst), 4 + StopReason::Clean as usize);
+}
+
+#[test]
+fn stopping_a_stopped_default_instance_releases_it() {
+    let handle = instance::insert(Instance::new(CONFIG).unwrap());
+    instance::set_default_handle(handle);
+
+    let mut err = ptr::null();
+    let code = tc_stop_network_instance(100, ptr::null_mut(), &mut err);
+    assert_eq!(code, TcErrorCode::NotRunning);
+    tc_string_free(err);
+    assert!(instance::get(handle).is_err());
+    assert!(!instance::free(handle));
+}
+
+#[tokio::test]
+async fn waits_for_listener_ports_to_be_released() {
+    let urls: Vec<url::Url> = [
+        "tcp://0.0.0.0:11010",
+        "udp://0.0.0.0:11010",
+        "wss://0.0.0.0:11012",
+        "quic://[::]:11013",
+        "tcp://0.0.0.0:0",
+        "ring://whatever",
+    ]
+    .iter()
+    .map(|url| url.parse().unwrap())
+    .collect();
+    assert_eq!(
+        ListenerPort::from_urls(&urls),
+        [
+            ListenerPort::Tcp(11010),
+            ListenerPort::Udp(11010),
+            ListenerPort::Tcp(11012),
+            ListenerPort::Udp(11013),
+        ]
+    );
+
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = ListenerPort::Tcp(listener.local_addr().unwrap().port());
+    assert!(!port.is_released());
+    let soon = tokio::time::Instant::now() + Duration::from_millis(100);
+    assert!(!shutdown::wait_released(&[port], soon).await);
+
+    let closing = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(listener);
+    });
+    let later = tokio::time::Instant::now() + Duration::from_secs(5);
+    assert!(shutdown::wait_released(&[port], later).await);
+    closing.await.unwrap();
+}
@@ -0,0 +1,101 @@
+//! Every string handed out by the FFI must come back through tc_string_free.
+//! Kept in its own test binary so the live string count is not shared.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::instance::Handle;
+use terracotta_ios::strings;
+use terracotta_ios::*;
+
+const CONFIG: &str = r#"instance_name = "leak-check"
+dhcp = true
+listeners = []
+
+[network_identity]
+network_name = "terracotta-mc-ab12-cd34"
+network_secret = "ef56-gh70"
+
+[flags]
+no_tun = true
+"#;
+
+fn take(ptr: &mut *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let s = unsafe { CStr::from_ptr(*ptr) }
+        .to_string_lossy()
+        .into_owned();
+    tc_string_free(*ptr);
+    *ptr = ptr::null();
+    Some(s)
+}
+
+#[test]
+fn no_strings_leak_across_instance_lifecycle() {
+    let Some(before) = strings::live_count() else {
+        // 发布版本不跟踪分配
+        return;
+    };
+
+    let cfg = CString::new(CONFIG).unwrap();
+    let mut handle: Handle = 0;
+    let mut err = ptr::null();
+    let mut out = ptr::null();
+
+    assert_eq!(
+        tc_instance_new(cfg.as_ptr(), &mut handle, &mut err),
+        TcErrorCode::Ok,
+        "{:?}",
+        take(&mut err)
+    );
+    assert_eq!(
+        tc_instance_start(handle, &mut err),
+        TcErrorCode::Ok,
+        "{:?}",
+        take(&mut err)
+    );
+
+    tc_instance_get_running_info(handle, &mut out, &mut err);
+    take(&mut out);
+    take(&mut err);
+    tc_instance_get_tunnel_settings(handle, &mut out, &mut err);
+    take(&mut out);
+    take(&mut err);
+    tc_instance_get_latest_error_msg(handle, &mut out, &mut err);
+    take(&mut out);
+    take(&mut err);
+    tc_instance_poll_events(handle, 8, 0, &mut out, &mut err);
+    take(&mut out);
+    take(&mut err);
+
+    assert_eq!(tc_instance_stop(handle, &mut err), TcErrorCode::Ok);
+    assert_eq!(tc_instance_free(handle), TcErrorCode::Ok);
+
+    // 失败路径同样会分配 err_msg
+    assert_eq!(
+        tc_instance_start(handle, &mut err),
+        TcErrorCode::InvalidHandle
+    );
+    assert!(take(&mut err).unwrap().contains("invalid instance handle"));
+
+    let bad = CString::new("{\"config\": \"\"}").unwrap();
+    let mut report = ptr::null();
+    assert_eq!(
+        validate_config(bad.as_ptr(), &mut err, &mut report),
+        TcErrorCode::Ok
+    );
+    assert!(take(&mut report).is_some());
+
+    let mut last = tc_last_error();
+    assert!(take(&mut last).is_some());
+
+    assert_eq!(strings::live_count(), Some(before));
+}
+
+#[test]
+fn free_accepts_null() {
+    tc_string_free(ptr::null());
+}
@@ -0,0 +1,167 @@
+//! The utun fd is replaced by a datagram socketpair, which keeps packet
+//! boundaries the same way.
+
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use terracotta_ios::error::TcErrorCode;
+use terracotta_ios::instance::{self, Instance};
+use terracotta_ios::tun::{self, TunAdapter, TunStatsSnapshot, UTUN_HEADER_LEN};
+use terracotta_ios::{tc_instance_set_tun_fd, tc_string_free};
+
+const CONFIG: &str = r#"instance_name = "tun"
+listeners = []
+
+[network_identity]
+network_name = "terracotta-mc-ab12-cd34"
+network_secret = "ef56-gh70"
+
+[flags]
+no_tun = true
+"#;
+
+const IPV4_PACKET: [u8; 20] = [
+    0x45, 0, 0, 20, 0, 0, 0x40, 0, 64, 17, 0, 0, 10, 144, 144, 1, 10, 144, 144, 2,
+];
+
+fn ipv6_packet() -> Vec<u8> {
+    let mut packet = vec![0u8; 40];
+    packet[0] = 0x60;
+    packet[6] = 17;
+    packet
+}
+
+fn header(family: libc::c_int) -> [u8; UTUN_HEADER_LEN] {
+    (family as u32).to_be_bytes()
+}
+
+fn recv(socket: &UnixDatagram) -> Vec<u8> {
+    let mut buf = [0u8; 2048];
+    let n = socket.recv(&mut buf).unwrap();
+    buf[..n].to_vec()
+}
+
+/// Counters are updated after the packet is sent, poll until they settle.
+fn wait_for(adapter: &TunAdapter, done: impl Fn(&TunStatsSnapshot) -> bool) -> TunStatsSnapshot {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stats = adapter.stats();
+        if done(&stats) {
+            return stats;
+        }
+        assert!(Instant::now() < deadline, "{:?}", stats);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+struct Harness {
+    adapter: TunAdapter,
+    /// Stands in for the system side of the utun interface.
+    os: UnixDatagram,
+    /// Stands in for EasyTier's virtual NIC.
+    easytier: UnixDatagram,
+}
+
+fn harness() -> Harness {
+    let (os, utun) = UnixDatagram::pair().unwrap();
+    let (easytier, core) = tun::nic_pair().unwrap();
+    let adapter = TunAdapter::start(OwnedFd::from(utun), core, None, None).unwrap();
+    let easytier = UnixDatagram::from(easytier);
+    for socket in [&os, &easytier] {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+    Harness {
+        adapter,
+        os,
+        easytier,
+    }
+}
+
+#[test]
+fn headers_are_stripped_and_added() {
+    assert_eq!(tun::utun_header(&IPV4_PACKET), Some(header(libc::AF_INET)));
+    assert_eq!(
+        tun::utun_header(&ipv6_packet()),
+        Some(header(libc::AF_INET6))
+    );
+    assert_eq!(tun::utun_header(&[0x10, 0, 0]), None);
+    assert_eq!(tun::utun_header(&[]), None);
+
+    assert_eq!(
+        tun::strip_utun_header(&[0, 0, 0, 2, 0x45]),
+        Some(&[0x45][..])
+    );
+    assert_eq!(tun::strip_utun_header(&[0, 0, 0, 2]), None);
+}
+
+#[test]
+fn packets_cross_the_adapter() {
+    let h = harness();
+
+    let mut frame = header(libc::AF_INET).to_vec();
+    frame.extend_from_slice(&IPV4_PACKET);
+    h.os.send(&frame).unwrap();
+    assert_eq!(recv(&h.easytier), IPV4_PACKET);
+
+    h.easytier.send(&ipv6_packet()).unwrap();
+    let frame = recv(&h.os);
+    assert_eq!(frame[..UTUN_HEADER_LEN], header(libc::AF_INET6));
+    assert_eq!(frame[UTUN_HEADER_LEN..], ipv6_packet());
+
+    h.easytier.send(&IPV4_PACKET).unwrap();
+    assert_eq!(recv(&h.os)[..UTUN_HEADER_LEN], header(libc::AF_INET));
+
+    let stats = wait_for(&h.adapter, |s| s.outbound_packets == 2);
+    assert_eq!(stats.inbound_packets, 1);
+    assert_eq!(stats.inbound_bytes, IPV4_PACKET.len() as u64);
+}
+
+#[test]
+fn malformed_packets_are_dropped() {
+    let h = harness();
+    h.os.send(&header(libc::AF_INET)).unwrap();
+    h.easytier.send(&[0x20, 1, 2, 3]).unwrap();
+
+    let stats = wait_for(&h.adapter, |s| s.malformed == 2);
+    assert_eq!(stats.inbound_packets, 0);
+    assert_eq!(stats.outbound_packets, 0);
+}
+
+#[test]
+fn dropping_the_adapter_closes_the_tun_fd() {
+    let h = harness();
+    drop(h.adapter);
+
+    // 适配器关闭后对端写入失败
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while h.os.send(&[0, 0, 0, 2, 0x45]).is_ok() {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn is_open(fd: RawFd) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+}
+
+#[test]
+fn keeps_the_fd_without_a_running_instance() {
+    let (system, _) = UnixDatagram::pair().unwrap();
+    let fd = system.as_raw_fd();
+    let mut err = ptr::null();
+    let code = tc_instance_set_tun_fd(u64::MAX, fd, &mut err);
+    assert_eq!(code, TcErrorCode::InvalidHandle);
+    tc_string_free(err);
+    assert!(is_open(fd));
+
+    let handle = instance::insert(Instance::new(CONFIG).unwrap());
+    let code = tc_instance_set_tun_fd(handle, fd, &mut err);
+    assert_eq!(code, TcErrorCode::NotRunning);
+    tc_string_free(err);
+    assert!(is_open(fd));
+    assert!(instance::free(handle));
+}
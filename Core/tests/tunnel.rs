@@ -0,0 +1,195 @@
+use easytier::proto::api::instance::{NodeInfo, PeerConnInfo, PeerInfo, Route};
+use easytier::proto::common::{Ipv4Addr, Ipv4Inet, TunnelInfo, Url};
+use terracotta_ios::net::{self, Ipv4Net};
+use terracotta_ios::tunnel::{compute, TunnelParams};
+
+fn route(peer_id: u32, addr: [u8; 4], prefix_len: u32, proxy_cidrs: &[&str]) -> Route {
+    Route {
+        peer_id,
+        ipv4_addr: Some(Ipv4Inet {
+            address: Some(Ipv4Addr {
+                addr: u32::from_be_bytes(addr),
+            }),
+            network_length: prefix_len,
+        }),
+        proxy_cidrs: proxy_cidrs.iter().map(|c| c.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn peer(peer_id: u32, remotes: &[&str]) -> PeerInfo {
+    PeerInfo {
+        peer_id,
+        conns: remotes
+            .iter()
+            .map(|remote| PeerConnInfo {
+                peer_id,
+                tunnel: Some(TunnelInfo {
+                    remote_addr: Some(Url {
+                        url: remote.to_string(),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+fn nets(cidrs: &[&str]) -> Vec<Ipv4Net> {
+    cidrs.iter().map(|cidr| cidr.parse().unwrap()).collect()
+}
+
+fn node(ipv4: &str, ipv6: Option<&str>) -> NodeInfo {
+    NodeInfo {
+        ipv4_addr: ipv4.to_string(),
+        ipv6: ipv6.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn reports_dhcp_address_and_routes() {
+    let node = node("10.14.23.7/16", Some("fd00::7/64"));
+    let routes = [
+        route(1, [10, 14, 0, 1], 16, &["192.168.1.0/24"]),
+        route(2, [10, 15, 0, 9], 16, &[]),
+    ];
+    let params = TunnelParams {
+        mtu: 1280,
+        dns: vec!["10.14.0.1".to_string()],
+        routes: vec!["172.16.0.0/12".to_string(), "192.168.1.0/24".to_string()],
+        magic_dns: false,
+    };
+
+    let settings = compute(Some(&node), &routes, &[], &params);
+    let ipv4 = settings.ipv4.unwrap();
+    assert_eq!(ipv4.address, "10.14.23.7");
+    assert_eq!(ipv4.prefix_len, 16);
+    assert_eq!(ipv4.subnet_mask, "255.255.0.0");
+    let ipv6 = settings.ipv6.unwrap();
+    assert_eq!((ipv6.address.as_str(), ipv6.prefix_len), ("fd00::7", 64));
+
+    let routes: Vec<String> = settings
+        .routes
+        .iter()
+        .map(|r| format!("{}/{}", r.destination, r.prefix_len))
+        .collect();
+    assert_eq!(
+        routes,
+        [
+            "10.14.0.0/16",
+            "10.15.0.9/32",
+            "172.16.0.0/12",
+            "192.168.1.0/24"
+        ]
+    );
+    assert_eq!(settings.dns, ["10.14.0.1"]);
+    assert_eq!(settings.mtu, 1280);
+}
+
+#[test]
+fn no_address_before_dhcp() {
+    let settings = compute(Some(&node("", None)), &[], &[], &TunnelParams::default());
+    assert_eq!(settings.ipv4, None);
+    assert!(settings.routes.is_empty());
+}
+
+#[test]
+fn points_dns_at_magic_dns_once_addressed() {
+    let params = TunnelParams {
+        dns: vec!["223.5.5.5".to_string()],
+        magic_dns: true,
+        ..TunnelParams::default()
+    };
+    let settings = compute(Some(&node("10.14.23.7/16", None)), &[], &[], &params);
+    assert_eq!(settings.dns, ["10.14.255.254"]);
+
+    // 地址分配之前仍使用配置的 DNS
+    let settings = compute(Some(&node("", None)), &[], &[], &params);
+    assert_eq!(settings.dns, ["223.5.5.5"]);
+}
+
+#[test]
+fn aggregates_routes_into_prefixes() {
+    let routes = [
+        route(1, [10, 14, 0, 1], 16, &["192.168.0.0/24", "192.168.1.0/24"]),
+        route(2, [10, 14, 0, 2], 16, &["192.168.2.0/23", "192.168.3.7/32"]),
+        route(3, [10, 15, 0, 9], 16, &["10.15.0.8/32"]),
+    ];
+    let settings = compute(
+        Some(&node("10.14.23.7/16", None)),
+        &routes,
+        &[],
+        &TunnelParams::default(),
+    );
+    let routes: Vec<String> = settings
+        .routes
+        .iter()
+        .map(|r| format!("{}/{}", r.destination, r.prefix_len))
+        .collect();
+    assert_eq!(routes, ["10.14.0.0/16", "10.15.0.8/31", "192.168.0.0/22"]);
+
+    assert_eq!(
+        net::aggregate(nets(&["10.0.0.0/25", "10.0.0.128/25", "10.0.1.0/24"])),
+        nets(&["10.0.0.0/23"])
+    );
+    // 不相邻的同级网段不能合并
+    assert_eq!(
+        net::aggregate(nets(&["10.0.1.0/24", "10.0.2.0/24"])),
+        nets(&["10.0.1.0/24", "10.0.2.0/24"])
+    );
+    assert_eq!(
+        net::aggregate(nets(&["0.0.0.0/1", "128.0.0.0/1", "10.0.0.0/8"])),
+        nets(&["0.0.0.0/0"])
+    );
+    assert!(net::aggregate([]).is_empty());
+}
+
+#[test]
+fn excludes_peer_endpoints() {
+    let peers = [
+        peer(
+            1,
+            &[
+                "tcp://203.0.113.5:11010",
+                "udp://203.0.113.5:11010",
+                "wg://[2001:db8::1]:11011",
+            ],
+        ),
+        peer(
+            2,
+            &[
+                "ws://relay.example.com:80",
+                "tcp://127.0.0.1:11010",
+                "udp://198.51.100.7:40000",
+                "tcp://10.14.0.1:11010",
+            ],
+        ),
+    ];
+    let settings = compute(
+        Some(&node("10.14.23.7/16", None)),
+        &[],
+        &peers,
+        &TunnelParams::default(),
+    );
+    let excluded: Vec<String> = settings
+        .excluded_routes
+        .iter()
+        .map(|r| format!("{}/{}", r.destination, r.subnet_mask))
+        .collect();
+    assert_eq!(
+        excluded,
+        [
+            "198.51.100.7/255.255.255.255",
+            "203.0.113.5/255.255.255.255"
+        ]
+    );
+    assert_eq!(settings.excluded_ipv6_routes.len(), 1);
+    assert_eq!(settings.excluded_ipv6_routes[0].destination, "2001:db8::1");
+    assert_eq!(settings.excluded_ipv6_routes[0].prefix_len, 128);
+
+    let json = serde_json::to_value(&settings).unwrap();
+    assert!(json["excludedRoutes"].is_array());
+    assert!(json["excludedIpv6Routes"].is_array());
+}
@@ -0,0 +1,86 @@
+use terracotta_ios::validate::{validate, Diagnostic, Severity};
+
+const VALID: &str = r#"instance_name = "terracotta"
+ipv4 = "10.14.0.1/16"
+listeners = ["tcp://0.0.0.0:11010", "udp://0.0.0.0:11010"]
+
+[network_identity]
+network_name = "terracotta-mc-ab12-cd34"
+network_secret = "ef56-gh70"
+
+[[proxy_network]]
+cidr = "192.168.1.0/24"
+
+[flags]
+mtu = 1380
+"#;
+
+fn find<'a>(diagnostics: &'a [Diagnostic], path: &str) -> &'a Diagnostic {
+    diagnostics
+        .iter()
+        .find(|d| d.path.as_deref() == Some(path))
+        .unwrap_or_else(|| panic!("no diagnostic for {}: {:?}", path, diagnostics))
+}
+
+#[test]
+fn accepts_valid_config() {
+    let report = validate(VALID);
+    assert!(report.valid, "{:?}", report.diagnostics);
+    assert!(report.diagnostics.is_empty());
+}
+
+#[test]
+fn reports_bad_listener_with_position() {
+    let cfg = VALID.replace("udp://0.0.0.0:11010", "udp//0.0.0.0");
+    let report = validate(&cfg);
+    assert!(!report.valid);
+    let d = find(&report.diagnostics, "listeners[1]");
+    assert_eq!(d.severity, Severity::Error);
+    assert_eq!(d.line, Some(3));
+    assert_eq!(d.column, Some(37));
+
+    let cfg = VALID.replace("tcp://0.0.0.0:11010", "http://0.0.0.0:80");
+    assert!(find(&validate(&cfg).diagnostics, "listeners[0]")
+        .message
+        .contains("scheme"));
+}
+
+#[test]
+fn reports_overlapping_subnets() {
+    let cfg = VALID.replace("192.168.1.0/24", "10.14.128.0/17");
+    let report = validate(&cfg);
+    assert!(!report.valid);
+    let d = find(&report.diagnostics, "proxy_network[0].cidr");
+    assert_eq!(d.line, Some(10));
+    assert!(d.message.contains("ipv4"));
+}
+
+#[test]
+fn reports_missing_secret() {
+    let cfg = VALID.replace("network_secret = \"ef56-gh70\"\n", "");
+    let report = validate(&cfg);
+    assert!(!report.valid);
+    let d = find(&report.diagnostics, "network_identity.network_secret");
+    assert_eq!(d.severity, Severity::Error);
+}
+
+#[test]
+fn reports_mtu_out_of_range() {
+    let cfg = VALID.replace("mtu = 1380", "mtu = 9000");
+    let report = validate(&cfg);
+    assert_eq!(find(&report.diagnostics, "flags.mtu").line, Some(13));
+
+    // 外层 JSON 的 mtu 覆盖 TOML 中的值
+    let json = serde_json::json!({ "config": VALID, "mtu": 200 }).to_string();
+    let report = validate(&json);
+    let d = find(&report.diagnostics, "mtu");
+    assert_eq!(d.line, None);
+    assert!(!report.valid);
+}
+
+#[test]
+fn reports_syntax_errors() {
+    let report = validate("instance_name = \"unterminated\n");
+    assert!(!report.valid);
+    assert_eq!(report.diagnostics[0].line, Some(1));
+}
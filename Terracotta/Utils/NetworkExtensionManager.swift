@@ -10,6 +10,8 @@ class NetworkExtensionManager: ObservableObject {
     
     private var vpnManager: NETunnelProviderManager?
     private var isMonitoring = false
+    // 等待中的启动, 隧道连接成功或启动失败后调用一次
+    private var startCompletion: ((Error?) -> Void)?
     
     private let logger = Logger(subsystem: "site.yinmo.terracotta", category: "NetworkExtensionManager")
     
@@ -17,7 +19,7 @@ class NetworkExtensionManager: ObservableObject {
         loadVPNManager()
     }
     
-    func startVPN(options: TerracottaOptions? = nil) {
+    func startVPN(options: TerracottaOptions? = nil, extraStartOptions: [String: NSObject] = [:], completion: ((Error?) -> Void)? = nil) {
         // 保存配置到共享UserDefaults
         if let options = options, let defaults = UserDefaults(suiteName: APP_GROUP_ID) {
             do {
@@ -34,15 +36,32 @@ class NetworkExtensionManager: ObservableObject {
                 DispatchQueue.main.async {
                     self?.status = .error
                     self?.errorMessage = "Failed to load VPN manager"
+                    completion?(NSError(domain: "NetworkExtensionManager", code: 1, userInfo: [NSLocalizedDescriptionKey: "Failed to load VPN manager"]))
                 }
                 return
             }
             
+            // 隧道已在运行时不会再次启动, 也就等不到结果
+            let current = vpnManager.connection.status
+            if current != .disconnected && current != .invalid {
+                completion?(NSError(domain: "NetworkExtensionManager", code: 2, userInfo: [NSLocalizedDescriptionKey: "The VPN is already running"]))
+                return
+            }
+            
+            // 扩展启动失败时写回错误, 先清掉上一次的
+            if let defaults = UserDefaults(suiteName: APP_GROUP_ID) {
+                defaults.removeObject(forKey: "TunnelStartError")
+                defaults.synchronize()
+            }
+            
             do {
                 // 传递配置信息到VPN扩展
-                var startOptions: [String: NSObject] = [:]
-                startOptions["action"] = "start" as NSString
+                var startOptions: [String: NSObject] = extraStartOptions
+                if startOptions["action"] == nil {
+                    startOptions["action"] = "start" as NSString
+                }
                 
+                self.startCompletion = completion
                 try vpnManager.connection.startVPNTunnel(options: startOptions)
                 DispatchQueue.main.async {
                     self.status = .connecting
@@ -50,9 +69,11 @@ class NetworkExtensionManager: ObservableObject {
                 }
                 self.logger.info("VPN tunnel started successfully")
             } catch {
+                self.startCompletion = nil
                 DispatchQueue.main.async {
                     self.status = .error
                     self.errorMessage = "Failed to start VPN: \(error.localizedDescription)"
+                    completion?(error)
                 }
                 self.logger.error("Failed to start VPN: \(error.localizedDescription)")
             }
@@ -166,7 +187,34 @@ class NetworkExtensionManager: ObservableObject {
             }
             
             self.updateStatus(from: vpnConnection.status)
+            self.finishStart(vpnConnection.status)
+        }
+    }
+    
+    // 只看启动后的状态变化, 连接成功或断开时结束等待中的启动
+    private func finishStart(_ vpnStatus: NEVPNStatus) {
+        guard let completion = startCompletion else {
+            return
+        }
+        switch vpnStatus {
+        case .connected:
+            startCompletion = nil
+            completion(nil)
+        case .disconnected, .invalid:
+            startCompletion = nil
+            completion(readStartError() ?? NSError(domain: "NetworkExtensionManager", code: 3, userInfo: [NSLocalizedDescriptionKey: "The VPN stopped before it connected"]))
+        default:
+            break
+        }
+    }
+    
+    // 网络扩展写回的启动错误, 带有核心的错误码
+    private func readStartError() -> TunnelStartError? {
+        guard let defaults = UserDefaults(suiteName: APP_GROUP_ID),
+              let data = defaults.data(forKey: "TunnelStartError") else {
+            return nil
         }
+        return try? JSONDecoder().decode(TunnelStartError.self, from: data)
     }
     
     private func updateStatus(from vpnStatus: NEVPNStatus) {
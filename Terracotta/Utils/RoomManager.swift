@@ -11,12 +11,10 @@ class RoomManager: ObservableObject {
     @Published var rooms: [RoomInfo] = []
     
     private let networkManager: NetworkExtensionManager
-    private let ffiWrapper: FFIWrapper
     private let logger = Logger(subsystem: "site.yinmo.terracotta", category: "RoomManager")
     
     init(networkManager: NetworkExtensionManager) {
         self.networkManager = networkManager
-        self.ffiWrapper = FFIWrapper(networkManager: networkManager)
     }
     
     func createRoom(name: String, completion: @escaping (Result<String, Error>) -> Void) {
@@ -24,38 +22,39 @@ class RoomManager: ObservableObject {
         isCreatingRoom = true
         errorMessage = nil
         
-        // 创建房间，生成与其他端兼容的配置
-        let roomCode = generateRoomCode(for: name)
-        let config = NetworkConfigManager.generateCompatibleConfig(for: RoomInfo(code: roomCode, name: name), isHost: true)
-        
-        // 保存配置以便网络扩展使用
+        // 房间代码与房主网络由网络扩展中的Rust核心生成并启动
         if let defaults = UserDefaults(suiteName: APP_GROUP_ID) {
-            let options = TerracottaOptions(
-                config: config,
-                ipv4: "10.14.0.1/16",  // 提供默认的IPv4配置
-                ipv6: "fd42:4242:4242::1/64",  // 提供默认的IPv6配置
-                mtu: 1380,
-                routes: [],
-                logLevel: LogLevel.info,
-                magicDNS: true,
-                dns: ["1.1.1.1", "8.8.8.8"]
-            )
-            
-            do {
-                let data = try JSONEncoder().encode(options)
-                defaults.set(data, forKey: "VPNConfig")
-                defaults.synchronize()
-            } catch {
-                logger.error("Failed to save VPN config: \(error.localizedDescription)")
-            }
+            defaults.removeObject(forKey: "RoomDescriptor")
+            defaults.synchronize()
         }
-        
-        // 在主线程上完成操作
-        DispatchQueue.main.async {
+        networkManager.startVPN(extraStartOptions: [
+            "action": "create" as NSString,
+            "roomName": name as NSString
+        ]) { error in
+            // 隧道连接成功后房间信息已由网络扩展写回
             defer {
                 self.isCreatingRoom = false
             }
             
+            if let error = error {
+                self.errorMessage = error.localizedDescription
+                self.logger.error("Failed to create room: \(error.localizedDescription)")
+                completion(.failure(error))
+                return
+            }
+            
+            guard let defaults = UserDefaults(suiteName: APP_GROUP_ID),
+                  let descriptor = defaults.string(forKey: "RoomDescriptor"),
+                  let data = descriptor.data(using: .utf8),
+                  let json = try? JSONSerialization.jsonObject(with: data) as? [String: Any],
+                  let roomCode = json["code"] as? String else {
+                let errorStr = "Failed to read the room descriptor"
+                self.errorMessage = errorStr
+                self.logger.error("\(errorStr)")
+                completion(.failure(NSError(domain: "RoomManagerError", code: 1, userInfo: [NSLocalizedDescriptionKey: errorStr])))
+                return
+            }
+            
             // 创建房间信息
             let roomInfo = RoomInfo(code: roomCode, name: name)
             self.currentRoom = roomInfo
@@ -71,77 +70,27 @@ class RoomManager: ObservableObject {
         isJoiningRoom = true
         errorMessage = nil
         
-        // 直接处理房间加入逻辑，不需要ffi调用
-        // 解析房间代码获取网络配置信息
-        if let roomInfo = parseRoomCode(code) {
-            // 使用兼容的配置生成器
-            let config = NetworkConfigManager.generateCompatibleConfig(for: roomInfo, isHost: false)
-            
-            let options = TerracottaOptions(
-                config: config,
-                ipv4: "10.14.0.2/16",  // 提供默认的IPv4配置
-                ipv6: "fd42:4242:4242::2/64",  // 提供默认的IPv6配置
-                mtu: 1380,
-                routes: [],
-                logLevel: LogLevel.info,
-                magicDNS: true,
-                dns: ["1.1.1.1", "8.8.8.8"]
-            )
-            
-            // 保存配置
-            if let defaults = UserDefaults(suiteName: APP_GROUP_ID) {
-                do {
-                    let data = try JSONEncoder().encode(options)
-                    defaults.set(data, forKey: "VPNConfig")
-                    defaults.synchronize()
-                } catch {
-                    logger.error("Failed to save VPN config: \(error.localizedDescription)")
-                }
+        // 房间代码的校验, 解码与网络配置都由网络扩展中的Rust核心完成
+        networkManager.startVPN(extraStartOptions: [
+            "action": "join" as NSString,
+            "roomCode": code as NSString
+        ]) { error in
+            defer {
+                self.isJoiningRoom = false
             }
             
-            // 在主线程上完成操作
-            DispatchQueue.main.async {
-                defer {
-                    self.isJoiningRoom = false
-                }
-                
-                self.currentRoom = roomInfo
-                self.networkManager.startVPN(options: options)
-                completion(.success(()))
+            // 房间代码无效时错误中带有核心给出的更正建议
+            if let error = error {
+                self.errorMessage = error.localizedDescription
+                self.logger.error("Failed to join room: \(error.localizedDescription)")
+                completion(.failure(error))
+                return
             }
-        } else {
-            let errorStr = "Invalid room code format"
-            self.errorMessage = errorStr
-            self.logger.error("Failed to parse room code: \(errorStr)")
             
-            DispatchQueue.main.async {
-                self.isJoiningRoom = false
-                completion(.failure(NSError(domain: "RoomManagerError", code: 2, userInfo: [NSLocalizedDescriptionKey: errorStr])))
-            }
-        }
-    }
-    
-    private func parseRoomCode(_ code: String) -> RoomInfo? {
-        // 验证房间代码格式，应该为 U/XXXX-XXXX-XXXX-XXXX
-        let components = code.split(separator: "/")
-        if components.count < 2 || components[0] != "U" {
-            return nil
+            self.currentRoom = RoomInfo(code: code, name: code)
+            self.logger.info("Successfully joined room: \(code)")
+            completion(.success(()))
         }
-        
-        // 提取各个部分
-        let parts = String(components[1]).split(separator: "-")
-        if parts.count != 4 {
-            return nil
-        }
-        
-        // 从第一部分获取房间名称
-        let roomName = "Room-\(String(parts[0]))"
-        return RoomInfo(code: code, name: roomName)
-    }
-    
-    private func generateRoomCode(for name: String) -> String {
-        // 使用兼容的房间代码生成器
-        return CompatibilityChecker.generateConsistentRoomCode()
     }
     
     func leaveRoom() {
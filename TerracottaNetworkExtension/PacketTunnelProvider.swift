@@ -1,11 +1,49 @@
 import NetworkExtension
 import os
+import TerracottaShared
 
 // 定义日志记录器
 private let logger = Logger(subsystem: "site.yinmo.terracotta", category: "PacketTunnelProvider")
 
+// 异步FFI调用的完成回调, 以 user_data 的形式传给Rust并在回调中释放
+private final class RustCompletion {
+    let handler: (TcErrorCode, String?, String?) -> Void
+    
+    init(_ handler: @escaping (TcErrorCode, String?, String?) -> Void) {
+        self.handler = handler
+    }
+    
+    // result 和 err_msg 只在回调期间有效, 由Rust释放
+    static let callback: tc_completion_t = { userData, code, result, errMsg in
+        guard let userData = userData else { return }
+        let completion = Unmanaged<RustCompletion>.fromOpaque(userData).takeRetainedValue()
+        completion.handler(code, result.map { String(cString: $0) }, errMsg.map { String(cString: $0) })
+    }
+}
+
+// 核心按批次交回的数据包, user_data 为 packetFlow, buf 和 lens 只在回调期间有效
+private let outboundPacketCallback: tc_outbound_packet_callback_t = { userData, buf, lens, count in
+    guard let userData = userData, let buf = buf, let lens = lens else { return }
+    let flow = Unmanaged<NEPacketTunnelFlow>.fromOpaque(userData).takeUnretainedValue()
+    var packets: [Data] = []
+    var protocols: [NSNumber] = []
+    packets.reserveCapacity(count)
+    protocols.reserveCapacity(count)
+    var offset = 0
+    for i in 0..<count {
+        let packet = Data(bytes: buf + offset, count: lens[i])
+        offset += lens[i]
+        let family = (packet.first ?? 0) >> 4 == 6 ? AF_INET6 : AF_INET
+        packets.append(packet)
+        protocols.append(NSNumber(value: family))
+    }
+    flow.writePackets(packets, withProtocols: protocols)
+}
+
 class PacketTunnelProvider: NEPacketTunnelProvider {
     private var isRunning = false
+    private var packetBridgeRunning = false
+    private let eventPollQueue = DispatchQueue(label: "site.yinmo.terracotta.events")
     private var lastAppliedSettings: TunnelNetworkSettingsSnapshot?
     private var needReapplySettings: Bool = false
     private var reasserting = false
@@ -20,6 +58,56 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
             rustInitialized = true
         }
         
+        // 创建房间: 由Rust核心生成房间代码并启动房主实例
+        if let action = options?["action"] as? String, action == "create" {
+            let roomName = (options?["roomName"] as? String) ?? ""
+            var resultPtr: UnsafePointer<CChar>? = nil
+            var errPtr: UnsafePointer<CChar>? = nil
+            let status = roomName.withCString { namePtr in
+                return create_room(namePtr, nil, &errPtr, &resultPtr)
+            }
+            
+            guard status == TC_OK, let descriptor = extractRustString(resultPtr) else {
+                let error = reportStartError(status, errPtr)
+                logger.error("startTunnel() failed to create room: \(error.localizedDescription, privacy: .public)")
+                completionHandler(error)
+                return
+            }
+            
+            // 将房间信息写回共享UserDefaults供主应用读取
+            if let defaults = UserDefaults(suiteName: APP_GROUP_ID) {
+                defaults.set(descriptor, forKey: "RoomDescriptor")
+                defaults.synchronize()
+            }
+            
+            startTunnelSettings(completionHandler: completionHandler)
+            return
+        }
+        
+        // 加入房间: 由Rust核心根据房间代码生成配置并启动实例
+        if let action = options?["action"] as? String, action == "join" {
+            guard let roomCode = options?["roomCode"] as? String else {
+                logger.error("startTunnel() room code is nil")
+                completionHandler(NSError(domain: "TerracottaError", code: 1001, userInfo: [NSLocalizedDescriptionKey: "Room code is not set"]))
+                return
+            }
+            
+            var errPtr: UnsafePointer<CChar>? = nil
+            let status = roomCode.withCString { codePtr in
+                return join_room(codePtr, nil, &errPtr)
+            }
+            
+            guard status == TC_OK else {
+                let error = reportStartError(status, errPtr, roomCode: roomCode)
+                logger.error("startTunnel() failed to join room: \(error.localizedDescription, privacy: .public)")
+                completionHandler(error)
+                return
+            }
+            
+            startTunnelSettings(completionHandler: completionHandler)
+            return
+        }
+        
         // 从共享UserDefaults加载配置
         guard let defaults = UserDefaults(suiteName: APP_GROUP_ID),
               let configData = defaults.data(forKey: "VPNConfig") else {
@@ -35,25 +123,107 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
             return
         }
         
+        // 启动前检查配置, 避免隧道建立后才发现错误
+        if let problem = validateConfig(configString) {
+            logger.error("startTunnel() invalid config: \(problem, privacy: .public)")
+            completionHandler(NSError(domain: "TerracottaError", code: 1005, userInfo: [NSLocalizedDescriptionKey: problem]))
+            return
+        }
+        
         // 启动网络实例
         var errPtr: UnsafePointer<CChar>? = nil
         let status = configString.withCString { strPtr in
             return run_network_instance(strPtr, &errPtr)
         }
         
-        guard status == 0 else {
-            let err = extractRustString(errPtr)
-            logger.error("startTunnel() failed to run: \(err ?? "Unknown", privacy: .public)")
-            completionHandler(NSError(domain: "TerracottaError", code: 1003, userInfo: [NSLocalizedDescriptionKey: err ?? "Unknown error"]))
+        guard status == TC_OK else {
+            let error = reportStartError(status, errPtr)
+            logger.error("startTunnel() failed to run: \(error.localizedDescription, privacy: .public)")
+            completionHandler(error)
             return
         }
         
-        // 注册回调
-        registerRustStopCallback()
-        registerRunningInfoCallback()
+        startTunnelSettings(completionHandler: completionHandler)
+    }
+    
+    // 把启动失败的核心错误写回共享UserDefaults, 主应用在隧道断开后读取
+    private func reportStartError(_ status: TcErrorCode, _ errPtr: UnsafePointer<CChar>?, roomCode: String? = nil) -> NSError {
+        let message = extractRustString(errPtr) ?? "Unknown error"
+        // tc_last_error 与 err_msg 来自同一次调用, 另带错误名和原因链
+        var error = TunnelStartError(code: Int(status.rawValue), name: "", message: message)
+        if let json = extractRustString(tc_last_error()),
+           let data = json.data(using: .utf8),
+           let last = try? JSONDecoder().decode(TunnelStartError.self, from: data) {
+            error = last
+        }
+        if status == TC_ERR_INVALID_ROOM_CODE, let roomCode = roomCode {
+            error.suggestions = roomCodeSuggestions(roomCode)
+        }
+        if let defaults = UserDefaults(suiteName: APP_GROUP_ID),
+           let data = try? JSONEncoder().encode(error) {
+            defaults.set(data, forKey: "TunnelStartError")
+            defaults.synchronize()
+        }
+        return NSError(domain: "TerracottaError", code: error.code, userInfo: [NSLocalizedDescriptionKey: error.message])
+    }
+    
+    // 核心给出的与输入只差易混字符的有效房间代码
+    private func roomCodeSuggestions(_ roomCode: String) -> [String] {
+        var resultPtr: UnsafePointer<CChar>? = nil
+        var errPtr: UnsafePointer<CChar>? = nil
+        let ret = roomCode.withCString { codePtr in
+            return diagnose_room_code(codePtr, &errPtr, &resultPtr)
+        }
+        guard ret == TC_OK,
+              let json = extractRustString(resultPtr),
+              let data = json.data(using: .utf8),
+              let diagnosis = try? JSONSerialization.jsonObject(with: data) as? [String: Any] else {
+            tc_string_free(errPtr)
+            return []
+        }
+        return diagnosis["suggestions"] as? [String] ?? []
+    }
+    
+    private func validateConfig(_ configString: String) -> String? {
+        var errPtr: UnsafePointer<CChar>? = nil
+        var resultPtr: UnsafePointer<CChar>? = nil
+        let ret = configString.withCString { strPtr in
+            return validate_config(strPtr, &errPtr, &resultPtr)
+        }
+        guard ret == TC_OK else {
+            return extractRustString(errPtr) ?? "Unknown error"
+        }
+        guard let report = extractRustString(resultPtr),
+              let data = report.data(using: .utf8),
+              let json = try? JSONSerialization.jsonObject(with: data) as? [String: Any],
+              let diagnostics = json["diagnostics"] as? [[String: Any]] else {
+            return nil
+        }
         
-        // 从配置字符串中提取网络设置
-        let tunnelNetworkSettings = extractNetworkSettings(from: configString)
+        var errors: [String] = []
+        for diagnostic in diagnostics {
+            let message = diagnostic["message"] as? String ?? ""
+            let path = diagnostic["path"] as? String
+            if diagnostic["severity"] as? String == "warning" {
+                logger.warning("config: \(path ?? "", privacy: .public) \(message, privacy: .public)")
+                continue
+            }
+            if let line = diagnostic["line"] as? Int, let column = diagnostic["column"] as? Int {
+                errors.append("\(path ?? "config") (\(line):\(column)): \(message)")
+            } else {
+                errors.append("\(path ?? "config"): \(message)")
+            }
+        }
+        return errors.isEmpty ? nil : errors.joined(separator: "\n")
+    }
+    
+    private func startTunnelSettings(completionHandler: @escaping (Error?) -> Void) {
+        // 轮询Rust事件队列, 代替无法捕获self的C回调
+        startEventPolling()
+        
+        // 由 Rust 根据运行中的实例计算网络设置, DHCP 分配地址后会再次更新
+        let tunnelNetworkSettings = buildNetworkSettings()
+        lastAppliedSettings = snapshotSettings(tunnelNetworkSettings)
         setTunnelNetworkSettings(tunnelNetworkSettings) { [weak self] (error) in
             guard let self = self else {
                 completionHandler(error)
@@ -67,6 +237,13 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
                 return
             }
             
+            // 首次设置生效后即接入数据包桥, 不等后续的设置变化
+            if let err = self.startPacketBridge() {
+                self.stopRustInstance()
+                completionHandler(NSError(domain: "TerracottaError", code: 997, userInfo: [NSLocalizedDescriptionKey: err]))
+                return
+            }
+            
             self.isRunning = true
             logger.info("Terracotta tunnel started successfully")
             completionHandler(nil)
@@ -83,9 +260,18 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
     }
     
     private func stopRustInstance() {
-        let status = stop_network_instance()
-        if status != 0 {
-            logger.error("stopRustInstance() failed")
+        packetBridgeRunning = false
+        // 等待监听端口和对等连接关闭, 避免立即重启时端口仍被占用
+        var reason = TC_STOP_CLEAN
+        var errPtr: UnsafePointer<CChar>? = nil
+        let status = tc_stop_network_instance(3000, &reason, &errPtr)
+        if status == TC_OK {
+            logger.info("stopRustInstance() stopped, reason=\(reason.rawValue, privacy: .public)")
+        } else if status != TC_ERR_NOT_RUNNING {
+            let err = extractRustString(errPtr)
+            logger.error("stopRustInstance() failed: \(err ?? "Unknown", privacy: .public)")
+        } else {
+            tc_string_free(errPtr)
         }
     }
     
@@ -99,84 +285,27 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
             return
         }
         
-        if messageString.hasPrefix("CREATE_ROOM:") {
-            let roomName = String(messageString.dropFirst(12)) // Remove "CREATE_ROOM:" prefix
-            var resultPtr: UnsafePointer<CChar>?
-            var errPtr: UnsafePointer<CChar>?
-            
-            let roomNameCString = roomName.cString(using: .utf8)!
-            let status = create_room(roomNameCString, &errPtr, &resultPtr)
-            
-            if status == 0, let resultPtr = resultPtr {
-                let roomCode = String(cString: resultPtr)
-                free(UnsafeMutableRawPointer(mutating: resultPtr))
-                
-                logger.info("Successfully created room: \(roomCode)")
-                let response = roomCode.data(using: .utf8)
-                completionHandler?(response)
-            } else if let errPtr = errPtr {
-                let errorStr = String(cString: errPtr)
-                free(UnsafeMutableRawPointer(mutating: errPtr))
-                
-                logger.error("Failed to create room: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
-            } else {
-                let errorStr = "Unknown error occurred while creating room"
-                logger.error("Failed to create room: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
-            }
-        } else if messageString.hasPrefix("JOIN_ROOM:") {
-            let roomCode = String(messageString.dropFirst(10)) // Remove "JOIN_ROOM:" prefix
-            var errPtr: UnsafePointer<CChar>?
-            
-            let roomCodeCString = roomCode.cString(using: .utf8)!
-            let status = join_room(roomCodeCString, &errPtr)
-            
-            if status == 0 {
-                logger.info("Successfully joined room: \(roomCode)")
-                let response = "SUCCESS".data(using: .utf8)
-                completionHandler?(response)
-            } else if let errPtr = errPtr {
-                let errorStr = String(cString: errPtr)
-                free(UnsafeMutableRawPointer(mutating: errPtr))
-                
-                logger.error("Failed to join room: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
-            } else {
-                let errorStr = "Unknown error occurred while joining room"
-                logger.error("Failed to join room: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
+        if messageString == "runningInfo" {
+            // 异步获取运行信息, 不阻塞消息处理
+            let request = RustCompletion { code, info, err in
+                if code == TC_OK, let info = info {
+                    logger.info("Returning running info")
+                    completionHandler?(info.data(using: .utf8))
+                } else {
+                    let errorStr = err ?? "Unknown error occurred while getting running info"
+                    logger.error("Failed to get running info: \(errorStr)")
+                    completionHandler?("ERROR:\(errorStr)".data(using: .utf8))
+                }
             }
-        } else if messageString == "runningInfo" {
-            // 获取运行信息
-            var infoPtr: UnsafePointer<CChar>?
+            let userData = Unmanaged.passRetained(request).toOpaque()
             var errPtr: UnsafePointer<CChar>?
             
-            let status = get_running_info(&infoPtr, &errPtr)
-            
-            if status == 0, let infoPtr = infoPtr {
-                let infoStr = String(cString: infoPtr)
-                free(UnsafeMutableRawPointer(mutating: infoPtr))
-                
-                logger.info("Returning running info")
-                let response = infoStr.data(using: .utf8)
-                completionHandler?(response)
-            } else if let errPtr = errPtr {
-                let errorStr = String(cString: errPtr)
-                free(UnsafeMutableRawPointer(mutating: errPtr))
-                
-                logger.error("Failed to get running info: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
-            } else {
-                let errorStr = "Unknown error occurred while getting running info"
+            if get_running_info_async(RustCompletion.callback, userData, &errPtr) != TC_OK {
+                // 回调不会被调用, 在这里释放
+                Unmanaged<RustCompletion>.fromOpaque(userData).release()
+                let errorStr = extractRustString(errPtr) ?? "Unknown error occurred while getting running info"
                 logger.error("Failed to get running info: \(errorStr)")
-                let response = "ERROR:\(errorStr)".data(using: .utf8)
-                completionHandler?(response)
+                completionHandler?("ERROR:\(errorStr)".data(using: .utf8))
             }
         } else {
             logger.info("Received unknown message type: \(messageString)")
@@ -185,58 +314,100 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
         }
     }
     
-    private func extractNetworkSettings(from configString: String) -> NEPacketTunnelNetworkSettings {
-        // 解析配置以提取网络设置
-        // 这里使用一个简化的实现，可以进一步完善
+    private func buildNetworkSettings() -> NEPacketTunnelNetworkSettings {
         let settings = NEPacketTunnelNetworkSettings(tunnelRemoteAddress: "127.0.0.1")
         
-        // IPv4 设置
-        let ipv4Settings = NEIPv4Settings(addresses: ["10.0.0.2"], subnetMasks: ["255.255.255.0"])
-        ipv4Settings.includedRoutes = [NEIPv4Route.default()]
-        settings.ipv4Settings = ipv4Settings
+        var settingsPtr: UnsafePointer<CChar>? = nil
+        var errPtr: UnsafePointer<CChar>? = nil
+        let ret = get_tunnel_settings(&settingsPtr, &errPtr)
+        guard ret == TC_OK,
+              let json = extractRustString(settingsPtr),
+              let data = json.data(using: .utf8),
+              let tunnel = try? JSONDecoder().decode(TunnelSettings.self, from: data) else {
+            let err = extractRustString(errPtr)
+            logger.error("buildNetworkSettings() failed to get tunnel settings: \(err ?? "Unknown", privacy: .public)")
+            return settings
+        }
         
-        // IPv6 设置 (可选)
-        let ipv6Settings = NEIPv6Settings(addresses: ["fd42:4242:4242::2"], networkPrefixLength: 64)
-        ipv6Settings.includedRoutes = [NEIPv6Route.default()]
-        settings.ipv6Settings = ipv6Settings
+        // IPv4 设置, 只路由虚拟网段, 对端和代理网段
+        if let ipv4 = tunnel.ipv4 {
+            let ipv4Settings = NEIPv4Settings(addresses: [ipv4.address], subnetMasks: [ipv4.subnetMask])
+            ipv4Settings.includedRoutes = tunnel.routes.map {
+                NEIPv4Route(destinationAddress: $0.destination, subnetMask: $0.subnetMask)
+            }
+            // 对端的公网地址不走隧道, 避免承载隧道的连接被路由回隧道
+            ipv4Settings.excludedRoutes = tunnel.excludedRoutes.map {
+                NEIPv4Route(destinationAddress: $0.destination, subnetMask: $0.subnetMask)
+            }
+            settings.ipv4Settings = ipv4Settings
+        } else {
+            logger.info("buildNetworkSettings() no IPv4 address assigned yet")
+        }
         
-        // DNS 设置
-        let dnsSettings = NEDNSSettings(servers: ["1.1.1.1", "8.8.8.8"])
-        settings.dnsSettings = dnsSettings
+        // IPv6 设置 (可选)
+        if let ipv6 = tunnel.ipv6 {
+            let ipv6Settings = NEIPv6Settings(addresses: [ipv6.address], networkPrefixLengths: [NSNumber(value: ipv6.prefixLen)])
+            ipv6Settings.includedRoutes = [NEIPv6Route(destinationAddress: ipv6.address, networkPrefixLength: NSNumber(value: ipv6.prefixLen))]
+            ipv6Settings.excludedRoutes = tunnel.excludedIpv6Routes.map {
+                NEIPv6Route(destinationAddress: $0.destination, networkPrefixLength: NSNumber(value: $0.prefixLen))
+            }
+            settings.ipv6Settings = ipv6Settings
+        }
         
-        // MTU 设置
-        settings.mtu = 1380  // 使用较小的MTU避免分片问题
+        // DNS 设置, 未配置时保留系统 DNS
+        if !tunnel.dns.isEmpty {
+            settings.dnsSettings = NEDNSSettings(servers: tunnel.dns)
+        }
         
+        settings.mtu = NSNumber(value: tunnel.mtu)
         return settings
     }
     
-    private func registerRustStopCallback() {
-        // 注册Rust停止回调
-        let rustStopCallback: @convention(c) () -> Void = { [weak self] in
-            self?.handleRustStop()
-        }
-        var regErrPtr: UnsafePointer<CChar>? = nil
-        let regRet = register_stop_callback(rustStopCallback, &regErrPtr)
-        if regRet != 0 {
-            let regErr = extractRustString(regErrPtr)
-            logger.error("registerRustStopCallback() failed: \(regErr ?? "Unknown", privacy: .public)")
-        } else {
-            logger.info("registerRustStopCallback() registered")
+    private func startEventPolling() {
+        eventPollQueue.async { [weak self] in
+            while true {
+                // 每次轮询只在本轮持有self
+                guard let self = self else { return }
+                var eventsPtr: UnsafePointer<CChar>? = nil
+                var errPtr: UnsafePointer<CChar>? = nil
+                let ret = tc_poll_events(32, 1000, &eventsPtr, &errPtr)
+                guard ret == TC_OK else {
+                    // 实例已释放, 停止轮询
+                    let err = self.extractRustString(errPtr)
+                    logger.info("startEventPolling() stopped: \(err ?? "Unknown", privacy: .public)")
+                    return
+                }
+                guard let json = self.extractRustString(eventsPtr),
+                      let data = json.data(using: .utf8),
+                      let events = try? JSONSerialization.jsonObject(with: data) as? [[String: Any]] else {
+                    continue
+                }
+                for event in events {
+                    self.handleCoreEvent(event)
+                }
+            }
         }
     }
     
-    private func registerRunningInfoCallback() {
-        // 注册运行信息回调
-        let infoChangedCallback: @convention(c) () -> Void = { [weak self] in
-            self?.handleRunningInfoChanged()
-        }
-        var errPtr: UnsafePointer<CChar>? = nil
-        let ret = register_running_info_callback(infoChangedCallback, &errPtr)
-        if ret != 0 {
-            let err = extractRustString(errPtr)
-            logger.error("registerRunningInfoCallback() failed: \(err ?? "Unknown", privacy: .public)")
-        } else {
-            logger.info("registerRunningInfoCallback() registered")
+    private func handleCoreEvent(_ event: [String: Any]) {
+        let type = event["type"] as? String ?? ""
+        switch type {
+        case "dhcp_ipv4_changed", "proxy_cidrs_updated", "config_patched":
+            handleRunningInfoChanged()
+        case "peer_added", "peer_removed", "peer_conn_added", "peer_conn_removed":
+            // 对端及其公网地址变化时重新计算路由
+            handleRunningInfoChanged()
+        case "instance_stopped":
+            // 主动停止时原因为 clean 或 forced
+            let reason = event["reason"] as? String ?? ""
+            logger.info("handleCoreEvent() instance stopped: \(reason, privacy: .public)")
+            if reason == "failed" {
+                handleRustStop()
+            }
+        case "events_dropped":
+            logger.warning("handleCoreEvent() dropped \(event["count"] as? Int ?? 0) events")
+        default:
+            logger.info("handleCoreEvent() \(type, privacy: .public)")
         }
     }
     
@@ -247,7 +418,7 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
         var msgPtr: UnsafePointer<CChar>? = nil
         var errPtr: UnsafePointer<CChar>? = nil
         let ret = get_latest_error_msg(&msgPtr, &errPtr)
-        if ret == 0, let msg = extractRustString(msgPtr) {
+        if ret == TC_OK, let msg = extractRustString(msgPtr) {
             logger.error("handleRustStop(): \(msg, privacy: .public)")
             // 在主线程上取消隧道
             DispatchQueue.main.async {
@@ -295,7 +466,7 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
         needReapplySettings = false
         
         // 创建新的网络设置
-        let settings = extractNetworkSettings(from: "") // 在实际实现中，可以从当前配置获取设置
+        let settings = buildNetworkSettings()
         let newSnapshot = snapshotSettings(settings)
         let wrappedCompletion: (Error?) -> Void = { [weak self] error in
             DispatchQueue.main.async {
@@ -319,6 +490,11 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
         
         if newSnapshot == lastAppliedSettings {
             logger.info("applyNetworkSettings() new settings are exactly the same as last applied, skipping")
+            // 设置未变但数据包桥可能已随实例停止, 需要重新接入
+            if !packetBridgeRunning, let err = startPacketBridge() {
+                wrappedCompletion(NSError(domain: "TerracottaError", code: 997, userInfo: [NSLocalizedDescriptionKey: err]))
+                return
+            }
             wrappedCompletion(nil)
             return
         }
@@ -336,19 +512,10 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
                 return
             }
             
-            // 检查是否需要设置TUN FD
-            if let tunFd = self.packetFlow.value(forKeyPath: "socket.fileDescriptor") as? Int32 {
-                var errPtr: UnsafePointer<CChar>? = nil
-                let ret = set_tun_fd(tunFd, &errPtr)
-                guard ret == 0 else {
-                    let err = extractRustString(errPtr)
-                    logger.error("applyNetworkSettings() failed to set tun fd to \(tunFd): \(err, privacy: .public)")
-                    wrappedCompletion(NSError(domain: "TerracottaError", code: 997, userInfo: [NSLocalizedDescriptionKey: err ?? "Failed to set TUN fd"]))
-                    return
-                }
-                logger.info("applyNetworkSettings() successfully set TUN fd to \(tunFd)")
-            } else {
-                logger.warning("applyNetworkSettings() no available tun fd")
+            // 数据包通过 packetFlow 与核心交换, 不再依赖 KVC 获取 utun fd
+            if let err = self.startPacketBridge() {
+                wrappedCompletion(NSError(domain: "TerracottaError", code: 997, userInfo: [NSLocalizedDescriptionKey: err]))
+                return
             }
             
             logger.info("applyNetworkSettings() settings applied")
@@ -356,31 +523,124 @@ class PacketTunnelProvider: NEPacketTunnelProvider {
         }
     }
     
+    // 接入核心的数据包桥, 重复调用只会替换回调; 失败时返回错误信息
+    private func startPacketBridge() -> String? {
+        var errPtr: UnsafePointer<CChar>? = nil
+        let flow = Unmanaged.passUnretained(packetFlow).toOpaque()
+        let ret = tc_set_outbound_packet_callback(outboundPacketCallback, flow, &errPtr)
+        guard ret == TC_OK else {
+            let err = extractRustString(errPtr)
+            logger.error("startPacketBridge() failed: \(err ?? "Unknown", privacy: .public)")
+            return err ?? "Failed to start packet bridge"
+        }
+        if !packetBridgeRunning {
+            packetBridgeRunning = true
+            readInboundPackets()
+            logger.info("startPacketBridge() packet bridge started")
+        }
+        return nil
+    }
+    
+    private func readInboundPackets() {
+        packetFlow.readPackets { [weak self] packets, _ in
+            guard let self = self, self.packetBridgeRunning else { return }
+            // 整批数据包首尾相接放入一个缓冲区
+            var buf = Data(capacity: packets.reduce(0) { $0 + $1.count })
+            var lens: [Int] = []
+            lens.reserveCapacity(packets.count)
+            for packet in packets {
+                buf.append(packet)
+                lens.append(packet.count)
+            }
+            var errPtr: UnsafePointer<CChar>? = nil
+            let ret = buf.withUnsafeBytes { bytes in
+                lens.withUnsafeBufferPointer { lens in
+                    tc_push_inbound_packets(bytes.bindMemory(to: UInt8.self).baseAddress, lens.baseAddress, lens.count, &errPtr)
+                }
+            }
+            if ret != TC_OK {
+                let err = self.extractRustString(errPtr)
+                logger.error("readInboundPackets() failed to push packets: \(err ?? "Unknown", privacy: .public)")
+                if ret == TC_ERR_NOT_RUNNING {
+                    // 实例已停止, 下次应用网络设置时重新接入
+                    self.packetBridgeRunning = false
+                    return
+                }
+            }
+            self.readInboundPackets()
+        }
+    }
+    
     private func extractRustString(_ ptr: UnsafePointer<CChar>?) -> String? {
         guard let ptr = ptr else { return nil }
         let str = String(cString: ptr)
-        // 释放Rust分配的字符串
-        free(UnsafeMutableRawPointer(mutating: ptr))
+        // 释放Rust分配的字符串, 不能使用 free()
+        tc_string_free(ptr)
         return str
     }
     
     // 这个辅助结构体用于比较网络设置
     struct TunnelNetworkSettingsSnapshot: Equatable {
         let ipv4Addresses: [String]
+        let ipv4Routes: [String]
+        let ipv4ExcludedRoutes: [String]
         let ipv6Addresses: [String]
+        let ipv6ExcludedRoutes: [String]
         let dnsServers: [String]
         let mtu: Int
     }
     
+    // get_tunnel_settings 返回的 JSON
+    struct TunnelSettings: Decodable {
+        struct IPv4: Decodable {
+            let address: String
+            let prefixLen: Int
+            let subnetMask: String
+        }
+        struct IPv6: Decodable {
+            let address: String
+            let prefixLen: Int
+        }
+        struct Route: Decodable {
+            let destination: String
+            let prefixLen: Int
+            let subnetMask: String
+        }
+        struct IPv6Route: Decodable {
+            let destination: String
+            let prefixLen: Int
+        }
+        let version: Int
+        let ipv4: IPv4?
+        let ipv6: IPv6?
+        let routes: [Route]
+        let excludedRoutes: [Route]
+        let excludedIpv6Routes: [IPv6Route]
+        let dns: [String]
+        let mtu: Int
+    }
+    
     private func snapshotSettings(_ settings: NEPacketTunnelNetworkSettings) -> TunnelNetworkSettingsSnapshot {
         let ipv4Addresses = settings.ipv4Settings?.addresses ?? []
+        let ipv4Routes = (settings.ipv4Settings?.includedRoutes ?? []).map {
+            "\($0.destinationAddress)/\($0.destinationSubnetMask)"
+        }
+        let ipv4ExcludedRoutes = (settings.ipv4Settings?.excludedRoutes ?? []).map {
+            "\($0.destinationAddress)/\($0.destinationSubnetMask)"
+        }
         let ipv6Addresses = settings.ipv6Settings?.addresses ?? []
+        let ipv6ExcludedRoutes = (settings.ipv6Settings?.excludedRoutes ?? []).map {
+            "\($0.destinationAddress)/\($0.destinationNetworkPrefixLength)"
+        }
         let dnsServers = settings.dnsSettings?.servers ?? []
         let mtu = settings.mtu?.intValue ?? 1500
         
         return TunnelNetworkSettingsSnapshot(
             ipv4Addresses: ipv4Addresses,
+            ipv4Routes: ipv4Routes,
+            ipv4ExcludedRoutes: ipv4ExcludedRoutes,
             ipv6Addresses: ipv6Addresses,
+            ipv6ExcludedRoutes: ipv6ExcludedRoutes,
             dnsServers: dnsServers,
             mtu: mtu
         )
@@ -1,40 +1,284 @@
 #ifndef terracotta_ios_h
 #define terracotta_ios_h
 
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
 #ifdef __cplusplus
 extern "C" {
 #endif
 
-// Run the network instance
-int run_network_instance(const char *cfg_str, const char **err_msg);
+// String ownership
+//
+// Every `const char **` out-parameter (err_msg, result, info, msg, settings)
+// receives a NUL-terminated UTF-8 string allocated by Rust, or is left
+// untouched when there is nothing to return; initialize it to NULL.
+// The caller owns the returned string and must release it exactly once with
+// tc_string_free. Never pass it to free(), Rust uses its own allocator.
+// `const char *` input parameters are borrowed for the duration of the call.
+
+// Free a string returned through an out-parameter, NULL is ignored
+void tc_string_free(const char *s);
+
+// Error codes
+//
+// Every function below returns TC_OK on success. On failure it returns a
+// negative code, writes a readable message to err_msg and records the error
+// for tc_last_error.
+typedef enum TcErrorCode {
+    TC_OK = 0,
+    TC_ERR_INVALID_ARGUMENT = -1,
+    TC_ERR_INVALID_ROOM_CODE = -2,
+    TC_ERR_INVALID_OPTIONS = -3,
+    TC_ERR_CONFIG = -4,
+    TC_ERR_ALREADY_RUNNING = -5,
+    TC_ERR_NETWORK = -6,
+    TC_ERR_NOT_RUNNING = -7,
+    TC_ERR_INVALID_HANDLE = -8,
+    TC_ERR_IO = -9,
+    TC_ERR_INTERNAL = -10,
+} TcErrorCode;
+
+// Last error reported on the calling thread, NULL if there is none:
+// {"code": -6, "name": "network", "message", "causes": ["outermost", ..., "root cause"]}
+// Free with tc_string_free.
+const char *tc_last_error(void);
+
+// Completion of an async query. result holds the JSON result when code is TC_OK,
+// err_msg the error message otherwise. Both are only valid until the callback
+// returns, do not free them. The callback runs on a core runtime thread.
+typedef void (*tc_completion_t)(void *user_data, TcErrorCode code, const char *result, const char *err_msg);
+
+// Run the network instance.
+// cfg_str is either EasyTier TOML or a JSON-encoded TerracottaOptions envelope:
+// {"version": 1, "config", "ipv4", "ipv6", "mtu", "routes", "logLevel", "magicDNS", "dns",
+//  "lanDiscovery", "scaffolding": {"port", "serverPort", "playerName", "machineId"}}
+// where "config" holds the TOML the other fields are applied to. "magicDNS", or
+// accept_dns in the TOML flags, is described at get_tunnel_settings, "lanDiscovery" at
+// set_tun_fd and "scaffolding" at tc_scaffolding_set_server_port.
+// Fails with TC_ERR_ALREADY_RUNNING while an instance is running, stop it first.
+TcErrorCode run_network_instance(const char *cfg_str, const char **err_msg);
+
+// Why a run of an instance ended
+typedef enum TcStopReason {
+    // Stopped on request, all listeners and peer connections were closed in time
+    TC_STOP_CLEAN = 0,
+    // Stopped on request, the shutdown timeout passed first
+    TC_STOP_FORCED = 1,
+    // The instance stopped on its own, see get_latest_error_msg
+    TC_STOP_FAILED = 2,
+} TcStopReason;
+
+// Called exactly once per run with the reason it ended, on any thread
+typedef void (*tc_stop_callback_t)(void *user_data, TcStopReason reason);
 
-// Stop the network instance
-int stop_network_instance();
+// Stop the network instance and release it, waiting up to 3 seconds for it to shut down
+TcErrorCode stop_network_instance();
+
+// Stop the network instance and release it, waiting up to timeout_ms for its
+// peer connections to close and its listener ports to be free again. reason receives
+// TC_STOP_CLEAN or TC_STOP_FORCED. Fails with TC_ERR_NOT_RUNNING if nothing was
+// running, an instance that stopped on its own is released all the same.
+TcErrorCode tc_stop_network_instance(uint32_t timeout_ms, TcStopReason *reason, const char **err_msg);
 
 // Register stop callback
-int register_stop_callback(void (*callback)(), const char **err_msg);
+TcErrorCode register_stop_callback(tc_stop_callback_t callback, void *user_data, const char **err_msg);
 
 // Register running info callback
-int register_running_info_callback(void (*callback)(), const char **err_msg);
+TcErrorCode register_running_info_callback(void (*callback)(), const char **err_msg);
+
+// Network events. Every EasyTier event is delivered as versioned JSON:
+// {"version": 1, "instance", "timestampMs", "type", ...fields of the type}
+// e.g. {"type": "peer_added", "peerId"}, {"type": "connection_error", "localAddr",
+// "remoteAddr", "error"}, {"type": "listener_add_failed", "url", "error"}.
+// A subscriber that falls behind receives {"type": "events_dropped", "count"},
+// the last event of a run is {"type": "instance_stopped", "reason": "clean|forced|failed"}.
+// event is only valid during the callback, a NULL event ends the subscription.
+typedef void (*tc_event_callback_t)(void *user_data, const char *event);
+
+// Subscribe to the events of the running instance until it stops
+TcErrorCode tc_subscribe_events(tc_event_callback_t callback, void *user_data, const char **err_msg);
+
+// Pull based alternative to tc_subscribe_events. Every instance buffers up to 256
+// events, the oldest are dropped once the buffer is full. events receives a JSON
+// array of at most max events, an empty array if none arrived within timeout_ms.
+// Lost events are reported by an {"type": "events_dropped", "count"} entry, and
+// {"type": "instance_stopped", "reason": "clean|forced|failed"} is the last event of a run.
+TcErrorCode tc_poll_events(uint32_t max, uint32_t timeout_ms, const char **events, const char **err_msg);
+
+// Total number of events dropped by the event queue
+TcErrorCode tc_events_dropped(uint64_t *dropped, const char **err_msg);
+
+// Create a Terracotta room and start the host network instance.
+// result receives a JSON room descriptor:
+// {"code", "name", "networkName", "virtualIp", "prefixLen", "listeners", "ports"}
+TcErrorCode create_room(const char *room_name, const char *options, const char **err_msg, const char **result);
+
+// Join a Terracotta room and start the guest network instance.
+//
+// options of create_room and join_room is an optional JSON object:
+// {"hostname", "peers", "mtu", "listenPort", "noTun", "magicDns", "lanDiscovery",
+//  "scaffolding", "serverPort"}, magicDns, lanDiscovery and scaffolding default to true.
+// A host with scaffolding answers Terracotta clients on port 13448 and reports serverPort
+// as its game port. Its EasyTier hostname is scaffolding-mc-server-13448 so that guests
+// find it, "hostname" (default: the room name) is its player name instead.
+// Both fail with TC_ERR_INVALID_ROOM_CODE or TC_ERR_INVALID_OPTIONS on bad input,
+// TC_ERR_ALREADY_RUNNING if an instance is running and TC_ERR_NETWORK if it fails to start.
+TcErrorCode join_room(const char *room_code, const char *options, const char **err_msg);
 
-// Create a Terracotta room
-int create_room(const char *room_name, const char **err_msg, const char **result);
+// Build an EasyTier TOML config from a JSON room description:
+// {"role": "host|guest", "code", "hostname", "networkName", "networkSecret", "ipv4",
+//  "listenPort", "listeners", "peers", "proxyNetworks", "rpcPortal", "mtu", "noTun", "magicDns"}
+TcErrorCode build_room_config(const char *room_json, const char **err_msg, const char **result);
 
-// Join a Terracotta room
-int join_room(const char *room_code, const char **err_msg);
+// Check a config without starting anything. cfg_str takes the same input as
+// run_network_instance, result receives
+// {"valid": bool, "diagnostics": [{"severity": "error|warning", "path", "line", "column", "message"}]}
+TcErrorCode validate_config(const char *cfg_str, const char **err_msg, const char **result);
+
+// Check a room code, returns JSON with status and suggested corrections
+TcErrorCode diagnose_room_code(const char *room_code, const char **err_msg, const char **result);
+
+// Probe a Minecraft Java server at addr:port with a Server List Ping. addr is an IP
+// address, e.g. a peer's virtual IP, or a hostname. port 0 means 25565 and timeout_ms 0
+// means 5 seconds. result receives
+// {"version": {"name", "protocol"}, "motd", "players": {"online", "max",
+//  "sample": [{"name", "id"}]}, "latencyMs"}
+// Fails with TC_ERR_NETWORK if the server cannot be reached, does not answer in time or
+// does not speak the protocol. The async variant reports the result to callback instead.
+TcErrorCode tc_probe_minecraft(const char *addr, uint16_t port, uint32_t timeout_ms, const char **err_msg, const char **result);
+TcErrorCode tc_probe_minecraft_async(const char *addr, uint16_t port, uint32_t timeout_ms, tc_completion_t callback, void *user_data, const char **err_msg);
+
+// Terracotta scaffolding protocol, spoken by the room host on its virtual IP, port
+// 13448 by default. addr must be an IP address, port 0 means 13448 and timeout_ms 0
+// means 5 seconds. All fail with TC_ERR_NETWORK if the host cannot be reached, does
+// not answer in time or fails the request.
+//
+// Ask the host for its Minecraft server and players. result receives
+// {"latencyMs", "protocols": [...], "serverPort": port | null,
+//  "players": [{"name", "machine_id", "vendor", "kind": "HOST|GUEST"}]}
+// serverPort is null while the host has no game open.
+TcErrorCode tc_scaffolding_query(const char *addr, uint16_t port, uint32_t timeout_ms, const char **err_msg, const char **result);
+TcErrorCode tc_scaffolding_query_async(const char *addr, uint16_t port, uint32_t timeout_ms, tc_completion_t callback, void *user_data, const char **err_msg);
+// Heartbeat keeping this player in the room, send one every 5 seconds or the host drops
+// the player after 10. profile is {"name", "machine_id", "vendor"}, vendor defaults to
+// "Terracotta-iOS".
+TcErrorCode tc_scaffolding_player_ping(const char *addr, uint16_t port, const char *profile, uint32_t timeout_ms, const char **err_msg);
+
+// Scaffolding server of a hosting instance. With "scaffolding" the instance serves it
+// on "port", 13448 by default, reporting "serverPort" as the game port and "playerName"
+// (default: the hostname) as the host of the room. Both fail with TC_ERR_NOT_RUNNING if
+// the instance does not host one.
+// Set the game port reported to guests, 0 while no world is open to them.
+TcErrorCode tc_scaffolding_set_server_port(uint16_t port, const char **err_msg);
+// Players of the room, the host first, then the guests that sent a heartbeat within
+// the last 10 seconds: [{"name", "machine_id", "vendor", "kind": "HOST|GUEST"}]
+TcErrorCode tc_scaffolding_get_players(const char **players, const char **err_msg);
 
 // Get latest error message
-int get_latest_error_msg(const char **msg, const char **err_msg);
+TcErrorCode get_latest_error_msg(const char **msg, const char **err_msg);
 
 // Get running info
-int get_running_info(const char **info, const char **err_msg);
+TcErrorCode get_running_info(const char **info, const char **err_msg);
+
+// Get the network settings of the running instance as JSON:
+// {"version": 1, "ipv4": {"address", "prefixLen", "subnetMask"} | null,
+//  "ipv6": {"address", "prefixLen"} | null,
+//  "routes": [{"destination", "prefixLen", "subnetMask"}],
+//  "excludedRoutes": [{"destination", "prefixLen", "subnetMask"}],
+//  "excludedIpv6Routes": [{"destination", "prefixLen"}], "dns": [...], "mtu"}
+// ipv4 is null until DHCP has assigned an address. routes covers only the virtual network,
+// aggregated into the fewest prefixes. The excluded routes are the public endpoints of the
+// connected peers. Both change with the peers, fetch the settings again on peer events.
+// With magic DNS, dns holds only the core's resolver once ipv4 is known. It answers on
+// the last host address of the virtual subnet: <hostname>.terracotta and host.terracotta
+// (or host.<room>.terracotta) resolve to virtual addresses, where host is the peer with
+// the EasyTier hostname scaffolding-mc-server-<port>. Other names are forwarded to "dns"
+// or public resolvers. accept_dns is cleared before EasyTier starts so its own DNS stays off.
+TcErrorCode get_tunnel_settings(const char **settings, const char **err_msg);
+
+// Non-blocking variants of get_running_info and get_tunnel_settings. They only fail
+// directly for a NULL callback, every other error is reported to the callback.
+TcErrorCode get_running_info_async(tc_completion_t callback, void *user_data, const char **err_msg);
+TcErrorCode get_tunnel_settings_async(tc_completion_t callback, void *user_data, const char **err_msg);
+
+// With "lanDiscovery" the core relays Minecraft LAN worlds once a TUN fd or the packet
+// bridge is attached. Java worlds opened on this device are sent to port 4445 of every
+// peer's virtual address, and announcements from peers are multicast again to
+// 224.0.2.60:4445 on this device, so they show up under "LAN Games". Bedrock worlds of
+// the peers are found by pinging their virtual addresses on port 19132 and answered to
+// the pings of Bedrock games on this device. The relay leaves port 19132 to a world
+// hosted on this device and then only pings the peers.
+//
+// Set TUN file descriptor. The core takes ownership of fd and closes it when the
+// instance stops, pass a dup() of a descriptor that is still used elsewhere.
+// Without a running instance the call fails with fd left open and owned by the
+// caller, once the instance is running fd is taken even if attaching it fails.
+// Packets are moved between the utun fd and EasyTier on the core runtime.
+TcErrorCode set_tun_fd(int fd, const char **err_msg);
+
+// Packet bridge, an alternative to set_tun_fd for driving packet I/O through
+// NEPacketTunnelFlow. Packets are bare IP packets without the utun header. A batch
+// is one buffer holding count packets back to back plus an array of their lengths.
+// Receives EasyTier's packets on a core thread, buf and lens are only valid during the call
+typedef void (*tc_outbound_packet_callback_t)(void *user_data, const uint8_t *buf, const size_t *lens, size_t count);
+// Attach the packet bridge and send EasyTier's packets to callback, replaces a TUN fd
+// set earlier. Calling it again only replaces the callback
+TcErrorCode tc_set_outbound_packet_callback(tc_outbound_packet_callback_t callback, void *user_data, const char **err_msg);
+// Pass packets read from the system to EasyTier. Packets that are not IP packets are
+// skipped, packets EasyTier cannot take in time are dropped. TC_ERR_NOT_RUNNING until an
+// outbound packet callback is set
+TcErrorCode tc_push_inbound_packets(const uint8_t *buf, const size_t *lens, size_t count, const char **err_msg);
 
-// Set TUN file descriptor
-int set_tun_fd(int fd, const char **err_msg);
+// Packet counters of the TUN fd or packet bridge as JSON, TC_ERR_NOT_RUNNING if neither is attached:
+// {"inboundPackets", "inboundBytes", "outboundPackets", "outboundBytes", "malformed",
+//  "inboundRing": {"packets", "bytesCopied", "full", "dropped", "highWater"}, "outboundRing": {...}}
+// "full" counts how often the ring was full, "dropped" the packets lost to it.
+TcErrorCode get_tun_stats(const char **stats, const char **err_msg);
 
 // Initialize Rust logger
 void init_rust_logger(const char *level);
 
+// Handle based API, one handle per network instance. Handles are never
+// reused, calls with a freed or unknown handle fail with an error.
+// The functions above operate on the instance started by run_network_instance,
+// create_room or join_room.
+typedef uint64_t tc_instance_t;
+
+// Create an instance from the same input as run_network_instance without starting it
+TcErrorCode tc_instance_new(const char *cfg_str, tc_instance_t *handle, const char **err_msg);
+
+// Start the instance
+TcErrorCode tc_instance_start(tc_instance_t handle, const char **err_msg);
+
+// Stop the instance, the handle stays valid and can be started again
+TcErrorCode tc_instance_stop(tc_instance_t handle, const char **err_msg);
+
+// Stop the instance, waiting up to timeout_ms, see tc_stop_network_instance
+TcErrorCode tc_instance_shutdown(tc_instance_t handle, uint32_t timeout_ms, TcStopReason *reason, const char **err_msg);
+
+// Stop the instance if running and release the handle, TC_ERR_INVALID_HANDLE for an unknown handle
+TcErrorCode tc_instance_free(tc_instance_t handle);
+
+TcErrorCode tc_instance_is_running(tc_instance_t handle, bool *running, const char **err_msg);
+TcErrorCode tc_instance_register_stop_callback(tc_instance_t handle, tc_stop_callback_t callback, void *user_data, const char **err_msg);
+TcErrorCode tc_instance_register_running_info_callback(tc_instance_t handle, void (*callback)(), const char **err_msg);
+TcErrorCode tc_instance_subscribe_events(tc_instance_t handle, tc_event_callback_t callback, void *user_data, const char **err_msg);
+TcErrorCode tc_instance_poll_events(tc_instance_t handle, uint32_t max, uint32_t timeout_ms, const char **events, const char **err_msg);
+TcErrorCode tc_instance_events_dropped(tc_instance_t handle, uint64_t *dropped, const char **err_msg);
+TcErrorCode tc_instance_get_latest_error_msg(tc_instance_t handle, const char **msg, const char **err_msg);
+TcErrorCode tc_instance_get_running_info(tc_instance_t handle, const char **info, const char **err_msg);
+TcErrorCode tc_instance_get_tunnel_settings(tc_instance_t handle, const char **settings, const char **err_msg);
+TcErrorCode tc_instance_get_running_info_async(tc_instance_t handle, tc_completion_t callback, void *user_data, const char **err_msg);
+TcErrorCode tc_instance_get_tunnel_settings_async(tc_instance_t handle, tc_completion_t callback, void *user_data, const char **err_msg);
+TcErrorCode tc_instance_set_tun_fd(tc_instance_t handle, int fd, const char **err_msg);
+TcErrorCode tc_instance_set_outbound_packet_callback(tc_instance_t handle, tc_outbound_packet_callback_t callback, void *user_data, const char **err_msg);
+TcErrorCode tc_instance_push_inbound_packets(tc_instance_t handle, const uint8_t *buf, const size_t *lens, size_t count, const char **err_msg);
+TcErrorCode tc_instance_get_tun_stats(tc_instance_t handle, const char **stats, const char **err_msg);
+TcErrorCode tc_instance_scaffolding_set_server_port(tc_instance_t handle, uint16_t port, const char **err_msg);
+TcErrorCode tc_instance_scaffolding_get_players(tc_instance_t handle, const char **players, const char **err_msg);
+
 #ifdef __cplusplus
 }
 #endif
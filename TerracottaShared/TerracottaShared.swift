@@ -32,6 +32,7 @@ public enum LogLevel: String, Codable, CaseIterable {
 }
 
 public struct TerracottaOptions: Codable {
+    public var version: Int = 1
     public var config: String = ""
     public var ipv4: String?
     public var ipv6: String?
@@ -40,6 +41,17 @@ public struct TerracottaOptions: Codable {
     public var logLevel: LogLevel = .info
     public var magicDNS: Bool = false
     public var dns: [String] = []
+    public var lanDiscovery: Bool?
+    public var scaffolding: ScaffoldingOptions?
+
+    public init() {}
+}
+
+public struct ScaffoldingOptions: Codable {
+    public var port: Int?
+    public var serverPort: Int?
+    public var playerName: String?
+    public var machineId: String?
 
     public init() {}
 }
@@ -72,6 +84,29 @@ public struct PlayerInfo: Codable {
     }
 }
 
+// 网络扩展启动隧道失败时写回共享UserDefaults的错误, 字段与 tc_last_error 一致
+public struct TunnelStartError: Codable, LocalizedError {
+    public var code: Int
+    public var name: String
+    public var message: String
+    public var causes: [String]?
+    // 房间代码无效时由 diagnose_room_code 给出的更正
+    public var suggestions: [String]?
+
+    public init(code: Int, name: String, message: String) {
+        self.code = code
+        self.name = name
+        self.message = message
+    }
+
+    public var errorDescription: String? {
+        guard let suggestions = suggestions, !suggestions.isEmpty else {
+            return message
+        }
+        return "\(message)\nDid you mean \(suggestions.joined(separator: ", "))?"
+    }
+}
+
 public enum ConnectionStatus: String, Codable, CaseIterable {
     case disconnected = "disconnected"
     case connecting = "connecting"